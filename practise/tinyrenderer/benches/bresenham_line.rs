@@ -1,10 +1,10 @@
 
 use criterion::{criterion_group, criterion_main, Criterion};
-use tinyrenderer::bresenham::{line_segment_v2, line_segment_v3};
+use tinyrenderer::bresenham::{line_segment_v2, line_segment_v3, line};
 use tinyrenderer::tga::{TgaColor, TgaImage, TgaFormat};
 
 
-const RED  : TgaColor = TgaColor::from_rgb(0, 0, 255);
+const RED  : TgaColor = TgaColor::from_rgb(255, 0, 0);
 const WHITE: TgaColor = TgaColor::from_rgb(255, 255, 255);
 
 
@@ -18,6 +18,9 @@ pub fn bresenham_benchmark(c: &mut Criterion) {
     group.bench_function("v3", |b| b.iter(|| {
         line_segment_v3(&mut image, 13, 20, 80, 40, &RED);
     }));
+    group.bench_function("integer clipped", |b| b.iter(|| {
+        line(&mut image, 13, 20, 80, 40, &RED);
+    }));
 }
 
 criterion_group!(benches, bresenham_benchmark);
@@ -7,7 +7,7 @@ use tinyrenderer::Vec3f;
 
 
 const GREEN: TgaColor = TgaColor::from_rgb(0, 255, 0);
-const RED  : TgaColor = TgaColor::from_rgb(0, 0, 255);
+const RED  : TgaColor = TgaColor::from_rgb(255, 0, 0);
 const WHITE: TgaColor = TgaColor::from_rgb(255, 255, 255);
 
 
@@ -1,7 +1,7 @@
 
 use tinyrenderer::tga::{TgaImage, TgaFormat, TgaColor};
 use tinyrenderer::mesh::ObjMesh;
-use tinyrenderer::bresenham::line_segment_v3 as draw_line;
+use tinyrenderer::bresenham::line as draw_line;
 
 const OUTPUT_PATH: &'static str = "output.tga";
 const WHITE: TgaColor = TgaColor::from_rgb(255, 255, 255);
@@ -28,5 +28,6 @@ fn main() -> std::io::Result<()> {
     }
 
     image.flip_vertically();
-    image.write_tga_file(OUTPUT_PATH, true)
+    image.write_tga_file(OUTPUT_PATH, true)?;
+    Ok(())
 }
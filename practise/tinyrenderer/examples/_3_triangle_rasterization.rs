@@ -3,7 +3,7 @@ use tinyrenderer::tga::{TgaImage, TgaFormat, TgaColor};
 use tinyrenderer::Vec2i;
 
 const OUTPUT_PATH: &'static str = "output.tga";
-const RED  : TgaColor = TgaColor::from_rgb(0,0,255);
+const RED  : TgaColor = TgaColor::from_rgb(255, 0, 0);
 
 
 fn main() -> std::io::Result<()> {
@@ -17,5 +17,6 @@ fn main() -> std::io::Result<()> {
     barycentric_rasterization_v1(&mut image, pts, &RED);
 
     image.flip_vertically();
-    image.write_tga_file(OUTPUT_PATH, true)
+    image.write_tga_file(OUTPUT_PATH, true)?;
+    Ok(())
 }
@@ -63,5 +63,6 @@ fn main() -> std::io::Result<()> {
     }
 
     image.flip_vertically();
-    image.write_tga_file(OUTPUT_PATH, true)
+    image.write_tga_file(OUTPUT_PATH, true)?;
+    Ok(())
 }
@@ -3,9 +3,12 @@ use tinyrenderer::tga::{TgaImage, TgaFormat, TgaColor};
 use tinyrenderer::{Vec3f, Vec4f, Mat4f, Vec2f, Mat3f};
 use tinyrenderer::rasterization::ZbufferEx;
 use tinyrenderer::mesh::ObjMesh;
+use tinyrenderer::sampler::{Sampler, Filter, AddressMode};
 use tinyrenderer::rasterization::triangle;
 use tinyrenderer::camera::{lookat, viewport, projection, sample_barycentric_uv};
 use tinyrenderer::shader::IShader;
+use tinyrenderer::hdr::{HdrImage, ResolveOptions};
+use tinyrenderer::color::Color;
 use tinyrenderer::Mat3Ext;
 
 const OUTPUT_PATH: &'static str = "output.tga";
@@ -128,6 +131,18 @@ impl IShader for TextureShader {
         let color = self.mesh.sample_diffuse(uv) * intensity;
         Some(color)
     }
+
+    fn fragment_with_derivatives(&self, barycentric: Vec3f, dbc_dx: Vec3f, dbc_dy: Vec3f) -> Option<TgaColor> {
+
+        let intensity: f32 = Vec3f::dot(self.varying_intensity, barycentric);
+        // uv is linear in the barycentric coordinates, so are its derivatives
+        let uv     = sample_barycentric_uv(&self.varying_uv, barycentric);
+        let duv_dx = sample_barycentric_uv(&self.varying_uv, dbc_dx);
+        let duv_dy = sample_barycentric_uv(&self.varying_uv, dbc_dy);
+        // trilinear filtering, with the sampler and mip chains set up in `textures`
+        let color = self.mesh.sample_diffuse_grad(uv, duv_dx, duv_dy) * intensity;
+        Some(color)
+    }
 }
 
 // https://github.com/ssloy/tinyrenderer/wiki/Lesson-6-Shaders-for-the-software-renderer#textures
@@ -137,6 +152,9 @@ fn textures(image: &mut TgaImage, projection: Mat4f, model_view: Mat4f, viewport
     let mut mesh = ObjMesh::load_mesh("./assets/african_head/african_head.obj")?;
     let faces = mesh.faces.clone();
     mesh.load_diffuse_map("./assets/african_head/african_head_diffuse.tga")?;
+    // trilinear filtering of the tiled texture, instead of the nearest texel
+    mesh.sampler = Sampler::default();
+    mesh.generate_mipmaps();
 
     let mut shader = TextureShader { // or GroundShader
         mesh,
@@ -168,7 +186,7 @@ struct PhongShader {
     affine_transform: Mat4f,
 }
 
-impl IShader for PhongShader {
+impl IShader<Color> for PhongShader {
 
     fn vertex(&mut self, vertex_idx: usize, nthvert: usize) -> Vec4f {
         let vertex = &self.mesh.vertices[vertex_idx];
@@ -178,7 +196,7 @@ impl IShader for PhongShader {
         self.affine_transform * gl_vertex // transform it to screen coordinates
     }
 
-    fn fragment(&self, barycentric: Vec3f) -> Option<TgaColor> {
+    fn fragment(&self, barycentric: Vec3f) -> Option<Color> {
 
         let uv = sample_barycentric_uv(&self.varying_uv, barycentric);
         let n = (self.uniform_mit * Vec4f::from_point(self.mesh.sample_normal(uv))).normalized().xyz();
@@ -187,15 +205,12 @@ impl IShader for PhongShader {
 
         // specular (here 10.0 is a magic number to adjust specular index)
         let specular = f32::max(r.z, 0.0).powf(self.mesh.sample_specular(uv) / 10.0);
-        // diffuse
-        let diffuse: TgaColor = self.mesh.sample_diffuse(uv);
-        // 5.0 is ambient estimation, 0.6 to adjust specular
+        // diffuse, decoded to linear
+        let albedo = self.mesh.sample_diffuse_color(uv);
+        // 0.02 is ambient estimation, 0.6 to adjust specular, the sum is left unclamped for the tone mapping
         let diff = f32::max(0.0, Vec3f::dot(n, l));
-        let color = TgaColor::from_rgb(
-            f32::min(5.0 + diffuse[0] as f32 * (diff + 0.6 * specular), 255.0) as u8,
-            f32::min(5.0 + diffuse[1] as f32 * (diff + 0.6 * specular), 255.0) as u8,
-            f32::min(5.0 + diffuse[2] as f32 * (diff + 0.6 * specular), 255.0) as u8,
-        );
+        // the ambient term adds no alpha, so that the color keeps the alpha of the albedo
+        let color = albedo * (diff + 0.6 * specular) + Color::rgba(0.02, 0.02, 0.02, 0.0);
         Some(color)
     }
 }
@@ -209,6 +224,7 @@ fn specular_mapping(image: &mut TgaImage, projection: Mat4f, model_view: Mat4f,
     mesh.load_diffuse_map("./assets/african_head/african_head_diffuse.tga")?;
     mesh.load_normal_map("./assets/african_head/african_head_nm.tga")?;
     mesh.load_specular_map("./assets/african_head/african_head_spec.tga")?;
+    mesh.sampler = Sampler::new(Filter::Bilinear, AddressMode::Repeat);
 
     let mut shader = PhongShader {
         mesh,
@@ -218,14 +234,17 @@ fn specular_mapping(image: &mut TgaImage, projection: Mat4f, model_view: Mat4f,
         affine_transform: viewport * projection * model_view,
     };
 
+    // render in high dynamic range, so that bright speculars don't clip before the tone mapping
+    let mut framebuffer = HdrImage::new(image.width, image.height);
     for face in faces {
         let screen_coords = [
             shader.vertex(face[0], 0),
             shader.vertex(face[1], 1),
             shader.vertex(face[2], 2),
         ];
-        triangle(image, &shader, &mut z_buffer, screen_coords, 255.0);
+        triangle(&mut framebuffer, &shader, &mut z_buffer, screen_coords, 255.0);
     }
+    *image = framebuffer.resolve(&ResolveOptions::default(), TgaFormat::RGB);
     Ok(())
 }
 // --------------------------------------------------------------------------------------
@@ -297,6 +316,7 @@ fn tangent_space_normal_mapping(image: &mut TgaImage, projection: Mat4f, model_v
     mesh.load_diffuse_map("./assets/african_head/african_head_diffuse.tga")?;
     mesh.load_normal_map("./assets/african_head/african_head_nm_tangent.tga")?;
     mesh.load_specular_map("./assets/african_head/african_head_spec.tga")?;
+    mesh.sampler = Sampler::new(Filter::Bilinear, AddressMode::Repeat);
 
     let mut shader = TBNPhongShader {
         mesh,
@@ -337,5 +357,6 @@ fn main() -> std::io::Result<()> {
     tangent_space_normal_mapping(&mut image, projection, model_view, view_port, z_buffer)?;
 
     image.flip_vertically(); // place the origin in the bottom left corner of the image
-    image.write_tga_file(OUTPUT_PATH, true)
+    image.write_tga_file(OUTPUT_PATH, true)?;
+    Ok(())
 }
@@ -4,12 +4,15 @@
 
 use tinyrenderer::tga::{TgaImage, TgaFormat, TgaColor};
 use tinyrenderer::{Vec3f, Vec4f, Mat4f, Vec2f, Mat3f};
-use tinyrenderer::rasterization::{ZbufferEx, ZBuffer};
+use tinyrenderer::rasterization::{ZbufferEx, ZBuffer, NullTarget};
+use tinyrenderer::depth::{DepthOptions, DepthCurve, Colormap};
 use tinyrenderer::mesh::ObjMesh;
 use tinyrenderer::rasterization::triangle;
 use tinyrenderer::camera::{lookat, viewport, projection, sample_barycentric_uv};
 use tinyrenderer::shader::IShader;
 use tinyrenderer::Mat3Ext;
+use tinyrenderer::hdr::{HdrImage, ResolveOptions};
+use tinyrenderer::color::Color;
 
 const OUTPUT_PATH: &'static str = "output.tga";
 const WIDTH : i32 = 800;
@@ -46,7 +49,7 @@ impl IShader for DepthShader {
     }
 }
 
-fn render_shadow(shadow: &mut ShadowBuffer) -> std::io::Result<TgaImage> {
+fn render_shadow(shadow: &mut ShadowBuffer) -> std::io::Result<()> {
 
     let model_view: Mat4f = lookat(LIGHT_DIR.normalized(), CENTER, UP);
     let projection: Mat4f = projection(0.0);
@@ -54,7 +57,8 @@ fn render_shadow(shadow: &mut ShadowBuffer) -> std::io::Result<TgaImage> {
 
     let mesh = ObjMesh::load_mesh("./assets/diablo3_pose/diablo3_pose.obj")?;
     let faces = mesh.faces.clone();
-    let mut depth_image = TgaImage::new(WIDTH, HEIGHT, TgaFormat::RGB);
+    // only the shadow buffer is kept, the colors of the depth pass are discarded
+    let mut depth_target = NullTarget { width: WIDTH, height: HEIGHT };
 
     let mut shader = DepthShader {
         mesh,
@@ -68,9 +72,9 @@ fn render_shadow(shadow: &mut ShadowBuffer) -> std::io::Result<TgaImage> {
             shader.vertex(face[1], 1),
             shader.vertex(face[2], 2),
         ];
-        triangle(&mut depth_image, &shader, shadow, screen_coords, DEPTH);
+        triangle(&mut depth_target, &shader, shadow, screen_coords, DEPTH);
     }
-    Ok(depth_image)
+    Ok(())
 }
 // --------------------------------------------------------------------------------------
 
@@ -88,7 +92,7 @@ struct ShadowShader {
     affine_transform: Mat4f,
 }
 
-impl IShader for ShadowShader {
+impl IShader<Color> for ShadowShader {
 
     fn vertex(&mut self, vertex_idx: usize, nthvert: usize) -> Vec4f {
         let vertex = &self.mesh.vertices[vertex_idx];
@@ -97,7 +101,7 @@ impl IShader for ShadowShader {
         gl_vertex
     }
 
-    fn fragment(&self, barycentric: Vec3f) -> Option<TgaColor> {
+    fn fragment(&self, barycentric: Vec3f) -> Option<Color> {
 
         // corresponding point in the shadow buffer
         let sb_p: Vec3f = (self.uniform_m_shadow * Vec4f::from_point(self.varying_tri * barycentric)).homogenized().xyz();
@@ -117,17 +121,16 @@ impl IShader for ShadowShader {
         let specular = f32::max(r.z, 0.0).powf(self.mesh.sample_specular(uv) / 10.0); // 10.0 is magic number
         let diff = f32::max(0.0, Vec3f::dot(n, l));
 
-        let mut color: TgaColor = self.mesh.sample_diffuse(uv);
-        for i in 0..3 {
-            color[i] = (20.0 + color[i] as f32 * shadow * (1.2 * diff + 0.6 * specular)).min(255.0) as u8;
-        }
+        let albedo = self.mesh.sample_diffuse_color(uv);
+        // 0.007 is the linear value of the former ambient term of 20 in sRGB, clamping happens in the resolve
+        let color = albedo * (shadow * (1.2 * diff + 0.6 * specular)) + Color::rgba(0.007, 0.007, 0.007, 0.0);
         Some(color)
     }
 }
 
 fn framebuffer(shadow: ShadowBuffer) -> std::io::Result<TgaImage> {
 
-    let mut image = TgaImage::new(WIDTH, HEIGHT, TgaFormat::RGB);
+    let mut framebuffer = HdrImage::new(WIDTH, HEIGHT);
     let mut z_buffer = ZbufferEx { buffer: vec![std::f32::MIN; (WIDTH * WIDTH) as usize], width: WIDTH as usize };
 
     let model_view: vek::Mat4<f32> = lookat(EYE_POSITION, CENTER, UP);
@@ -158,23 +161,28 @@ fn framebuffer(shadow: ShadowBuffer) -> std::io::Result<TgaImage> {
             shader.vertex(face[1], 1),
             shader.vertex(face[2], 2),
         ];
-        triangle(&mut image, &shader, &mut z_buffer, screen_coords, DEPTH);
+        triangle(&mut framebuffer, &shader, &mut z_buffer, screen_coords, DEPTH);
     }
-    Ok(image)
+    Ok(framebuffer.resolve(&ResolveOptions::default(), TgaFormat::RGB))
 }
 // --------------------------------------------------------------------------------------
 
 fn main() -> std::io::Result<()> {
 
     // rendering the shadow buffer
-    let mut shadow = ShadowBuffer { buffer: vec![std::f32::MIN; (WIDTH * WIDTH) as usize], width: WIDTH as usize };
+    let mut shadow = ShadowBuffer::new(WIDTH as usize, HEIGHT as usize);
 
-    let mut depth_image = render_shadow(&mut shadow)?;
-    let mut image = framebuffer(shadow)?;
+    render_shadow(&mut shadow)?;
 
+    // the light looks at the scene with an orthographic projection, so the stored depth is already linear
+    let mut depth_image = shadow.to_image(&DepthOptions { colormap: Colormap::Viridis, ..DepthOptions::default() });
     depth_image.flip_vertically();
     depth_image.write_tga_file("depth.tga", true)?;
+    shadow.to_pfm(DepthCurve::Raw).write_pfm_file("depth.pfm")?;
+
+    let mut image = framebuffer(shadow)?;
 
     image.flip_vertically();
-    image.write_tga_file(OUTPUT_PATH, true)
+    image.write_tga_file(OUTPUT_PATH, true)?;
+    Ok(())
 }
@@ -0,0 +1,32 @@
+use tinyrenderer::tga::TgaImage;
+use tinyrenderer::terminal::{print_preview, PreviewMode, PreviewOptions};
+
+// cargo run --example _8_preview -- [path] [--ascii] [--invert] [--columns N] [--rows N]
+const DEFAULT_PATH: &'static str = "output.tga";
+
+fn main() -> std::io::Result<()> {
+
+    let mut path = String::from(DEFAULT_PATH);
+    let mut options = PreviewOptions::for_terminal();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            | "--ascii" => options.mode = PreviewMode::Ascii,
+            | "--truecolor" => options.mode = PreviewMode::TrueColor,
+            | "--invert" => options.invert = true,
+            | "--columns" | "--rows" => {
+                let value = args.next().and_then(|v| v.parse().ok())
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} expects a number", arg)))?;
+                if arg == "--columns" { options.max_columns = value } else { options.max_rows = value }
+            },
+            | _ => path = arg,
+        }
+    }
+
+    let image = TgaImage::from_path(&path)?;
+    println!("{} ({}x{})", path, image.width, image.height);
+    print_preview(&image, &options)?;
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+//!
+//! Run with `cargo +nightly fuzz run tga_decode` from the root of tinyrenderer.
+//!
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use tinyrenderer::tga::{TgaImage, TgaEncoding};
+
+fuzz_target!(|data: &[u8]| {
+
+    // any input must either decode or be rejected with an error, never panic
+    if let Ok(image) = TgaImage::from_bytes(data) {
+
+        // whatever was decoded must survive a round trip through the encoder
+        for &encoding in &[TgaEncoding::TrueColor, TgaEncoding::ColorMapped, TgaEncoding::HighColor] {
+            for &rle in &[false, true] {
+                if let Ok(bytes) = image.to_bytes(rle, encoding) {
+                    let decoded = TgaImage::from_bytes(&bytes).expect("Failed to decode an encoded image!");
+                    assert_eq!((decoded.width, decoded.height), (image.width, image.height));
+                }
+            }
+        }
+    }
+});
@@ -1,5 +1,7 @@
 
 use crate::tga::{TgaImage, TgaColor};
+use crate::image::Rect;
+use crate::rasterization::RenderTarget;
 
 
 pub fn line_segment_v1(image: &mut TgaImage, x0: i32, y0: i32, x1: i32, y1: i32, color: &TgaColor) {
@@ -87,3 +89,296 @@ pub fn line_segment_v3(image: &mut TgaImage, mut x0: i32, mut y0: i32, mut x1: i
         }
     }
 }
+
+
+// Outcodes, the sides of the clip rectangle a point is beyond.
+const INSIDE: u8 = 0;
+const LEFT  : u8 = 1;
+const RIGHT : u8 = 2;
+const BELOW : u8 = 4; // y before the first row
+const ABOVE : u8 = 8; // y after the last row
+
+fn outcode(clip: &Rect, x: i64, y: i64) -> u8 {
+    let mut code = INSIDE;
+    if x < clip.x as i64 { code |= LEFT } else if x >= clip.right() as i64 { code |= RIGHT }
+    if y < clip.y as i64 { code |= BELOW } else if y >= clip.bottom() as i64 { code |= ABOVE }
+    code
+}
+
+// The pixels of a line from (x0, y0) to (x1, y1), both included, stepped with integer Bresenham.
+// Lines are always walked along increasing major axis, so that swapping the endpoints gives the same pixels.
+#[derive(Debug, Clone)]
+pub struct LinePixels {
+    x0: i64,
+    y0: i64,
+    steep: bool,   // y is the major axis
+    minor_step: i64,
+    major: i64,    // |delta| along the major axis
+    minor: i64,    // |delta| along the minor axis
+    // state of the walk over the steps [next, last]
+    next: i64,
+    last: i64,
+    offset: i64,   // of the minor axis at `next`
+    error: i64,    // remainder of the rounded offset, in [0, 2 * major)
+}
+
+impl LinePixels {
+
+    pub fn new(x0: i32, y0: i32, x1: i32, y1: i32) -> LinePixels {
+        let (mut x0, mut y0, mut x1, mut y1) = (x0 as i64, y0 as i64, x1 as i64, y1 as i64);
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        let starts_after = if steep { y0 > y1 } else { x0 > x1 };
+        if starts_after {
+            std::mem::swap(&mut x0, &mut x1);
+            std::mem::swap(&mut y0, &mut y1);
+        }
+        let (major, minor, minor_delta) = if steep { (y1 - y0, (x1 - x0).abs(), x1 - x0) } else { (x1 - x0, (y1 - y0).abs(), y1 - y0) };
+        let mut line = LinePixels { x0, y0, steep, minor_step: minor_delta.signum(), major, minor, next: 0, last: major, offset: 0, error: 0 };
+        line.seek(0);
+        line
+    }
+
+    // Only the pixels of the line inside of `clip`, the same as those of the whole line.
+    pub fn clipped(x0: i32, y0: i32, x1: i32, y1: i32, clip: Rect) -> LinePixels {
+        let mut line = LinePixels::new(x0, y0, x1, y1);
+        match line.clip(&clip) {
+            | Some((first, last)) => {
+                line.last = last;
+                line.seek(first);
+            },
+            | None => line.next = line.last + 1,
+        }
+        line
+    }
+
+    // Minor axis offset of a step: the exact offset step * minor / major rounded half up.
+    // The products overflow i64 for lines across the whole i32 range.
+    fn minor_offset(&self, step: i64) -> i64 {
+        if self.major == 0 { 0 } else { ((2 * step as i128 * self.minor as i128 + self.major as i128) / (2 * self.major as i128)) as i64 }
+    }
+
+    fn point(&self, step: i64) -> (i64, i64) {
+        let (major, minor) = (step, self.minor_step * self.minor_offset(step));
+        if self.steep { (self.x0 + minor, self.y0 + major) } else { (self.x0 + major, self.y0 + minor) }
+    }
+
+    fn seek(&mut self, step: i64) {
+        self.next = step;
+        if self.major > 0 {
+            let numerator = 2 * step as i128 * self.minor as i128 + self.major as i128;
+            self.offset = (numerator / (2 * self.major as i128)) as i64;
+            self.error = (numerator % (2 * self.major as i128)) as i64;
+        }
+    }
+
+    // Outcode-guided bisection on the steps of the line: while an endpoint is outside, move it to the first
+    // step inside the side it is beyond. The coordinates are monotonic along the line, so that step is found
+    // by bisection instead of intersecting the edges, and the clipped endpoints stay on the pixels of the whole line.
+    fn clip(&self, clip: &Rect) -> Option<(i64, i64)> {
+
+        if clip.is_empty() {
+            return None
+        }
+
+        let (mut first, mut last) = (0, self.major);
+        loop {
+            let (x0, y0) = self.point(first);
+            let (x1, y1) = self.point(last);
+            let (code0, code1) = (outcode(clip, x0, y0), outcode(clip, x1, y1));
+            if code0 | code1 == INSIDE {
+                return Some((first, last))
+            }
+            if code0 & code1 != INSIDE {
+                return None
+            }
+
+            let moving_first = code0 != INSIDE;
+            let code = if moving_first { code0 } else { code1 };
+            // the lowest side bit of the outside endpoint
+            let side = code & code.wrapping_neg();
+            let inside = |step: i64| outcode(clip, self.point(step).0, self.point(step).1) & side == 0;
+
+            // bisect for the boundary between the steps beyond the side and those inside of it
+            let (mut outside, mut within) = if moving_first { (first, last) } else { (last, first) };
+            if !inside(within) {
+                return None
+            }
+            while (within - outside).abs() > 1 {
+                let middle = outside + (within - outside) / 2;
+                if inside(middle) { within = middle } else { outside = middle }
+            }
+            if moving_first { first = within } else { last = within }
+        }
+    }
+}
+
+impl Iterator for LinePixels {
+    type Item = (i32, i32);
+
+    fn next(&mut self) -> Option<(i32, i32)> {
+        if self.next > self.last {
+            return None
+        }
+        let (major, minor) = (self.next, self.minor_step * self.offset);
+        let point = if self.steep { (self.x0 + minor, self.y0 + major) } else { (self.x0 + major, self.y0 + minor) };
+
+        self.next += 1;
+        self.error += 2 * self.minor;
+        if self.error >= 2 * self.major {
+            self.error -= 2 * self.major;
+            self.offset += 1;
+        }
+        Some((point.0 as i32, point.1 as i32))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.last - self.next + 1).max(0) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+// Draw a line with both endpoints, clipped to the target.
+pub fn line<C>(image: &mut impl RenderTarget<C>, x0: i32, y0: i32, x1: i32, y1: i32, color: &C) {
+    let bounds = Rect::of_size(image.width(), image.height());
+    line_clipped(image, x0, y0, x1, y1, bounds, color)
+}
+
+// Draw a line with both endpoints, clipped to `clip` and the target.
+pub fn line_clipped<C>(image: &mut impl RenderTarget<C>, x0: i32, y0: i32, x1: i32, y1: i32, clip: Rect, color: &C) {
+    let Some(clip) = clip.intersect(&Rect::of_size(image.width(), image.height())) else {
+        return
+    };
+    for (x, y) in LinePixels::clipped(x0, y0, x1, y1, clip) {
+        // Safety: the line was clipped to the target
+        unsafe { image.set_unchecked(x, y, color) }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels(line: LinePixels) -> Vec<(i32, i32)> {
+        let mut pixels: Vec<_> = line.collect();
+        pixels.sort_unstable();
+        pixels
+    }
+
+    // The pixels of `line_segment_v3` and both endpoints, it leaves out the one with the larger major coordinate.
+    fn reference_pixels(x0: i32, y0: i32, x1: i32, y1: i32) -> Vec<(i32, i32)> {
+        let white = TgaColor::from_rgb(255, 255, 255);
+        let mut image = TgaImage::new(21, 21, crate::tga::TgaFormat::RGB);
+        line_segment_v3(&mut image, x0, y0, x1, y1, &white);
+        image.set(x0, y0, &white);
+        image.set(x1, y1, &white);
+
+        let mut pixels = Vec::new();
+        for y in 0..21 {
+            for x in 0..21 {
+                if image.try_get(x, y) == Some(white.clone()) {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels.sort_unstable();
+        pixels
+    }
+
+    #[test]
+    fn matches_the_reference_in_every_octant() {
+        // lines with an odd length along the major axis never fall halfway between two pixels,
+        // where the reference rounds differently
+        for dy in -9..=9i32 {
+            for dx in -9..=9i32 {
+                if dx.abs().max(dy.abs()) % 2 == 0 {
+                    continue
+                }
+                let line = pixels(LinePixels::new(10, 10, 10 + dx, 10 + dy));
+                assert_eq!(line, reference_pixels(10, 10, 10 + dx, 10 + dy), "line to ({}, {})", dx, dy);
+                assert!(line.contains(&(10, 10)) && line.contains(&(10 + dx, 10 + dy)));
+                assert_eq!(line.len() as i32, dx.abs().max(dy.abs()) + 1);
+            }
+        }
+        assert_eq!(pixels(LinePixels::new(3, 4, 3, 4)), [(3, 4)]);
+    }
+
+    #[test]
+    fn swapping_the_endpoints_gives_the_same_pixels() {
+        for dy in -8..=8 {
+            for dx in -8..=8 {
+                assert_eq!(pixels(LinePixels::new(1, 2, 1 + dx, 2 + dy)), pixels(LinePixels::new(1 + dx, 2 + dy, 1, 2)));
+            }
+        }
+    }
+
+    #[test]
+    fn clipping_keeps_the_pixels_of_the_whole_line() {
+        let clips = [Rect::new(2, 3, 10, 7), Rect::new(-5, -5, 3, 40), Rect::new(0, 0, 1, 1), Rect::new(4, 4, 0, 5)];
+        let lines = [
+            (-10, -10, 20, 20),  // crosses the corners
+            (-3, 12, 14, 1),
+            (11, -4, 11, 30),
+            (-20, 5, 40, 6),
+            (30, 30, 40, 35),    // entirely outside
+            (-8, 14, 3, 30),     // outside, next to a corner
+            (5, 5, 6, 6),        // entirely inside
+        ];
+        for clip in &clips {
+            for &(x0, y0, x1, y1) in &lines {
+                let expected: Vec<_> = pixels(LinePixels::new(x0, y0, x1, y1)).into_iter().filter(|&(x, y)| clip.contains(x, y)).collect();
+                assert_eq!(pixels(LinePixels::clipped(x0, y0, x1, y1, *clip)), expected, "{:?} clipped to {:?}", (x0, y0, x1, y1), clip);
+                assert_eq!(pixels(LinePixels::clipped(x1, y1, x0, y0, *clip)), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn clips_lines_across_the_whole_range() {
+        let clip = Rect::new(-3, -2, 7, 5);
+        let lines = [
+            (i32::MIN, i32::MIN, i32::MAX, i32::MAX),
+            (i32::MIN, i32::MAX, i32::MAX, i32::MIN),
+            (i32::MIN, -1, i32::MAX, 1),
+            (0, i32::MIN, 1, i32::MAX),
+            (i32::MIN, -50, i32::MAX, 50),
+        ];
+        for &(x0, y0, x1, y1) in &lines {
+            // the whole line is too long to filter, only its steps with a major coordinate inside of `clip` can be in it
+            let line = LinePixels::new(x0, y0, x1, y1);
+            let (start, end) = if line.steep { (clip.y as i64 - line.y0, clip.bottom() as i64 - line.y0) } else { (clip.x as i64 - line.x0, clip.right() as i64 - line.x0) };
+            let mut expected: Vec<_> = (start.max(0)..end.min(line.major + 1))
+                .map(|step| line.point(step))
+                .map(|(x, y)| (x as i32, y as i32))
+                .filter(|&(x, y)| clip.contains(x, y))
+                .collect();
+            expected.sort_unstable();
+            assert!(!expected.is_empty());
+            assert_eq!(pixels(LinePixels::clipped(x0, y0, x1, y1, clip)), expected, "{:?}", (x0, y0, x1, y1));
+
+            // and every pixel is the closest one to the exact line
+            let (dx, dy) = (x1 as i128 - x0 as i128, y1 as i128 - y0 as i128);
+            for (x, y) in expected {
+                let (px, py) = (x as i128 - x0 as i128, y as i128 - y0 as i128);
+                let distance = (2 * (px * dy - py * dx)).abs();
+                assert!(distance <= dx.abs().max(dy.abs()), "({}, {}) is off the line", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn size_hint_is_exact() {
+        let mut line = LinePixels::new(-4, 7, 9, -2);
+        for remaining in (0..=14).rev() {
+            assert_eq!(line.size_hint(), (remaining, Some(remaining)));
+            line.next();
+        }
+        assert_eq!(line.size_hint(), (0, Some(0)));
+
+        for &clip in &[Rect::new(0, 0, 5, 5), Rect::new(100, 100, 5, 5), Rect::new(0, 0, 0, 0)] {
+            let line = LinePixels::clipped(-4, 7, 9, -2, clip);
+            let count = line.clone().count();
+            assert_eq!(line.size_hint(), (count, Some(count)));
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//!
+//! Linear float colors and sRGB transfer functions, see https://en.wikipedia.org/wiki/SRGB
+//! Shading math should be done on `Color`, 8-bit colors(`TgaColor`) are sRGB encoded.
+//!
+
+use std::ops::{Add, AddAssign, Sub, Mul, MulAssign, Div};
+
+use crate::tga::TgaColor;
+use crate::hdr::Rgbaf;
+use crate::image::luminance;
+
+
+// Decoding table of the 256 sRGB values, built on first use.
+fn srgb_table() -> &'static [f32; 256] {
+    static TABLE: std::sync::OnceLock<[f32; 256]> = std::sync::OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, v) in table.iter_mut().enumerate() {
+            let c = i as f32 / 255.0;
+            *v = if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+        }
+        table
+    })
+}
+
+// Convert an 8-bit sRGB encoded value to linear in [0, 1].
+pub fn srgb_to_linear(v: u8) -> f32 {
+    srgb_table()[v as usize]
+}
+
+// Convert a linear value to 8-bit sRGB, values outside of [0, 1] are clamped.
+pub fn linear_to_srgb(v: f32) -> u8 {
+    let v = if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) };
+    let c = if v <= 0.003_130_8 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0 + 0.5) as u8
+}
+
+
+// A color in linear space with straight alpha, channels are not clamped.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+
+    pub const BLACK: Color = Color::rgb(0.0, 0.0, 0.0);
+    pub const WHITE: Color = Color::rgb(1.0, 1.0, 1.0);
+    pub const TRANSPARENT: Color = Color::rgba(0.0, 0.0, 0.0, 0.0);
+
+    pub const fn rgb(r: f32, g: f32, b: f32) -> Color {
+        Color { r, g, b, a: 1.0 }
+    }
+
+    pub const fn rgba(r: f32, g: f32, b: f32, a: f32) -> Color {
+        Color { r, g, b, a }
+    }
+
+    pub const fn gray(v: f32) -> Color {
+        Color::rgb(v, v, v)
+    }
+
+    // Decode a sRGB color, alpha is linear.
+    pub fn from_srgb8(color: &TgaColor) -> Color {
+        Color::rgba(srgb_to_linear(color.r()), srgb_to_linear(color.g()), srgb_to_linear(color.b()), color.a() as f32 / 255.0)
+    }
+
+    // Encode to a sRGB color, channels are clamped to [0, 1].
+    pub fn to_srgb8(&self) -> TgaColor {
+        TgaColor::from_rgba(linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b), (self.a.clamp(0.0, 1.0) * 255.0 + 0.5) as u8)
+    }
+
+    pub fn luminance(&self) -> f32 {
+        luminance(self.r, self.g, self.b)
+    }
+
+    // Alpha is interpolated too.
+    pub fn lerp(&self, other: Color, t: f32) -> Color {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Color::rgba(lerp(self.r, other.r), lerp(self.g, other.g), lerp(self.b, other.b), lerp(self.a, other.a))
+    }
+
+    pub fn clamped(&self) -> Color {
+        Color::rgba(self.r.clamp(0.0, 1.0), self.g.clamp(0.0, 1.0), self.b.clamp(0.0, 1.0), self.a.clamp(0.0, 1.0))
+    }
+
+    pub fn with_alpha(&self, a: f32) -> Color {
+        Color { a, ..*self }
+    }
+}
+
+// Sums and products of colors apply to all four channels, while scaling by a number leaves alpha as is,
+// like `TgaColor`, since alpha is a coverage rather than an intensity.
+impl Add for Color {
+    type Output = Color;
+
+    fn add(self, rhs: Color) -> Color {
+        Color::rgba(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b, self.a + rhs.a)
+    }
+}
+
+impl AddAssign for Color {
+
+    fn add_assign(&mut self, rhs: Color) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Color {
+    type Output = Color;
+
+    fn sub(self, rhs: Color) -> Color {
+        Color::rgba(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b, self.a - rhs.a)
+    }
+}
+
+impl Mul for Color {
+    type Output = Color;
+
+    // component-wise, e.g. light color times albedo
+    fn mul(self, rhs: Color) -> Color {
+        Color::rgba(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b, self.a * rhs.a)
+    }
+}
+
+impl Mul<f32> for Color {
+    type Output = Color;
+
+    fn mul(self, rhs: f32) -> Color {
+        Color::rgba(self.r * rhs, self.g * rhs, self.b * rhs, self.a)
+    }
+}
+
+impl MulAssign<f32> for Color {
+
+    fn mul_assign(&mut self, rhs: f32) {
+        *self = *self * rhs;
+    }
+}
+
+impl Div<f32> for Color {
+    type Output = Color;
+
+    fn div(self, rhs: f32) -> Color {
+        Color::rgba(self.r / rhs, self.g / rhs, self.b / rhs, self.a)
+    }
+}
+
+impl From<Color> for Rgbaf {
+    fn from(c: Color) -> Rgbaf {
+        Rgbaf::new(c.r, c.g, c.b, c.a)
+    }
+}
+
+impl From<Rgbaf> for Color {
+    fn from(c: Rgbaf) -> Color {
+        Color::rgba(c.r, c.g, c.b, c.a)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaling_leaves_alpha() {
+        let color = Color::rgba(0.5, 1.0, 2.0, 0.25);
+        assert_eq!(color * 0.5, Color::rgba(0.25, 0.5, 1.0, 0.25));
+        assert_eq!(color / 2.0, Color::rgba(0.25, 0.5, 1.0, 0.25));
+
+        let mut scaled = color;
+        scaled *= 0.0;
+        assert_eq!(scaled, Color::rgba(0.0, 0.0, 0.0, 0.25));
+
+        let color = TgaColor::from_rgba(100, 200, 50, 64) * 0.5;
+        assert_eq!(color.rgba(), [50, 100, 25, 64]);
+    }
+
+    #[test]
+    fn interpolates_alpha() {
+        let color = Color::TRANSPARENT.lerp(Color::rgba(1.0, 0.5, 0.0, 1.0), 0.25);
+        assert_eq!(color, Color::rgba(0.25, 0.125, 0.0, 0.25));
+    }
+}
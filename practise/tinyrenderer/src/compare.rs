@@ -0,0 +1,369 @@
+//!
+//! Image comparison for golden-image tests: per-channel error statistics, PSNR, SSIM and heat-map diff images.
+//! SSIM follows Wang et al., "Image Quality Assessment: From Error Visibility to Structural Similarity"(2004),
+//! with a 11x11 gaussian window(sigma = 1.5).
+//!
+
+use crate::tga::{TgaImage, TgaFormat};
+use crate::image::luminance;
+
+
+#[derive(Debug)]
+pub enum CompareError {
+    DimensionMismatch { expected: (i32, i32), actual: (i32, i32) },
+}
+
+pub type CompareResult<T> = Result<T, CompareError>;
+
+impl std::fmt::Display for CompareError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            | CompareError::DimensionMismatch { expected, actual } =>
+                write!(f, "image is {}x{}, expected {}x{}", actual.0, actual.1, expected.0, expected.1),
+        }
+    }
+}
+
+impl std::error::Error for CompareError {}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompareOptions {
+    pub tolerance: u8,               // largest per-channel difference for two pixels to be considered equal
+    pub max_mismatch_fraction: f64,  // fraction of the pixels which may exceed the tolerance
+    pub min_ssim: f64,               // smallest accepted mean SSIM
+    pub heat_scale: f32,             // amplification of the differences in the diff image
+}
+
+impl Default for CompareOptions {
+
+    fn default() -> CompareOptions {
+        CompareOptions { tolerance: 0, max_mismatch_fraction: 0.0, min_ssim: 0.0, heat_scale: 4.0 }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelStats {
+    pub max_error: u8,
+    pub rmse: f64,
+    pub psnr: f64, // in dB, infinite for identical channels
+    pub ssim: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageComparison {
+    pub channels: Vec<ChannelStats>, // in R, G, B, A order, a single channel when both images are grayscale
+    pub max_error: u8,
+    pub rmse: f64,
+    pub psnr: f64,
+    pub ssim: f64, // mean of the channels
+    pub mismatched_pixels: usize,
+    pub pixel_count: usize,
+}
+
+impl ImageComparison {
+
+    pub fn is_identical(&self) -> bool {
+        self.max_error == 0
+    }
+
+    pub fn mismatch_fraction(&self) -> f64 {
+        if self.pixel_count == 0 { 0.0 } else { self.mismatched_pixels as f64 / self.pixel_count as f64 }
+    }
+
+    pub fn passes(&self, options: &CompareOptions) -> bool {
+        self.mismatch_fraction() <= options.max_mismatch_fraction && self.ssim >= options.min_ssim
+    }
+}
+
+
+// Channel count used when comparing images of these formats, grayscale < RGB < RGBA.
+fn common_channels(a: TgaFormat, b: TgaFormat) -> usize {
+    (a as usize).max(b as usize)
+}
+
+// Samples of the image in R, G, B, A order with the given channel count, one plane per channel.
+// Grayscale is replicated to the color channels and missing alpha is opaque.
+fn planes(image: &TgaImage, channels: usize) -> Vec<Vec<u8>> {
+
+    let bytes_per_pixel = image.format() as usize;
+    let mut planes = vec![Vec::with_capacity(image.raw_data().len() / bytes_per_pixel); channels];
+    for pixel in image.raw_data().chunks_exact(bytes_per_pixel) {
+        for (c, plane) in planes.iter_mut().enumerate() {
+            plane.push(match (bytes_per_pixel, c) {
+                | (1, 3) | (3, 3) => 255,
+                | (1, _) => pixel[0],
+                | (_, 3) => pixel[3],
+                | (_, _) => pixel[c],
+            });
+        }
+    }
+    planes
+}
+
+fn check_dimensions(expected: &TgaImage, actual: &TgaImage) -> CompareResult<()> {
+    if expected.width != actual.width || expected.height != actual.height {
+        return Err(CompareError::DimensionMismatch { expected: (expected.width, expected.height), actual: (actual.width, actual.height) })
+    }
+    Ok(())
+}
+
+fn psnr(mse: f64) -> f64 {
+    if mse == 0.0 { f64::INFINITY } else { 10.0 * (255.0 * 255.0 / mse).log10() }
+}
+
+// Compare an image against the expected(golden) image, `options` only decides which pixels count as mismatched.
+pub fn compare(expected: &TgaImage, actual: &TgaImage, options: &CompareOptions) -> CompareResult<ImageComparison> {
+
+    check_dimensions(expected, actual)?;
+
+    let channels = common_channels(expected.format(), actual.format());
+    let expected_planes = planes(expected, channels);
+    let actual_planes = planes(actual, channels);
+    let pixel_count = (expected.width * expected.height) as usize;
+    let (width, height) = (expected.width as usize, expected.height as usize);
+
+    let mut stats = Vec::with_capacity(channels);
+    let mut total_squared_error = 0.0;
+    for (a, b) in expected_planes.iter().zip(actual_planes.iter()) {
+        let mut max_error = 0;
+        let mut squared_error = 0.0;
+        for (&va, &vb) in a.iter().zip(b.iter()) {
+            let error = va.abs_diff(vb);
+            max_error = max_error.max(error);
+            squared_error += (error as f64) * (error as f64);
+        }
+        total_squared_error += squared_error;
+
+        let mse = if pixel_count == 0 { 0.0 } else { squared_error / pixel_count as f64 };
+        stats.push(ChannelStats { max_error, rmse: mse.sqrt(), psnr: psnr(mse), ssim: ssim(a, b, width, height) });
+    }
+
+    let mismatched_pixels = (0..pixel_count)
+        .filter(|&i| expected_planes.iter().zip(actual_planes.iter()).any(|(a, b)| a[i].abs_diff(b[i]) > options.tolerance))
+        .count();
+
+    let sample_count = pixel_count * channels;
+    let mse = if sample_count == 0 { 0.0 } else { total_squared_error / sample_count as f64 };
+    Ok(ImageComparison {
+        max_error: stats.iter().map(|s| s.max_error).max().unwrap_or(0),
+        rmse: mse.sqrt(),
+        psnr: psnr(mse),
+        ssim: stats.iter().map(|s| s.ssim).sum::<f64>() / channels as f64,
+        channels: stats,
+        mismatched_pixels,
+        pixel_count,
+    })
+}
+
+// Gaussian blur with a 11x11 window(sigma = 1.5), samples outside of the image are clamped to the border.
+fn gaussian_blur(plane: &[f64], width: usize, height: usize) -> Vec<f64> {
+
+    const RADIUS: i64 = 5;
+    const SIGMA: f64 = 1.5;
+
+    let kernel: Vec<f64> = (-RADIUS..=RADIUS).map(|i| (-((i * i) as f64) / (2.0 * SIGMA * SIGMA)).exp()).collect();
+    let sum: f64 = kernel.iter().sum();
+    let kernel: Vec<f64> = kernel.iter().map(|k| k / sum).collect();
+
+    let clamp = |v: i64, max: usize| v.clamp(0, max as i64 - 1) as usize;
+
+    // the kernel is separable, blur the rows then the columns
+    let mut horizontal = vec![0.0; plane.len()];
+    for y in 0..height {
+        for x in 0..width {
+            horizontal[x + y * width] = kernel.iter().enumerate()
+                .map(|(k, w)| w * plane[clamp(x as i64 + k as i64 - RADIUS, width) + y * width])
+                .sum();
+        }
+    }
+
+    let mut result = vec![0.0; plane.len()];
+    for y in 0..height {
+        for x in 0..width {
+            result[x + y * width] = kernel.iter().enumerate()
+                .map(|(k, w)| w * horizontal[x + clamp(y as i64 + k as i64 - RADIUS, height) * width])
+                .sum();
+        }
+    }
+    result
+}
+
+// Mean structural similarity of two planes, 1.0 for identical planes.
+fn ssim(a: &[u8], b: &[u8], width: usize, height: usize) -> f64 {
+
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    if a.is_empty() {
+        return 1.0
+    }
+
+    let a: Vec<f64> = a.iter().map(|&v| v as f64).collect();
+    let b: Vec<f64> = b.iter().map(|&v| v as f64).collect();
+    let aa: Vec<f64> = a.iter().map(|v| v * v).collect();
+    let bb: Vec<f64> = b.iter().map(|v| v * v).collect();
+    let ab: Vec<f64> = a.iter().zip(b.iter()).map(|(x, y)| x * y).collect();
+
+    let mu_a = gaussian_blur(&a, width, height);
+    let mu_b = gaussian_blur(&b, width, height);
+    let sigma_aa = gaussian_blur(&aa, width, height);
+    let sigma_bb = gaussian_blur(&bb, width, height);
+    let sigma_ab = gaussian_blur(&ab, width, height);
+
+    let total: f64 = (0..a.len()).map(|i| {
+        let (ma, mb) = (mu_a[i], mu_b[i]);
+        // E[x^2] - E[x]^2 may come out slightly negative because of rounding
+        let var_a = (sigma_aa[i] - ma * ma).max(0.0);
+        let var_b = (sigma_bb[i] - mb * mb).max(0.0);
+        let cov = sigma_ab[i] - ma * mb;
+        ((2.0 * ma * mb + C1) * (2.0 * cov + C2)) / ((ma * ma + mb * mb + C1) * (var_a + var_b + C2))
+    }).sum();
+
+    total / a.len() as f64
+}
+
+// Map t in [0, 1] to a blue -> cyan -> green -> yellow -> red ramp, returned in RGB order.
+fn heat_color(t: f32) -> [u8; 3] {
+
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    let (r, g, b) = match t as u32 {
+        | 0 => (0.0, t, 1.0),
+        | 1 => (0.0, 1.0, 2.0 - t),
+        | 2 => (t - 2.0, 1.0, 0.0),
+        | _ => (1.0, (4.0 - t).max(0.0), 0.0),
+    };
+    [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8]
+}
+
+// Make a RGB heat map of the differences: pixels within the tolerance show the expected image dimmed to grayscale,
+// the others go from blue(just above the tolerance) to red, the largest channel difference is scaled by `heat_scale`.
+pub fn diff_image(expected: &TgaImage, actual: &TgaImage, options: &CompareOptions) -> CompareResult<TgaImage> {
+
+    check_dimensions(expected, actual)?;
+
+    let channels = common_channels(expected.format(), actual.format());
+    let expected_planes = planes(expected, channels);
+    let actual_planes = planes(actual, channels);
+    let pixel_count = (expected.width * expected.height) as usize;
+
+    let mut data = Vec::with_capacity(pixel_count * TgaFormat::RGB as usize);
+    for i in 0..pixel_count {
+        let error = expected_planes.iter().zip(actual_planes.iter()).map(|(a, b)| a[i].abs_diff(b[i])).max().unwrap_or(0);
+        if error <= options.tolerance {
+            // luma of the expected pixel
+            let luma = if channels == 1 {
+                expected_planes[0][i] as f32
+            } else {
+                luminance(expected_planes[0][i] as f32, expected_planes[1][i] as f32, expected_planes[2][i] as f32)
+            };
+            let v = (luma * 0.25) as u8;
+            data.extend_from_slice(&[v, v, v]);
+        } else {
+            data.extend_from_slice(&heat_color(error as f32 / 255.0 * options.heat_scale));
+        }
+    }
+
+    Ok(TgaImage::from_raw_data(expected.width, expected.height, TgaFormat::RGB, data).expect("Pixel count must match the dimensions"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tga::TgaColor;
+
+    fn noise(width: i32, height: i32) -> TgaImage {
+        let mut state = 5_u32;
+        let mut image = TgaImage::new(width, height, TgaFormat::RGB);
+        for y in 0..height {
+            for x in 0..width {
+                let mut next = || {
+                    state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                    ((state >> 16) % 200) as u8
+                };
+                image.set(x, y, &TgaColor::from_rgb(next(), next(), next()));
+            }
+        }
+        image
+    }
+
+    // Add `delta` to the red channel of the pixels, the noise leaves room for 55.
+    fn shifted(image: &TgaImage, pixels: &[(i32, i32)], delta: u8) -> TgaImage {
+        let mut image = image.clone();
+        for &(x, y) in pixels {
+            let color = image.try_get(x, y).unwrap();
+            image.set(x, y, &TgaColor::from_rgb(color.r() + delta, color.g(), color.b()));
+        }
+        image
+    }
+
+    #[test]
+    fn identical_images_match_exactly() {
+        let image = noise(16, 12);
+        let comparison = compare(&image, &image, &CompareOptions::default()).unwrap();
+        assert!(comparison.is_identical());
+        assert_eq!(comparison.psnr, f64::INFINITY);
+        assert_eq!(comparison.ssim, 1.0);
+        assert_eq!((comparison.rmse, comparison.mismatched_pixels, comparison.pixel_count), (0.0, 0, 16 * 12));
+        assert_eq!(comparison.channels.len(), 3);
+        assert!(comparison.channels.iter().all(|c| c.psnr == f64::INFINITY && c.ssim == 1.0));
+        assert!(comparison.passes(&CompareOptions { min_ssim: 1.0, ..CompareOptions::default() }));
+
+        let different = compare(&image, &shifted(&image, &[(3, 4)], 50), &CompareOptions::default()).unwrap();
+        assert!(!different.is_identical());
+        assert!(different.psnr.is_finite() && different.ssim < 1.0);
+    }
+
+    #[test]
+    fn rejects_different_dimensions() {
+        let (a, b) = (noise(4, 3), noise(3, 4));
+        assert!(matches!(compare(&a, &b, &CompareOptions::default()), Err(CompareError::DimensionMismatch { expected: (4, 3), actual: (3, 4) })));
+        assert!(diff_image(&a, &b, &CompareOptions::default()).is_err());
+    }
+
+    #[test]
+    fn tolerance_and_mismatch_fraction_decide_passing() {
+        let expected = noise(10, 10);
+        let slightly = [(0, 0), (1, 0), (2, 0), (3, 0), (4, 0)];
+        let actual = shifted(&shifted(&expected, &slightly, 3), &[(9, 9)], 20);
+        let options = |tolerance, max_mismatch_fraction| CompareOptions { tolerance, max_mismatch_fraction, ..CompareOptions::default() };
+
+        let strict = compare(&expected, &actual, &options(2, 0.0)).unwrap();
+        assert_eq!(strict.mismatched_pixels, 6);
+        assert_eq!(strict.max_error, 20);
+        assert!(!strict.passes(&options(2, 0.05)));
+        assert!(strict.passes(&options(2, 0.06)));
+
+        let tolerant = compare(&expected, &actual, &options(3, 0.0)).unwrap();
+        assert_eq!(tolerant.mismatched_pixels, 1);
+        assert!(!tolerant.passes(&options(3, 0.0)));
+        assert!(tolerant.passes(&options(3, 0.01)));
+        assert_eq!(compare(&expected, &actual, &options(20, 0.0)).unwrap().mismatched_pixels, 0);
+    }
+
+    #[test]
+    fn diff_image_colors_the_pixels_above_the_tolerance() {
+        let expected = noise(6, 5);
+        let actual = shifted(&shifted(&expected, &[(1, 1)], 2), &[(4, 3), (0, 4)], 50);
+        let diff = diff_image(&expected, &actual, &CompareOptions { tolerance: 2, ..CompareOptions::default() }).unwrap();
+        assert_eq!(diff.format(), TgaFormat::RGB);
+
+        for y in 0..5 {
+            for x in 0..6 {
+                let color = diff.try_get(x, y).unwrap();
+                let is_gray = color.r() == color.g() && color.g() == color.b();
+                if (x, y) == (4, 3) || (x, y) == (0, 4) {
+                    assert!(!is_gray, "({}, {}) is above the tolerance", x, y);
+                } else {
+                    // the expected pixel, dimmed
+                    let pixel = expected.try_get(x, y).unwrap();
+                    let dimmed = (luminance(pixel.r() as f32, pixel.g() as f32, pixel.b() as f32) * 0.25) as u8;
+                    assert!(is_gray && color.r() == dimmed, "({}, {})", x, y);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//!
+//! Alpha compositing of images with the Porter-Duff operators and separable blend modes,
+//! following the W3C "Compositing and Blending Level 1" model:
+//!     source' = (1 - dst_alpha) * source + dst_alpha * blend(destination, source)
+//!     result  = source' * Fa + destination * Fb   (premultiplied, Fa/Fb depend on the operator)
+//! The channels are blended as stored, 8-bit images are not decoded from sRGB.
+//!
+
+use crate::image::{Image, Pixel, Rect};
+use crate::tga::{TgaImage, TgaPixels};
+
+
+// Porter-Duff operators, how the source and the destination shapes are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Over, // source on top of the destination
+    In,   // source where the destination is, the destination is dropped
+    Out,  // source where the destination isn't, the destination is dropped
+    Atop, // source where the destination is, on top of the destination
+    Xor,  // source and destination where the other one isn't
+}
+
+impl Operator {
+
+    // (Fa, Fb), the fractions of the source and the destination kept.
+    fn fractions(&self, source_alpha: f32, destination_alpha: f32) -> (f32, f32) {
+        match self {
+            | Operator::Over => (1.0, 1.0 - source_alpha),
+            | Operator::In   => (destination_alpha, 0.0),
+            | Operator::Out  => (1.0 - destination_alpha, 0.0),
+            | Operator::Atop => (destination_alpha, 1.0 - source_alpha),
+            | Operator::Xor  => (1.0 - destination_alpha, 1.0 - source_alpha),
+        }
+    }
+}
+
+// How the colors of the source and the destination are mixed where both are present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    Normal,   // the source color
+    Multiply, // darkens, white is neutral
+    Screen,   // lightens, black is neutral
+    Additive, // sum of the colors, clamped to 1
+}
+
+impl BlendMode {
+
+    // Blend straight(non-premultiplied) color channels.
+    fn apply(&self, destination: f32, source: f32) -> f32 {
+        match self {
+            | BlendMode::Normal => source,
+            | BlendMode::Multiply => destination * source,
+            | BlendMode::Screen => destination + source - destination * source,
+            | BlendMode::Additive => (destination + source).min(1.0),
+        }
+    }
+}
+
+// How the color channels of images with alpha are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    Straight,      // colors are independent of alpha
+    Premultiplied, // colors are already multiplied by alpha
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompositeOptions {
+    pub operator: Operator,
+    pub blend: BlendMode,
+    pub opacity: f32,     // scales the source alpha
+    pub alpha: AlphaMode, // of both the source and the destination
+}
+
+impl Default for CompositeOptions {
+
+    fn default() -> CompositeOptions {
+        CompositeOptions { operator: Operator::Over, blend: BlendMode::Normal, opacity: 1.0, alpha: AlphaMode::Straight }
+    }
+}
+
+impl CompositeOptions {
+
+    pub fn new(operator: Operator, blend: BlendMode) -> CompositeOptions {
+        CompositeOptions { operator, blend, ..CompositeOptions::default() }
+    }
+
+    // Composite two premultiplied RGBA colors.
+    pub fn composite_premultiplied(&self, destination: [f32; 4], source: [f32; 4]) -> [f32; 4] {
+
+        let opacity = self.opacity.clamp(0.0, 1.0);
+        let source = source.map(|v| v * opacity);
+        let (sa, da) = (source[3], destination[3]);
+        let (fa, fb) = self.operator.fractions(sa, da);
+
+        let mut result = [0.0; 4];
+        for c in 0..3 {
+            let mixed = if self.blend == BlendMode::Normal {
+                source[c]
+            } else {
+                // blend the straight colors, then premultiply again by the source alpha
+                let straight = |v: f32, a: f32| if a > 0.0 { v / a } else { 0.0 };
+                (1.0 - da) * source[c] + sa * da * self.blend.apply(straight(destination[c], da), straight(source[c], sa))
+            };
+            result[c] = mixed * fa + destination[c] * fb;
+        }
+        result[3] = sa * fa + da * fb;
+        result
+    }
+
+    fn load(&self, rgba: [f32; 4]) -> [f32; 4] {
+        match self.alpha {
+            | AlphaMode::Straight => premultiply(rgba),
+            | AlphaMode::Premultiplied => rgba,
+        }
+    }
+
+    fn store<P: Pixel>(&self, rgba: [f32; 4]) -> P {
+        // formats without alpha keep the result flattened over black, which is its premultiplied color
+        if !P::HAS_ALPHA {
+            return P::from_rgba([rgba[0], rgba[1], rgba[2], 1.0])
+        }
+        match self.alpha {
+            | AlphaMode::Straight => P::from_rgba(unpremultiply(rgba)),
+            | AlphaMode::Premultiplied => P::from_rgba(rgba),
+        }
+    }
+}
+
+fn premultiply(rgba: [f32; 4]) -> [f32; 4] {
+    [rgba[0] * rgba[3], rgba[1] * rgba[3], rgba[2] * rgba[3], rgba[3]]
+}
+
+fn unpremultiply(rgba: [f32; 4]) -> [f32; 4] {
+    if rgba[3] > 0.0 {
+        [rgba[0] / rgba[3], rgba[1] / rgba[3], rgba[2] / rgba[3], rgba[3]]
+    } else {
+        [0.0; 4]
+    }
+}
+
+
+// Composite the `source_rect` part of `source` onto `destination` with its top-left corner at (x, y),
+// the parts falling outside of either image are skipped.
+pub fn composite_image<P: Pixel, Q: Pixel>(destination: &mut Image<P>, x: i32, y: i32, source: &Image<Q>, source_rect: Rect, options: &CompositeOptions) {
+
+    let (dx, dy) = (x - source_rect.x, y - source_rect.y);
+    let area = source_rect.intersect(&Rect::of_size(source.width() as i32, source.height() as i32))
+        .and_then(|rect| rect.translated(dx, dy).intersect(&Rect::of_size(destination.width() as i32, destination.height() as i32)));
+    let Some(area) = area else {
+        return
+    };
+
+    for j in area.y..area.bottom() {
+        for i in area.x..area.right() {
+            let source = options.load(source[((i - dx) as usize, (j - dy) as usize)].to_rgba());
+            let pixel = &mut destination[(i as usize, j as usize)];
+            let result = options.composite_premultiplied(options.load(pixel.to_rgba()), source);
+            *pixel = options.store(result);
+        }
+    }
+}
+
+// Composite the whole source onto the destination, both anchored at the top-left corner.
+pub fn composite(destination: &mut TgaImage, source: &TgaImage, options: &CompositeOptions) {
+    composite_rect(destination, 0, 0, source, Rect::of_size(source.width, source.height), options)
+}
+
+// Composite the whole source with its top-left corner at (x, y) of the destination.
+pub fn composite_at(destination: &mut TgaImage, x: i32, y: i32, source: &TgaImage, options: &CompositeOptions) {
+    composite_rect(destination, x, y, source, Rect::of_size(source.width, source.height), options)
+}
+
+// Composite a region of the source with its top-left corner at (x, y) of the destination, see `composite_image`.
+pub fn composite_rect(destination: &mut TgaImage, x: i32, y: i32, source: &TgaImage, source_rect: Rect, options: &CompositeOptions) {
+    match source.pixels() {
+        | TgaPixels::Grayscale(source) => composite_into(destination, x, y, source, source_rect, options),
+        | TgaPixels::RGB(source)  => composite_into(destination, x, y, source, source_rect, options),
+        | TgaPixels::RGBA(source) => composite_into(destination, x, y, source, source_rect, options),
+    }
+}
+
+fn composite_into<Q: Pixel>(destination: &mut TgaImage, x: i32, y: i32, source: &Image<Q>, source_rect: Rect, options: &CompositeOptions) {
+    match destination.pixels_mut() {
+        | TgaPixels::Grayscale(destination) => composite_image(destination, x, y, source, source_rect, options),
+        | TgaPixels::RGB(destination)  => composite_image(destination, x, y, source, source_rect, options),
+        | TgaPixels::RGBA(destination) => composite_image(destination, x, y, source, source_rect, options),
+    }
+}
+
+// Convert a straight alpha image to premultiplied alpha, images without alpha are unchanged.
+pub fn premultiply_alpha(image: &mut TgaImage) {
+    if let TgaPixels::RGBA(image) = image.pixels_mut() {
+        image.pixels_mut().iter_mut().for_each(|p| *p = Pixel::from_rgba(premultiply(p.to_rgba())));
+    }
+}
+
+// Convert a premultiplied alpha image to straight alpha, fully transparent pixels become transparent black.
+pub fn unpremultiply_alpha(image: &mut TgaImage) {
+    if let TgaPixels::RGBA(image) = image.pixels_mut() {
+        image.pixels_mut().iter_mut().for_each(|p| *p = Pixel::from_rgba(unpremultiply(p.to_rgba())));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tga::{TgaColor, TgaFormat};
+
+    const OPAQUE_RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const HALF_BLUE: [f32; 4] = [0.0, 0.0, 0.5, 0.5]; // premultiplied
+    const TRANSPARENT: [f32; 4] = [0.0; 4];
+
+    fn close(a: [f32; 4], b: [f32; 4]) -> bool {
+        a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-6)
+    }
+
+    #[test]
+    fn over_an_opaque_source_gives_the_source() {
+        let options = CompositeOptions::default();
+        for &destination in &[OPAQUE_RED, HALF_BLUE, TRANSPARENT, [0.2, 0.4, 0.6, 1.0]] {
+            let source = [0.1, 0.7, 0.3, 1.0];
+            assert!(close(options.composite_premultiplied(destination, source), source));
+        }
+        // a transparent source leaves the destination
+        assert!(close(options.composite_premultiplied(HALF_BLUE, TRANSPARENT), HALF_BLUE));
+    }
+
+    #[test]
+    fn in_and_out_against_a_transparent_destination() {
+        let composite = |operator, destination, source| CompositeOptions::new(operator, BlendMode::Normal).composite_premultiplied(destination, source);
+        assert!(close(composite(Operator::In, TRANSPARENT, HALF_BLUE), TRANSPARENT));
+        assert!(close(composite(Operator::Out, TRANSPARENT, HALF_BLUE), HALF_BLUE));
+        assert!(close(composite(Operator::Atop, TRANSPARENT, HALF_BLUE), TRANSPARENT));
+
+        // against an opaque destination it is the other way around, and the destination is dropped
+        assert!(close(composite(Operator::In, OPAQUE_RED, HALF_BLUE), HALF_BLUE));
+        assert!(close(composite(Operator::Out, OPAQUE_RED, HALF_BLUE), TRANSPARENT));
+    }
+
+    #[test]
+    fn xor_of_opaque_pixels_is_transparent() {
+        let options = CompositeOptions::new(Operator::Xor, BlendMode::Normal);
+        assert!(close(options.composite_premultiplied(OPAQUE_RED, [0.0, 1.0, 0.0, 1.0]), TRANSPARENT));
+        assert!(close(options.composite_premultiplied(TRANSPARENT, HALF_BLUE), HALF_BLUE));
+        assert!(close(options.composite_premultiplied(HALF_BLUE, TRANSPARENT), HALF_BLUE));
+    }
+
+    #[test]
+    fn multiply_with_white_is_the_identity() {
+        let white = [1.0; 4];
+        let color = [0.2, 0.4, 0.6, 1.0];
+        let options = CompositeOptions::new(Operator::Over, BlendMode::Multiply);
+        assert!(close(options.composite_premultiplied(white, color), color));
+        assert!(close(options.composite_premultiplied(color, white), color));
+
+        let screen = CompositeOptions::new(Operator::Over, BlendMode::Screen);
+        assert!(close(screen.composite_premultiplied([0.0, 0.0, 0.0, 1.0], color), color));
+    }
+
+    #[test]
+    fn composite_rect_clips_negative_offsets() {
+        let mut source = TgaImage::new(3, 3, TgaFormat::RGB);
+        for y in 0..3 {
+            for x in 0..3 {
+                source.set(x, y, &TgaColor::from_rgb((10 * (x + 3 * y)) as u8, 0, 0));
+            }
+        }
+        let reds = |image: &TgaImage| -> Vec<u8> { image.raw_data().chunks_exact(4).map(|p| p[0]).collect() };
+        let alphas = |image: &TgaImage| -> Vec<u8> { image.raw_data().chunks_exact(4).map(|p| p[3]).collect() };
+
+        let mut destination = TgaImage::new(3, 3, TgaFormat::RGBA);
+        composite_at(&mut destination, -1, -2, &source, &CompositeOptions::default());
+        assert_eq!(reds(&destination), [70, 80, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(alphas(&destination), [255, 255, 0, 0, 0, 0, 0, 0, 0]);
+
+        // and parts of the source rectangle outside of the source
+        let mut destination = TgaImage::new(3, 3, TgaFormat::RGBA);
+        composite_rect(&mut destination, -1, 1, &source, Rect::new(-1, 1, 3, 5), &CompositeOptions::default());
+        assert_eq!(reds(&destination), [0, 0, 0, 30, 40, 0, 60, 70, 0]);
+
+        let mut destination = TgaImage::new(3, 3, TgaFormat::RGBA);
+        composite_at(&mut destination, -3, 0, &source, &CompositeOptions::default());
+        composite_at(&mut destination, 0, i32::MIN + 1, &source, &CompositeOptions::default());
+        assert_eq!(alphas(&destination), [0; 9]);
+    }
+}
@@ -0,0 +1,254 @@
+//!
+//! Depth buffers as viewable images and float files. Larger depths are closer to the camera, as the rasterizer
+//! keeps the largest one, and pixels still at f32::MIN were never drawn. The rows keep the layout of the render target,
+//! so the images are flipped like the color output before writing.
+//!
+
+use crate::image::{Image, Depth32F};
+use crate::netpbm::PfmImage;
+use crate::rasterization::{ZBuffer, ZbufferEx};
+use crate::tga::{TgaImage, TgaColor, TgaFormat};
+
+
+// The depths mapped to black and white(or the ends of the colormap), fixed ones are given as stored, before the curve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthRange {
+    Auto,                       // the smallest and largest depth drawn
+    Fixed { min: f32, max: f32 },
+}
+
+// How the stored depths are turned into the values that are normalized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthCurve {
+    Raw,
+    // Undo `camera::projection(coeff)` and the depth of `camera::viewport`, giving the eye space z,
+    // which is linear in the distance to the camera.
+    Perspective { coeff: f32, depth: f32 },
+}
+
+impl DepthCurve {
+
+    pub fn apply(&self, v: f32) -> f32 {
+        match self {
+            | DepthCurve::Raw => v,
+            | DepthCurve::Perspective { coeff, depth } => {
+                // the viewport stores depth / 2 * (z / w + 1), with w = 1 + coeff * z
+                let projected = 2.0 * v / depth - 1.0;
+                projected / (1.0 - coeff * projected)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colormap {
+    Grayscale,
+    Viridis, // perceptually uniform from dark blue to yellow, readable in grayscale print
+    Turbo,   // rainbow from dark blue to dark red, shows small differences better
+}
+
+impl Colormap {
+
+    // Color of t in [0, 1].
+    pub fn map(&self, t: f32) -> [u8; 3] {
+        let t = t.clamp(0.0, 1.0);
+        // polynomial fits of the colormaps, see https://www.shadertoy.com/view/WlfXRN and
+        // https://gist.github.com/mikhailov-work/0d177465a8151eb6ede1768d51d476c7
+        let polynomial = |coefficients: &[[f32; 3]]| -> [f32; 3] {
+            let mut v = [0.0; 3];
+            for coefficient in coefficients.iter().rev() {
+                for c in 0..3 {
+                    v[c] = v[c] * t + coefficient[c];
+                }
+            }
+            v
+        };
+        let rgb = match self {
+            | Colormap::Grayscale => [t, t, t],
+            | Colormap::Viridis => polynomial(&[
+                [ 0.277_727_33,  0.005_407_344,  0.334_099_8],
+                [ 0.105_093_04,  1.404_613_5,    1.384_590_2],
+                [-0.330_861_83,  0.214_847_56,   0.095_095_16],
+                [-4.634_230_5,  -5.799_101,    -19.332_441],
+                [ 6.228_27,     14.179_933,     56.690_55],
+                [ 4.776_385,   -13.745_145,    -65.353_03],
+                [-5.435_456,     4.645_852_6,   26.312_435],
+            ]),
+            | Colormap::Turbo => polynomial(&[
+                [  0.135_721_38,   0.091_402_61,   0.106_673_3],
+                [  4.615_392_6,    2.194_188_4,   12.641_946],
+                [-42.660_32,       4.842_966_6,  -60.582_05],
+                [132.131_08,     -14.185_033,    110.362_77],
+                [-152.942_4,       4.277_298_6,  -89.903_11],
+                [ 59.286_38,       2.829_566,     27.348_25],
+            ]),
+        };
+        rgb.map(|v| (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8)
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct DepthOptions {
+    pub range: DepthRange,
+    pub curve: DepthCurve,
+    pub colormap: Colormap,
+    pub invert: bool,         // far is bright instead of near
+    pub background: TgaColor, // of the pixels never drawn
+}
+
+impl Default for DepthOptions {
+
+    fn default() -> DepthOptions {
+        DepthOptions {
+            range: DepthRange::Auto,
+            curve: DepthCurve::Raw,
+            colormap: Colormap::Grayscale,
+            invert: false,
+            background: TgaColor::from_rgb(0, 0, 0),
+        }
+    }
+}
+
+fn is_drawn(v: f32) -> bool {
+    v.is_finite() && v > f32::MIN
+}
+
+// The depths after the curve, pixels never drawn are -inf.
+pub fn depth_values(zbuffer: &impl ZBuffer, width: usize, height: usize, curve: DepthCurve) -> Image<Depth32F> {
+    let mut image = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let v = zbuffer.get(x, y);
+            image[(x, y)] = Depth32F([if is_drawn(v) { curve.apply(v) } else { f32::NEG_INFINITY }]);
+        }
+    }
+    image
+}
+
+// Normalize the depth buffer of a width x height target into a grayscale image, or a color one with a colormap.
+pub fn depth_to_image(zbuffer: &impl ZBuffer, width: usize, height: usize, options: &DepthOptions) -> TgaImage {
+
+    let values = depth_values(zbuffer, width, height, options.curve);
+    let (min, max) = match options.range {
+        | DepthRange::Auto => values.pixels().iter()
+            .map(|p| p.0[0])
+            .filter(|v| v.is_finite())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v))),
+        | DepthRange::Fixed { min, max } => (options.curve.apply(min), options.curve.apply(max)),
+    };
+    // a flat buffer is all white
+    let scale = if max > min { 1.0 / (max - min) } else { 0.0 };
+
+    let format = if options.colormap == Colormap::Grayscale { TgaFormat::Grayscale } else { TgaFormat::RGB };
+    let mut image = TgaImage::new(width as i32, height as i32, format);
+    for (i, p) in values.pixels().iter().enumerate() {
+        let v = p.0[0];
+        let color = if v.is_finite() {
+            let t = if scale > 0.0 { (v - min) * scale } else { 1.0 };
+            let [r, g, b] = options.colormap.map(if options.invert { 1.0 - t } else { t });
+            TgaColor::from_rgb(r, g, b)
+        } else {
+            options.background.clone()
+        };
+        let color = if format == TgaFormat::Grayscale { TgaColor::from_greyscale(color.luma()) } else { color };
+        image.set((i % width) as i32, (i / width) as i32, &color);
+    }
+    image
+}
+
+// The depths after the curve as a one channel float image, pixels never drawn are -inf.
+// Its rows are flipped, so that the file has the same orientation as the flipped color output.
+pub fn depth_to_pfm(zbuffer: &impl ZBuffer, width: usize, height: usize, curve: DepthCurve) -> PfmImage {
+    let mut values = depth_values(zbuffer, width, height, curve);
+    values.flip_vertically();
+    PfmImage { width, height, channels: 1, data: values.pixels().iter().map(|p| p.0[0]).collect() }
+}
+
+
+impl ZbufferEx {
+
+    // A buffer with nothing drawn yet.
+    pub fn new(width: usize, height: usize) -> ZbufferEx {
+        ZbufferEx { buffer: vec![f32::MIN; width * height], width }
+    }
+
+    pub fn height(&self) -> usize {
+        self.buffer.len().checked_div(self.width).unwrap_or(0)
+    }
+
+    pub fn to_image(&self, options: &DepthOptions) -> TgaImage {
+        depth_to_image(self, self.width, self.height(), options)
+    }
+
+    pub fn to_pfm(&self, curve: DepthCurve) -> PfmImage {
+        depth_to_pfm(self, self.width, self.height(), curve)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{projection, viewport};
+    use crate::Vec4f;
+
+    #[test]
+    fn perspective_curve_inverts_the_viewport() {
+        let (coeff, depth) = (-0.25, 255.0);
+        let transform = viewport(0, 0, 100, 100, depth) * projection(coeff);
+        let curve = DepthCurve::Perspective { coeff, depth };
+        for &z in &[-1.5, -1.0, -0.3, 0.0, 0.4, 1.0] {
+            let stored = transform * Vec4f::new(0.2, -0.1, z, 1.0);
+            let stored = stored.z / stored.w;
+            assert!((curve.apply(stored) - z).abs() < 1e-4, "z = {} gives {}", z, curve.apply(stored));
+        }
+        assert_eq!(DepthCurve::Raw.apply(12.5), 12.5);
+    }
+
+    fn buffer(depths: &[f32]) -> ZbufferEx {
+        ZbufferEx { buffer: depths.to_vec(), width: depths.len() }
+    }
+
+    #[test]
+    fn auto_range_spans_the_drawn_depths() {
+        let zbuffer = buffer(&[f32::MIN, 10.0, 20.0, 30.0]);
+        let options = DepthOptions { background: TgaColor::from_greyscale(77), ..DepthOptions::default() };
+        let image = zbuffer.to_image(&options);
+        assert_eq!(image.format(), TgaFormat::Grayscale);
+        assert_eq!(image.raw_data(), &[77, 0, 128, 255]);
+
+        let inverted = zbuffer.to_image(&DepthOptions { invert: true, ..options.clone() });
+        assert_eq!(inverted.raw_data(), &[77, 255, 128, 0]);
+
+        // fixed ranges clamp the depths outside of them
+        let fixed = zbuffer.to_image(&DepthOptions { range: DepthRange::Fixed { min: 15.0, max: 25.0 }, ..options.clone() });
+        assert_eq!(fixed.raw_data(), &[77, 0, 128, 255]);
+
+        // a flat buffer is white, and one never drawn is the background
+        assert_eq!(buffer(&[5.0, 5.0]).to_image(&options).raw_data(), &[255, 255]);
+        assert_eq!(ZbufferEx::new(2, 1).to_image(&options).raw_data(), &[77, 77]);
+    }
+
+    #[test]
+    fn colormaps_keep_the_background() {
+        let zbuffer = buffer(&[10.0, f32::MIN, 30.0]);
+        let options = DepthOptions { colormap: Colormap::Turbo, background: TgaColor::from_rgb(1, 2, 3), ..DepthOptions::default() };
+        let image = zbuffer.to_image(&options);
+        assert_eq!(image.format(), TgaFormat::RGB);
+        assert_eq!(&image.raw_data()[..3], &Colormap::Turbo.map(0.0));
+        assert_eq!(&image.raw_data()[3..6], &[1, 2, 3]);
+        assert_eq!(&image.raw_data()[6..], &Colormap::Turbo.map(1.0));
+    }
+
+    #[test]
+    fn pfm_marks_pixels_never_drawn() {
+        let zbuffer = ZbufferEx { buffer: vec![1.0, f32::MIN, 3.0, 4.0], width: 2 };
+        let pfm = zbuffer.to_pfm(DepthCurve::Raw);
+        assert_eq!((pfm.width, pfm.height, pfm.channels), (2, 2, 1));
+        // the rows are flipped
+        assert_eq!(pfm.data[..2], [3.0, 4.0]);
+        assert_eq!(pfm.data[2], 1.0);
+        assert_eq!(pfm.data[3], f32::NEG_INFINITY);
+    }
+}
@@ -0,0 +1,424 @@
+//!
+//! Image processing filters: convolution, blurs, gradients, unsharp masking, median filtering and histogram equalization.
+//! Kernels are applied as a correlation(they are not flipped), like most image processing libraries do.
+//! Pixels outside of the image are resolved with a sampler `AddressMode`, ClampToBorder counts them as transparent black.
+//!
+
+use crate::image::{Image, Pixel, Luma8, Rgba32F, luminance};
+use crate::sampler::AddressMode;
+use crate::tga::{TgaImage, TgaPixels, map_pixels};
+
+
+// A 2D kernel of odd dimensions, centered on the filtered pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kernel {
+    width : usize,
+    height: usize,
+    weights: Vec<f32>, // row by row
+}
+
+impl Kernel {
+
+    // Return None if the dimensions are even or don't match the number of weights.
+    pub fn new(width: usize, height: usize, weights: Vec<f32>) -> Option<Kernel> {
+        if width % 2 == 1 && height % 2 == 1 && width * height == weights.len() {
+            Some(Kernel { width, height, weights })
+        } else {
+            None
+        }
+    }
+
+    // The outer product of a horizontal and a vertical kernel, both of odd length.
+    pub fn from_separable(horizontal: &[f32], vertical: &[f32]) -> Option<Kernel> {
+        let weights = vertical.iter().flat_map(|v| horizontal.iter().map(move |h| h * v)).collect();
+        Kernel::new(horizontal.len(), vertical.len(), weights)
+    }
+
+    // Scale the weights so that they sum to 1, kernels summing to 0(e.g. edge detectors) are unchanged.
+    pub fn normalized(mut self) -> Kernel {
+        let sum: f32 = self.weights.iter().sum();
+        if sum != 0.0 {
+            self.weights.iter_mut().for_each(|w| *w /= sum);
+        }
+        self
+    }
+
+    pub fn identity() -> Kernel {
+        Kernel { width: 1, height: 1, weights: vec![1.0] }
+    }
+
+    pub fn sharpen() -> Kernel {
+        Kernel { width: 3, height: 3, weights: vec![0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0] }
+    }
+
+    pub fn laplacian() -> Kernel {
+        Kernel { width: 3, height: 3, weights: vec![0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0] }
+    }
+
+    pub fn width(&self) -> usize { self.width }
+    pub fn height(&self) -> usize { self.height }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+}
+
+// Largest radius of `gaussian_weights`, huge or infinite sigmas would exhaust the memory otherwise.
+pub const MAX_GAUSSIAN_RADIUS: usize = 1 << 16;
+
+// Weights of a 1D gaussian of standard deviation sigma, cut at 3 sigma(at most MAX_GAUSSIAN_RADIUS) and normalized.
+pub fn gaussian_weights(sigma: f32) -> Vec<f32> {
+    gaussian_weights_within(sigma, MAX_GAUSSIAN_RADIUS)
+}
+
+// The blurs also cut the kernel at the size of the image, the taps beyond it only see the border again.
+fn gaussian_weights_within(sigma: f32, max_radius: usize) -> Vec<f32> {
+    if sigma.is_nan() || sigma <= 0.0 {
+        return vec![1.0]
+    }
+    let radius = (3.0 * sigma).ceil().min(max_radius.min(MAX_GAUSSIAN_RADIUS) as f32) as i32;
+    let weights: Vec<f32> = (-radius..=radius).map(|i| (-((i as f32) * (i as f32)) / (2.0 * sigma * sigma)).exp()).collect();
+    let sum: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / sum).collect()
+}
+
+
+// 3x3 derivative operators, the derivative of a unit step is 1/2 per pixel for both(a central difference).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GradientOperator {
+    Sobel,  // smoothing weights 1, 2, 1
+    Scharr, // smoothing weights 3, 10, 3, better rotational symmetry
+}
+
+impl GradientOperator {
+
+    // (smoothing, derivative) separable factors.
+    fn factors(&self) -> ([f32; 3], [f32; 3]) {
+        let smoothing = match self {
+            | GradientOperator::Sobel  => [1.0 / 4.0, 2.0 / 4.0, 1.0 / 4.0],
+            | GradientOperator::Scharr => [3.0 / 16.0, 10.0 / 16.0, 3.0 / 16.0],
+        };
+        (smoothing, [-0.5, 0.0, 0.5])
+    }
+
+    // Kernels of the derivatives along x and y.
+    pub fn kernels(&self) -> (Kernel, Kernel) {
+        let (smoothing, derivative) = self.factors();
+        (Kernel::from_separable(&derivative, &smoothing).unwrap(), Kernel::from_separable(&smoothing, &derivative).unwrap())
+    }
+}
+
+
+// Pixels as RGBA floats, premultiplied for formats with alpha so that linear filters don't bleed transparent colors.
+struct Planes {
+    data: Vec<[f32; 4]>,
+    width : usize,
+    height: usize,
+}
+
+impl Planes {
+
+    fn load<P: Pixel>(image: &Image<P>) -> Planes {
+        let data = image.pixels().iter().map(|p| {
+            let [r, g, b, a] = p.to_rgba();
+            if P::HAS_ALPHA { [r * a, g * a, b * a, a] } else { [r, g, b, a] }
+        }).collect();
+        Planes { data, width: image.width(), height: image.height() }
+    }
+
+    fn store<P: Pixel>(&self) -> Image<P> {
+        let pixels = self.data.iter().map(|&[r, g, b, a]| {
+            if P::HAS_ALPHA && a > 0.0 { P::from_rgba([r / a, g / a, b / a, a]) } else { P::from_rgba([r, g, b, a]) }
+        }).collect();
+        Image::from_vec(self.width, self.height, pixels).expect("Pixel count must match the dimensions")
+    }
+
+    fn get(&self, x: i64, y: i64, border: AddressMode) -> [f32; 4] {
+        match border.resolve(x, self.width as i64).zip(border.resolve(y, self.height as i64)) {
+            | Some((x, y)) => self.data[x as usize + y as usize * self.width],
+            | None => [0.0; 4],
+        }
+    }
+
+    fn convolve(&self, kernel: &Kernel, border: AddressMode) -> Planes {
+
+        let (rx, ry) = ((kernel.width / 2) as i64, (kernel.height / 2) as i64);
+        let mut data = Vec::with_capacity(self.data.len());
+        for y in 0..self.height as i64 {
+            for x in 0..self.width as i64 {
+                let mut sum = [0.0; 4];
+                for (k, &w) in kernel.weights.iter().enumerate() {
+                    if w == 0.0 {
+                        continue
+                    }
+                    let (i, j) = ((k % kernel.width) as i64, (k / kernel.width) as i64);
+                    let v = self.get(x + i - rx, y + j - ry, border);
+                    for c in 0..4 {
+                        sum[c] += w * v[c];
+                    }
+                }
+                data.push(sum);
+            }
+        }
+        Planes { data, width: self.width, height: self.height }
+    }
+
+    fn convolve_separable(&self, horizontal: &[f32], vertical: &[f32], border: AddressMode) -> Planes {
+        self.convolve(&Kernel { width: horizontal.len(), height: 1, weights: horizontal.to_vec() }, border)
+            .convolve(&Kernel { width: 1, height: vertical.len(), weights: vertical.to_vec() }, border)
+    }
+}
+
+
+// Convolve with an arbitrary kernel.
+pub fn convolve<P: Pixel>(image: &Image<P>, kernel: &Kernel, border: AddressMode) -> Image<P> {
+    Planes::load(image).convolve(kernel, border).store()
+}
+
+// Convolve with a separable kernel, the rows then the columns, both kernels must have an odd length.
+pub fn convolve_separable<P: Pixel>(image: &Image<P>, horizontal: &[f32], vertical: &[f32], border: AddressMode) -> Image<P> {
+    assert!(horizontal.len() % 2 == 1 && vertical.len() % 2 == 1, "separable kernels must have an odd length");
+    Planes::load(image).convolve_separable(horizontal, vertical, border).store()
+}
+
+pub fn gaussian_blur<P: Pixel>(image: &Image<P>, sigma: f32, border: AddressMode) -> Image<P> {
+    let weights = gaussian_weights_within(sigma, image.width().max(image.height()));
+    convolve_separable(image, &weights, &weights, border)
+}
+
+// Average over a (2 * radius + 1) square.
+pub fn box_blur<P: Pixel>(image: &Image<P>, radius: usize, border: AddressMode) -> Image<P> {
+    let weights = vec![1.0 / (2 * radius + 1) as f32; 2 * radius + 1];
+    convolve_separable(image, &weights, &weights, border)
+}
+
+// Sharpen by adding back the difference with a gaussian blur: image + amount * (image - blur).
+pub fn unsharp_mask<P: Pixel>(image: &Image<P>, sigma: f32, amount: f32, border: AddressMode) -> Image<P> {
+    let planes = Planes::load(image);
+    let weights = gaussian_weights_within(sigma, image.width().max(image.height()));
+    let blurred = planes.convolve_separable(&weights, &weights, border);
+    let data = planes.data.iter().zip(blurred.data.iter())
+        .map(|(v, b)| [0, 1, 2, 3].map(|c| v[c] + amount * (v[c] - b[c])))
+        .collect();
+    Planes { data, ..planes }.store()
+}
+
+// Derivatives of each channel along x and y, straight(non-premultiplied) colors are derived.
+pub fn gradients<P: Pixel>(image: &Image<P>, operator: GradientOperator, border: AddressMode) -> (Image<Rgba32F>, Image<Rgba32F>) {
+    let planes = Planes { data: image.pixels().iter().map(|p| p.to_rgba()).collect(), width: image.width(), height: image.height() };
+    let (smoothing, derivative) = operator.factors();
+    let dx = planes.convolve_separable(&derivative, &smoothing, border);
+    let dy = planes.convolve_separable(&smoothing, &derivative, border);
+    let to_image = |planes: Planes| Image::from_vec(planes.width, planes.height, planes.data.into_iter().map(Rgba32F).collect()).unwrap();
+    (to_image(dx), to_image(dy))
+}
+
+fn luminance_of(p: &Rgba32F) -> f32 {
+    luminance(p.0[0], p.0[1], p.0[2])
+}
+
+// Gradient magnitude of the luminance as an opaque gray image, for edge detection.
+pub fn gradient_magnitude<P: Pixel>(image: &Image<P>, operator: GradientOperator, border: AddressMode) -> Image<P> {
+    let (dx, dy) = gradients(image, operator, border);
+    let pixels = dx.pixels().iter().zip(dy.pixels().iter()).map(|(dx, dy)| {
+        let v = luminance_of(dx).hypot(luminance_of(dy));
+        P::from_rgba([v, v, v, 1.0])
+    }).collect();
+    Image::from_vec(image.width(), image.height(), pixels).expect("Pixel count must match the dimensions")
+}
+
+// Median of each channel over a (2 * radius + 1) square, removes salt-and-pepper noise while keeping the edges.
+pub fn median<P: Pixel>(image: &Image<P>, radius: usize, border: AddressMode) -> Image<P> {
+
+    let planes = Planes { data: image.pixels().iter().map(|p| p.to_rgba()).collect(), width: image.width(), height: image.height() };
+    let radius = radius as i64;
+    let mut window: [Vec<f32>; 4] = Default::default();
+    let pixels = (0..image.height() as i64).flat_map(|y| (0..image.width() as i64).map(move |x| (x, y))).map(|(x, y)| {
+        window.iter_mut().for_each(|w| w.clear());
+        for j in (y - radius)..=(y + radius) {
+            for i in (x - radius)..=(x + radius) {
+                // ClampToBorder pixels are left out instead of counting as black
+                if let Some((i, j)) = border.resolve(i, planes.width as i64).zip(border.resolve(j, planes.height as i64)) {
+                    let v = planes.data[i as usize + j as usize * planes.width];
+                    window.iter_mut().zip(v.iter()).for_each(|(w, &v)| w.push(v));
+                }
+            }
+        }
+        P::from_rgba([0, 1, 2, 3].map(|c| {
+            let w = &mut window[c];
+            let middle = w.len() / 2;
+            *w.select_nth_unstable_by(middle, f32::total_cmp).1
+        }))
+    }).collect();
+    Image::from_vec(image.width(), image.height(), pixels).expect("Pixel count must match the dimensions")
+}
+
+
+// Spread the luma histogram over the whole range. Colors keep their chroma(YCbCr), alpha is unchanged.
+pub fn equalize_histogram(image: &TgaImage) -> TgaImage {
+
+    let luma = |p: &[u8]| if p.len() == 1 { p[0] as f32 } else { luminance(p[0] as f32, p[1] as f32, p[2] as f32) };
+    let bytes_per_pixel = image.format() as usize;
+
+    let mut histogram = [0_usize; 256];
+    for pixel in image.raw_data().chunks_exact(bytes_per_pixel) {
+        histogram[(luma(pixel) + 0.5) as usize] += 1;
+    }
+
+    // map the cumulative distribution to [0, 255], the darkest level present goes to 0
+    let total = image.raw_data().len() / bytes_per_pixel;
+    let first = histogram.iter().copied().find(|&n| n > 0).unwrap_or(0);
+    let mut lut = [0.0_f32; 256];
+    let mut cumulative = 0;
+    for (v, &n) in histogram.iter().enumerate() {
+        cumulative += n;
+        lut[v] = if total > first { cumulative.saturating_sub(first) as f32 / (total - first) as f32 * 255.0 } else { v as f32 };
+    }
+
+    let mut result = image.clone();
+    for pixel in result.pixels_mut().as_raw_mut().chunks_exact_mut(bytes_per_pixel) {
+        let y = luma(pixel);
+        let mapped = lut[(y + 0.5) as usize];
+        if bytes_per_pixel == 1 {
+            pixel[0] = (mapped + 0.5) as u8;
+        } else {
+            // shift the luma, keeping Cb and Cr
+            let delta = mapped - y;
+            for v in pixel[..3].iter_mut() {
+                *v = (*v as f32 + delta + 0.5).clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    result
+}
+
+
+// The filters applied to the typed pixels of a TgaImage, the format is kept.
+impl TgaImage {
+
+    pub fn convolve(&self, kernel: &Kernel, border: AddressMode) -> TgaImage {
+        TgaImage::from_pixels(map_pixels!(self.pixels(), image => convolve(image, kernel, border)))
+    }
+
+    pub fn gaussian_blur(&self, sigma: f32, border: AddressMode) -> TgaImage {
+        TgaImage::from_pixels(map_pixels!(self.pixels(), image => gaussian_blur(image, sigma, border)))
+    }
+
+    pub fn box_blur(&self, radius: usize, border: AddressMode) -> TgaImage {
+        TgaImage::from_pixels(map_pixels!(self.pixels(), image => box_blur(image, radius, border)))
+    }
+
+    pub fn unsharp_mask(&self, sigma: f32, amount: f32, border: AddressMode) -> TgaImage {
+        TgaImage::from_pixels(map_pixels!(self.pixels(), image => unsharp_mask(image, sigma, amount, border)))
+    }
+
+    pub fn median(&self, radius: usize, border: AddressMode) -> TgaImage {
+        TgaImage::from_pixels(map_pixels!(self.pixels(), image => median(image, radius, border)))
+    }
+
+    // Grayscale edge image of the luminance, gradient magnitudes are scaled by 2 so that a black to white step is white.
+    pub fn edges(&self, operator: GradientOperator, border: AddressMode) -> TgaImage {
+        let (dx, dy) = match self.pixels() {
+            | TgaPixels::Grayscale(image) => gradients(image, operator, border),
+            | TgaPixels::RGB(image)  => gradients(image, operator, border),
+            | TgaPixels::RGBA(image) => gradients(image, operator, border),
+        };
+        let edges = Image::from_fn(dx.width(), dx.height(), |x, y| {
+            let v = luminance_of(&dx[(x, y)]).hypot(luminance_of(&dy[(x, y)])) * 2.0;
+            Luma8([(v * 255.0 + 0.5).clamp(0.0, 255.0) as u8])
+        });
+        TgaImage::from(edges)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Rgba8;
+    use crate::tga::TgaFormat;
+
+    const BORDERS: [AddressMode; 4] = [AddressMode::Repeat, AddressMode::MirroredRepeat, AddressMode::ClampToEdge, AddressMode::ClampToBorder];
+
+    fn noise(width: usize, height: usize) -> Image<Rgba8> {
+        let mut state = 7_u32;
+        Image::from_fn(width, height, |_, _| {
+            let mut next = || {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            };
+            Rgba8([next(), next(), next(), next()])
+        })
+    }
+
+    #[test]
+    fn identity_leaves_the_image_unchanged() {
+        let image = noise(5, 4);
+        for &border in BORDERS.iter() {
+            assert_eq!(convolve(&image, &Kernel::identity(), border), image);
+            assert_eq!(gaussian_blur(&image, 0.0, border), image);
+            assert_eq!(box_blur(&image, 0, border), image);
+        }
+    }
+
+    #[test]
+    fn blur_weights_sum_to_one() {
+        for &sigma in &[0.3, 1.0, 2.5, 10.0, 1e30, f32::INFINITY] {
+            let weights = gaussian_weights(sigma);
+            assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-4, "sigma = {}", sigma);
+            assert_eq!(weights.len() % 2, 1);
+            assert!(weights.len() <= 2 * MAX_GAUSSIAN_RADIUS + 1);
+        }
+        assert_eq!(gaussian_weights(f32::NAN), [1.0]);
+
+        // a constant image stays constant, whatever the width of the kernel
+        let constant = Image::from_pixel(6, 5, Rgba8([10, 100, 200, 255]));
+        for &border in &[AddressMode::Repeat, AddressMode::MirroredRepeat, AddressMode::ClampToEdge] {
+            assert_eq!(gaussian_blur(&constant, 1.5, border), constant);
+            assert_eq!(gaussian_blur(&constant, f32::INFINITY, border), constant);
+            assert_eq!(box_blur(&constant, 2, border), constant);
+            assert_eq!(unsharp_mask(&constant, 1e30, 1.0, border), constant);
+        }
+    }
+
+    #[test]
+    fn derivative_of_a_unit_step_is_one_half() {
+        let step = Image::from_fn(6, 3, |x, _| Rgba32F(if x < 3 { [0.0, 0.0, 0.0, 1.0] } else { [1.0, 1.0, 1.0, 1.0] }));
+        for &operator in &[GradientOperator::Sobel, GradientOperator::Scharr] {
+            let (dx, dy) = gradients(&step, operator, AddressMode::ClampToEdge);
+            for y in 0..3 {
+                for x in 0..6 {
+                    let expected = if x == 2 || x == 3 { 0.5 } else { 0.0 };
+                    assert!((dx[(x, y)].0[0] - expected).abs() < 1e-6, "{:?} at ({}, {})", operator, x, y);
+                    assert!(dy[(x, y)].0[0].abs() < 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn median_removes_an_outlier() {
+        let mut image = Image::from_pixel(5, 5, Luma8([100]));
+        image[(2, 2)] = Luma8([255]);
+        image[(0, 4)] = Luma8([0]);
+        for &border in BORDERS.iter() {
+            assert_eq!(median(&image, 1, border), Image::from_pixel(5, 5, Luma8([100])), "{:?}", border);
+        }
+    }
+
+    #[test]
+    fn equalization_spreads_luma_to_the_extremes() {
+        let mut gray = TgaImage::new(101, 1, TgaFormat::Grayscale);
+        let mut color = TgaImage::new(101, 1, TgaFormat::RGB);
+        for x in 0..101 {
+            gray.set(x, 0, &crate::tga::TgaColor::from_greyscale(50 + x as u8));
+            color.set(x, 0, &crate::tga::TgaColor::from_rgb(50 + x as u8, 50 + x as u8, 50 + x as u8));
+        }
+        for image in &[gray, color] {
+            let equalized = equalize_histogram(image);
+            let lumas: Vec<u8> = (0..101).map(|x| equalized.try_get(x, 0).unwrap().luma()).collect();
+            assert_eq!((lumas[0], lumas[100]), (0, 255));
+            assert!(lumas.windows(2).all(|w| w[0] <= w[1]));
+        }
+    }
+}
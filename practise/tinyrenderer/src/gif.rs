@@ -0,0 +1,690 @@
+//!
+//! Animated GIF encoding of frame sequences, see https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+//! Every frame gets its own palette(local color table) of at most 256 colors, built by median cut or an octree,
+//! optionally with Floyd-Steinberg dithering. Pixels with alpha below 128 become transparent.
+//!
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::fs::File;
+use std::io::Write;
+
+use crate::tga::{TgaImage, TgaFormat};
+
+
+#[derive(Debug)]
+pub enum GifError {
+    BadDimensions { width: i32, height: i32 },
+    FrameSize { expected: (i32, i32), found: (i32, i32) },
+    NoFrames,
+    Io(std::io::Error),
+}
+
+pub type GifResult<T> = Result<T, GifError>;
+
+impl std::fmt::Display for GifError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            | GifError::BadDimensions { width, height } => write!(f, "gif dimensions must be in 1..=65535, got {}x{}", width, height),
+            | GifError::FrameSize { expected, found } => write!(f, "frame is {}x{}, the animation is {}x{}", found.0, found.1, expected.0, expected.1),
+            | GifError::NoFrames => write!(f, "a gif needs at least one frame"),
+            | GifError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for GifError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            | GifError::Io(e) => Some(e),
+            | _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for GifError {
+    fn from(e: std::io::Error) -> GifError {
+        GifError::Io(e)
+    }
+}
+
+impl From<GifError> for std::io::Error {
+    fn from(e: GifError) -> std::io::Error {
+        match e {
+            | GifError::Io(e) => e,
+            | _ => std::io::Error::new(std::io::ErrorKind::InvalidInput, e),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantizer {
+    MedianCut, // split the box of colors with the widest channel range at its median, until there are enough boxes
+    Octree,    // merge the least used leaves of an octree over the color bits, until there are few enough
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dithering {
+    None,
+    FloydSteinberg, // spread the error of each pixel over its unvisited neighbours
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Once,       // no looping extension, most viewers play the animation once
+    Times(u16), // play again this many times after the first
+    Forever,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GifOptions {
+    pub quantizer: Quantizer,
+    pub dithering: Dithering,
+    pub colors: usize, // palette size of each frame, in 2..=256
+    pub delay: u16,    // default delay of the frames, in hundredths of a second
+    pub repeat: Repeat,
+}
+
+impl Default for GifOptions {
+
+    fn default() -> GifOptions {
+        GifOptions { quantizer: Quantizer::MedianCut, dithering: Dithering::None, colors: 256, delay: 4, repeat: Repeat::Forever }
+    }
+}
+
+
+// Streams frames into a gif, the trailer is written by `finish`.
+pub struct GifEncoder<W: Write> {
+    writer: W,
+    width : i32,
+    height: i32,
+    options: GifOptions,
+}
+
+impl<W: Write> GifEncoder<W> {
+
+    pub fn new(mut writer: W, width: i32, height: i32, options: GifOptions) -> GifResult<GifEncoder<W>> {
+
+        if !(1..=0xffff).contains(&width) || !(1..=0xffff).contains(&height) {
+            return Err(GifError::BadDimensions { width, height })
+        }
+
+        let mut header = b"GIF89a".to_vec();
+        // logical screen descriptor, without a global color table: every frame has its own
+        header.extend_from_slice(&(width as u16).to_le_bytes());
+        header.extend_from_slice(&(height as u16).to_le_bytes());
+        header.extend_from_slice(&[0x00, 0, 0]);
+
+        let loop_count = match options.repeat {
+            | Repeat::Once => None,
+            | Repeat::Times(count) => Some(count),
+            | Repeat::Forever => Some(0),
+        };
+        if let Some(count) = loop_count {
+            header.extend_from_slice(&[0x21, 0xff, 11]);
+            header.extend_from_slice(b"NETSCAPE2.0");
+            header.extend_from_slice(&[3, 1]);
+            header.extend_from_slice(&count.to_le_bytes());
+            header.push(0);
+        }
+        writer.write_all(&header)?;
+
+        Ok(GifEncoder { writer, width, height, options })
+    }
+
+    pub fn add_frame(&mut self, image: &TgaImage) -> GifResult<()> {
+        self.add_frame_with_delay(image, self.options.delay)
+    }
+
+    // `delay` is in hundredths of a second, many viewers slow down delays below 2.
+    pub fn add_frame_with_delay(&mut self, image: &TgaImage, delay: u16) -> GifResult<()> {
+
+        if (image.width, image.height) != (self.width, self.height) {
+            return Err(GifError::FrameSize { expected: (self.width, self.height), found: (image.width, image.height) })
+        }
+
+        let pixels = rgba_pixels(image);
+        let has_transparency = pixels.iter().any(|p| p[3] < 128);
+        let colors = self.options.colors.clamp(2, 256) - has_transparency as usize;
+
+        let mut histogram: HashMap<[u8; 3], u32> = HashMap::new();
+        for p in pixels.iter().filter(|p| p[3] >= 128) {
+            *histogram.entry([p[0], p[1], p[2]]).or_insert(0) += 1;
+        }
+        let mut palette = match self.options.quantizer {
+            | Quantizer::MedianCut => median_cut(&histogram, colors),
+            | Quantizer::Octree => octree(&histogram, colors),
+        };
+        let indices = map_to_palette(&pixels, self.width as usize, &palette, self.options.dithering);
+
+        // the transparent color takes the index after the opaque ones
+        let transparent_index = palette.len() as u8;
+        if has_transparency {
+            palette.push([0, 0, 0]);
+        }
+        let indices: Vec<u8> = indices.into_iter().map(|i| i.unwrap_or(transparent_index)).collect();
+
+        // the color table holds 2^bits entries, at least 2 of them
+        let bits = (usize::BITS - (palette.len().max(2) - 1).leading_zeros()) as u8;
+
+        let mut bytes = Vec::new();
+        // graphic control extension: the disposal method restores the background after every frame,
+        // so that the previous frame never shows through the transparent pixels of the next one
+        let disposal: u8 = 2;
+        bytes.extend_from_slice(&[0x21, 0xf9, 4, disposal << 2 | has_transparency as u8]);
+        bytes.extend_from_slice(&delay.to_le_bytes());
+        bytes.extend_from_slice(&[if has_transparency { transparent_index } else { 0 }, 0]);
+
+        // image descriptor covering the whole screen, followed by the local color table
+        bytes.push(0x2c);
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(&(self.width as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.height as u16).to_le_bytes());
+        bytes.push(0x80 | (bits - 1));
+        for i in 0..(1 << bits) {
+            bytes.extend_from_slice(&palette.get(i).copied().unwrap_or([0, 0, 0]));
+        }
+
+        let min_code_size = bits.max(2);
+        bytes.push(min_code_size);
+        for block in lzw_compress(&indices, min_code_size).chunks(255) {
+            bytes.push(block.len() as u8);
+            bytes.extend_from_slice(block);
+        }
+        bytes.push(0);
+
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    // Write the trailer and return the writer.
+    pub fn finish(mut self) -> GifResult<W> {
+        self.writer.write_all(&[0x3b])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// Encode the frames, all of the size of the first one.
+pub fn encode_gif(frames: &[TgaImage], options: &GifOptions) -> GifResult<Vec<u8>> {
+    write_gif(frames, Vec::new(), options)
+}
+
+pub fn write_gif<W: Write>(frames: &[TgaImage], writer: W, options: &GifOptions) -> GifResult<W> {
+    let first = frames.first().ok_or(GifError::NoFrames)?;
+    let mut encoder = GifEncoder::new(writer, first.width, first.height, *options)?;
+    for frame in frames {
+        encoder.add_frame(frame)?;
+    }
+    encoder.finish()
+}
+
+pub fn save_gif(frames: &[TgaImage], path: impl AsRef<Path>, options: &GifOptions) -> GifResult<()> {
+    write_gif(frames, std::io::BufWriter::new(File::create(path)?), options)?;
+    Ok(())
+}
+
+fn rgba_pixels(image: &TgaImage) -> Vec<[u8; 4]> {
+    let data = image.raw_data();
+    match image.format() {
+        | TgaFormat::Grayscale => data.iter().map(|&v| [v, v, v, 255]).collect(),
+        | TgaFormat::RGB  => data.chunks_exact(3).map(|p| [p[0], p[1], p[2], 255]).collect(),
+        | TgaFormat::RGBA => data.chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]]).collect(),
+    }
+}
+
+
+// Count-weighted mean of colors.
+fn mean_color<'a>(colors: impl Iterator<Item = (&'a [u8; 3], &'a u32)>) -> [u8; 3] {
+    let (mut sum, mut count) = ([0_u64; 3], 0_u64);
+    for (color, &n) in colors {
+        for c in 0..3 {
+            sum[c] += color[c] as u64 * n as u64;
+        }
+        count += n as u64;
+    }
+    sum.map(|s| ((s + count / 2) / count.max(1)) as u8)
+}
+
+fn median_cut(histogram: &HashMap<[u8; 3], u32>, colors: usize) -> Vec<[u8; 3]> {
+
+    let mut boxes: Vec<Vec<([u8; 3], u32)>> = vec![histogram.iter().map(|(&color, &n)| (color, n)).collect()];
+    if boxes[0].len() <= colors {
+        return boxes.remove(0).into_iter().map(|(color, _)| color).collect()
+    }
+
+    // (range, channel) of the widest channel of a box
+    let widest = |colors: &[([u8; 3], u32)]| (0..3).map(|c| {
+        let (min, max) = colors.iter().fold((255, 0), |(min, max), (color, _)| (color[c].min(min), color[c].max(max)));
+        (max - min, c)
+    }).max().unwrap_or((0, 0));
+
+    while boxes.len() < colors {
+        let Some((i, (_, channel))) = boxes.iter().enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, widest(b)))
+            .max_by_key(|&(_, (range, _))| range) else {
+            break
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|(color, _)| color[channel]);
+        // split at the weighted median, keeping at least one color on each side
+        let total: u64 = colors.iter().map(|&(_, n)| n as u64).sum();
+        let mut cumulative = 0;
+        let split = colors.iter().position(|&(_, n)| {
+            cumulative += n as u64;
+            cumulative * 2 >= total
+        }).unwrap_or(0).clamp(0, colors.len() - 2) + 1;
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|colors| mean_color(colors.iter().map(|(color, n)| (color, n)))).collect()
+}
+
+#[derive(Default)]
+struct OctreeNode {
+    sum: [u64; 3],
+    count: u64,
+    children: [usize; 8], // 0 if missing, the root is never a child
+    leaf: bool,
+}
+
+fn octree(histogram: &HashMap<[u8; 3], u32>, colors: usize) -> Vec<[u8; 3]> {
+
+    if histogram.len() <= colors {
+        return histogram.keys().copied().collect()
+    }
+
+    let mut nodes = vec![OctreeNode::default()];
+    // the inner nodes at each depth, the candidates for merging
+    let mut levels: Vec<Vec<usize>> = vec![vec![0]; 1];
+    levels.resize(8, Vec::new());
+    let mut leaves = 0;
+
+    for (color, &n) in histogram {
+        let mut node = 0;
+        for depth in 0..8 {
+            let bit = 7 - depth;
+            let child = ((color[0] >> bit & 1) << 2 | (color[1] >> bit & 1) << 1 | (color[2] >> bit & 1)) as usize;
+            if nodes[node].children[child] == 0 {
+                nodes.push(OctreeNode { leaf: depth == 7, ..OctreeNode::default() });
+                nodes[node].children[child] = nodes.len() - 1;
+                if depth == 7 {
+                    leaves += 1;
+                } else {
+                    levels[depth + 1].push(nodes.len() - 1);
+                }
+            }
+            node = nodes[node].children[child];
+        }
+        for (sum, &v) in nodes[node].sum.iter_mut().zip(color) {
+            *sum += v as u64 * n as u64;
+        }
+        nodes[node].count += n as u64;
+    }
+
+    // the total count below every node, to merge the least used ones first
+    fn total(nodes: &[OctreeNode], node: usize) -> u64 {
+        nodes[node].count + nodes[node].children.iter().filter(|&&c| c != 0).map(|&c| total(nodes, c)).sum::<u64>()
+    }
+    for level in levels.iter_mut() {
+        level.sort_by_key(|&node| std::cmp::Reverse(total(&nodes, node)));
+    }
+
+    // merge the children of the deepest nodes into them, their children are all leaves
+    while leaves > colors {
+        let Some(node) = levels.iter_mut().rev().find_map(|level| level.pop()) else {
+            break
+        };
+        let children = std::mem::take(&mut nodes[node].children);
+        for child in children.iter().copied().filter(|&c| c != 0) {
+            for c in 0..3 {
+                nodes[node].sum[c] += nodes[child].sum[c];
+            }
+            nodes[node].count += nodes[child].count;
+            leaves -= 1;
+        }
+        nodes[node].leaf = true;
+        leaves += 1;
+    }
+
+    let mut palette = Vec::with_capacity(leaves);
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        if node.leaf {
+            let count = node.count.max(1);
+            palette.push(node.sum.map(|s| ((s + count / 2) / count) as u8));
+        } else {
+            stack.extend(node.children.iter().filter(|&&c| c != 0));
+        }
+    }
+    palette
+}
+
+// The palette index of each pixel, None for transparent ones.
+fn map_to_palette(pixels: &[[u8; 4]], width: usize, palette: &[[u8; 3]], dithering: Dithering) -> Vec<Option<u8>> {
+
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut nearest = |color: [u8; 3]| *cache.entry(color).or_insert_with(|| {
+        let distance = |p: &[u8; 3]| (0..3).map(|c| (p[c] as i32 - color[c] as i32).pow(2)).sum::<i32>();
+        (0..palette.len()).min_by_key(|&i| distance(&palette[i])).unwrap_or(0) as u8
+    });
+
+    if palette.is_empty() {
+        return vec![None; pixels.len()]
+    }
+
+    match dithering {
+        | Dithering::None => pixels.iter()
+            .map(|p| if p[3] < 128 { None } else { Some(nearest([p[0], p[1], p[2]])) })
+            .collect(),
+        | Dithering::FloydSteinberg => {
+            // accumulated error of the current and the next row
+            let mut errors = vec![[0.0_f32; 3]; width + 2];
+            let mut next_errors = vec![[0.0_f32; 3]; width + 2];
+            let mut indices = Vec::with_capacity(pixels.len());
+            for row in pixels.chunks_exact(width) {
+                for (x, p) in row.iter().enumerate() {
+                    if p[3] < 128 {
+                        indices.push(None);
+                        continue
+                    }
+                    // the errors are shifted by one, so that x - 1 exists
+                    let wanted = [0, 1, 2].map(|c| (p[c] as f32 + errors[x + 1][c]).clamp(0.0, 255.0));
+                    let index = nearest(wanted.map(|v| v.round() as u8));
+                    let chosen = palette[index as usize];
+                    for c in 0..3 {
+                        let error = wanted[c] - chosen[c] as f32;
+                        errors[x + 2][c] += error * 7.0 / 16.0;
+                        next_errors[x][c] += error * 3.0 / 16.0;
+                        next_errors[x + 1][c] += error * 5.0 / 16.0;
+                        next_errors[x + 2][c] += error * 1.0 / 16.0;
+                    }
+                    indices.push(Some(index));
+                }
+                std::mem::swap(&mut errors, &mut next_errors);
+                next_errors.iter_mut().for_each(|e| *e = [0.0; 3]);
+            }
+            indices
+        },
+    }
+}
+
+
+// Variable length LZW as used by gif: codes are packed from the least significant bit, start one bit wider
+// than the minimum code size and grow up to 12 bits. A clear code resets the dictionary once it is full.
+pub fn lzw_compress(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+
+    const MAX_CODES: u16 = 1 << 12;
+    let clear = 1_u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut output = Vec::new();
+    let (mut buffer, mut buffered) = (0_u32, 0_u32);
+    let mut emit = |code: u16, width: u8, output: &mut Vec<u8>| {
+        buffer |= (code as u32) << buffered;
+        buffered += width as u32;
+        while buffered >= 8 {
+            output.push(buffer as u8);
+            buffer >>= 8;
+            buffered -= 8;
+        }
+    };
+
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut width = min_code_size + 1;
+    emit(clear, width, &mut output);
+
+    let mut symbols = indices.iter().copied();
+    if let Some(first) = symbols.next() {
+        let mut prefix = first as u16;
+        for symbol in symbols {
+            if let Some(&code) = dictionary.get(&(prefix, symbol)) {
+                prefix = code;
+                continue
+            }
+            emit(prefix, width, &mut output);
+            if next_code < MAX_CODES {
+                dictionary.insert((prefix, symbol), next_code);
+                next_code += 1;
+                // the decoder adds its entry one code later, so it widens when this one passes the limit
+                if next_code > 1 << width && width < 12 {
+                    width += 1;
+                }
+            } else {
+                emit(clear, width, &mut output);
+                dictionary.clear();
+                next_code = end + 1;
+                width = min_code_size + 1;
+            }
+            prefix = symbol as u16;
+        }
+        emit(prefix, width, &mut output);
+        // the decoder adds an entry for the last code too, which may widen the end code
+        if next_code < MAX_CODES && next_code == 1 << width && width < 12 {
+            width += 1;
+        }
+    }
+    emit(end, width, &mut output);
+    emit(0, 7, &mut output); // flush the last partial byte
+    output
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tga::TgaColor;
+
+    // The symbols of a gif LZW stream, and how many clear codes it holds.
+    fn lzw_decompress(bytes: &[u8], min_code_size: u8) -> (Vec<u8>, usize) {
+        let clear = 1_usize << min_code_size;
+        let end = clear + 1;
+        let initial_table = || (0..=end).map(|code| vec![code as u8]).collect::<Vec<_>>();
+
+        let mut table = initial_table();
+        let mut width = min_code_size + 1;
+        let (mut buffer, mut buffered, mut position) = (0_u32, 0_u32, 0);
+        let mut previous: Option<Vec<u8>> = None;
+        let (mut output, mut clears) = (Vec::new(), 0);
+        loop {
+            while buffered < width as u32 {
+                buffer |= (bytes[position] as u32) << buffered;
+                position += 1;
+                buffered += 8;
+            }
+            let code = (buffer & ((1 << width) - 1)) as usize;
+            buffer >>= width;
+            buffered -= width as u32;
+
+            if code == clear {
+                table = initial_table();
+                width = min_code_size + 1;
+                previous = None;
+                clears += 1;
+                continue
+            }
+            if code == end {
+                break
+            }
+            let entry = match (table.get(code), &previous) {
+                | (Some(entry), _) => entry.clone(),
+                | (None, Some(previous)) if code == table.len() => [&previous[..], &previous[..1]].concat(),
+                | _ => panic!("code {} is not in the table of {} entries", code, table.len()),
+            };
+            if let Some(previous) = previous.take() {
+                if table.len() < 4096 {
+                    table.push([&previous[..], &entry[..1]].concat());
+                }
+            }
+            if table.len() == 1 << width && width < 12 {
+                width += 1;
+            }
+            output.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+        assert_eq!(position, bytes.len(), "the stream has bytes after the end code");
+        (output, clears)
+    }
+
+    fn noise(count: usize, symbols: u32) -> Vec<u8> {
+        let mut state = 12345_u32;
+        (0..count).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            ((state >> 16) % symbols) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        let inputs = [
+            (Vec::new(), 2),
+            (vec![1], 2),
+            (vec![0, 1, 0, 1, 0, 1, 0, 1, 1, 1, 1, 1, 1], 2),
+            (vec![0; 10000], 2),
+            (noise(300, 4), 2),
+            (noise(20000, 4), 2),
+            (noise(20000, 16), 4),
+            (noise(20000, 256), 8),
+        ];
+        for (indices, min_code_size) in inputs.iter() {
+            let (output, clears) = lzw_decompress(&lzw_compress(indices, *min_code_size), *min_code_size);
+            assert_eq!(&output, indices);
+            // long noise fills the dictionary, which is cleared at 4096 codes
+            if indices.len() == 20000 {
+                assert!(clears > 1, "{} clears for {} bit symbols", clears, min_code_size);
+            }
+        }
+    }
+
+    struct Frame {
+        flags: u8,
+        transparent_index: u8,
+        palette: Vec<[u8; 3]>,
+        indices: Vec<u8>,
+    }
+
+    // The loop count and the frames of an encoded gif, checking the header and the trailer.
+    fn parse(bytes: &[u8], width: u16, height: u16) -> (Option<u16>, Vec<Frame>) {
+        assert_eq!(&bytes[..6], b"GIF89a");
+        assert_eq!(&bytes[6..13], &[width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8, 0, 0, 0]);
+
+        let sub_blocks = |mut position: usize| {
+            let mut data = Vec::new();
+            while bytes[position] != 0 {
+                let length = bytes[position] as usize;
+                data.extend_from_slice(&bytes[(position + 1)..(position + 1 + length)]);
+                position += 1 + length;
+            }
+            (data, position + 1)
+        };
+
+        let (mut loop_count, mut frames, mut control) = (None, Vec::new(), None);
+        let mut position = 13;
+        loop {
+            match bytes[position] {
+                | 0x21 if bytes[position + 1] == 0xff => {
+                    assert_eq!(&bytes[(position + 2)..(position + 14)], b"\x0bNETSCAPE2.0");
+                    let (data, next) = sub_blocks(position + 14);
+                    assert_eq!(data.len(), 3);
+                    assert_eq!(data[0], 1);
+                    loop_count = Some(u16::from_le_bytes([data[1], data[2]]));
+                    position = next;
+                },
+                | 0x21 if bytes[position + 1] == 0xf9 => {
+                    assert_eq!(bytes[position + 2], 4);
+                    assert_eq!(bytes[position + 7], 0);
+                    control = Some((bytes[position + 3], bytes[position + 6]));
+                    position += 8;
+                },
+                | 0x2c => {
+                    assert_eq!(&bytes[(position + 1)..(position + 9)], &[0, 0, 0, 0, width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8]);
+                    let table = bytes[position + 9];
+                    assert_eq!(table & 0x80, 0x80, "frames have a local color table");
+                    let size = 1 << ((table & 7) + 1);
+                    let palette = bytes[(position + 10)..(position + 10 + 3 * size)].chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+                    position += 10 + 3 * size;
+                    let min_code_size = bytes[position];
+                    let (data, next) = sub_blocks(position + 1);
+                    let (indices, _) = lzw_decompress(&data, min_code_size);
+                    let (flags, transparent_index) = control.take().expect("frames have a graphic control extension");
+                    frames.push(Frame { flags, transparent_index, palette, indices });
+                    position = next;
+                },
+                | 0x3b => {
+                    assert_eq!(position, bytes.len() - 1, "the trailer is the last byte");
+                    return (loop_count, frames)
+                },
+                | byte => panic!("unexpected block {:#x}", byte),
+            }
+        }
+    }
+
+    fn gradient(width: i32, height: i32) -> TgaImage {
+        let mut image = TgaImage::new(width, height, TgaFormat::RGBA);
+        for y in 0..height {
+            for x in 0..width {
+                image.set(x, y, &TgaColor::from_rgba((x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8, 255));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn writes_header_loop_and_trailer() {
+        let frames = [gradient(3, 2), gradient(3, 2)];
+        for &(repeat, expected) in &[(Repeat::Forever, Some(0)), (Repeat::Times(3), Some(3)), (Repeat::Once, None)] {
+            let bytes = encode_gif(&frames, &GifOptions { repeat, ..GifOptions::default() }).unwrap();
+            let (loop_count, parsed) = parse(&bytes, 3, 2);
+            assert_eq!(loop_count, expected);
+            assert_eq!(parsed.len(), 2);
+        }
+        assert!(matches!(encode_gif(&[], &GifOptions::default()), Err(GifError::NoFrames)));
+        assert!(matches!(encode_gif(&[gradient(3, 2), gradient(2, 3)], &GifOptions::default()), Err(GifError::FrameSize { .. })));
+    }
+
+    #[test]
+    fn frames_dispose_to_the_background() {
+        let mut transparent = gradient(4, 4);
+        transparent.set(1, 1, &TgaColor::from_rgba(0, 0, 0, 0));
+        let bytes = encode_gif(&[gradient(4, 4), transparent], &GifOptions::default()).unwrap();
+        let (_, frames) = parse(&bytes, 4, 4);
+
+        assert_eq!(frames[0].flags, 2 << 2);
+        assert_eq!(frames[1].flags, 2 << 2 | 1);
+        assert_eq!(frames[1].indices[5], frames[1].transparent_index);
+        assert_eq!(frames[1].indices.iter().filter(|&&i| i == frames[1].transparent_index).count(), 1);
+    }
+
+    #[test]
+    fn palettes_fit_the_color_count() {
+        let mut image = gradient(64, 64);
+        image.set(0, 0, &TgaColor::from_rgba(0, 0, 0, 0));
+        for &quantizer in &[Quantizer::MedianCut, Quantizer::Octree] {
+            for &dithering in &[Dithering::None, Dithering::FloydSteinberg] {
+                for &colors in &[2, 5, 16, 256] {
+                    let options = GifOptions { quantizer, dithering, colors, ..GifOptions::default() };
+                    let (_, frames) = parse(&encode_gif(&[image.clone()], &options).unwrap(), 64, 64);
+                    let used = frames[0].indices.iter().copied().max().unwrap() as usize + 1;
+                    assert!(used <= colors, "{:?} uses {} of {} colors", quantizer, used, colors);
+                    assert!(frames[0].palette.len() <= colors.next_power_of_two());
+                    assert_eq!(frames[0].indices.len(), 64 * 64);
+                }
+            }
+        }
+
+        let histogram: HashMap<[u8; 3], u32> = (0..=255).map(|v| ([v, 255 - v, v / 2], 1 + v as u32)).collect();
+        for &colors in &[1, 2, 7, 64, 255, 300] {
+            assert!(median_cut(&histogram, colors).len() <= colors.max(1));
+            assert!(octree(&histogram, colors).len() <= colors.max(1));
+        }
+    }
+}
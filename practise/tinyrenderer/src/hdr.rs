@@ -0,0 +1,280 @@
+//!
+//! High-dynamic-range framebuffer: colors are stored as linear f32 RGBA without clamping,
+//! and tone mapped and sRGB(or gamma) encoded when resolving to a TgaImage.
+//!
+
+use crate::tga::{TgaImage, TgaFormat};
+use crate::rasterization::RenderTarget;
+use crate::image::{Image, Rgba32F, luminance};
+use crate::netpbm::PfmImage;
+use crate::color::{Color, srgb_to_linear, linear_to_srgb};
+
+pub type Rgbaf = vek::Rgba<f32>;
+
+
+// Operator compressing linear radiance in [0, inf) to [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapping {
+    Clamp,                           // values above 1 clip
+    Reinhard,                        // x / (1 + x)
+    ReinhardExtended { white: f32 }, // Reinhard which maps `white` to 1
+    AcesFilmic,                      // Krzysztof Narkowicz's fit of the ACES filmic curve
+    Exposure,                        // 1 - exp(-x)
+}
+
+impl ToneMapping {
+
+    pub fn apply(&self, x: f32) -> f32 {
+        let x = x.max(0.0);
+        let v = match *self {
+            | ToneMapping::Clamp => x,
+            | ToneMapping::Reinhard => x / (1.0 + x),
+            | ToneMapping::ReinhardExtended { white } if white > 0.0 => x * (1.0 + x / (white * white)) / (1.0 + x),
+            // a white point which is not positive (or NaN) has no meaning, so it is plain Reinhard then
+            | ToneMapping::ReinhardExtended { .. } => x / (1.0 + x),
+            | ToneMapping::AcesFilmic => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            | ToneMapping::Exposure => 1.0 - (-x).exp(),
+        };
+        v.clamp(0.0, 1.0)
+    }
+}
+
+
+// Encoding of 8-bit color values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+    Linear,
+    Gamma(f32), // the encoded value is v^(1 / gamma)
+    Srgb,       // the sRGB curve, what images and displays usually expect
+}
+
+impl Transfer {
+
+    pub fn decode(&self, v: u8) -> f32 {
+        match *self {
+            | Transfer::Linear => v as f32 / 255.0,
+            | Transfer::Gamma(gamma) => decode_gamma(v, gamma),
+            | Transfer::Srgb => srgb_to_linear(v),
+        }
+    }
+
+    // Values outside of [0, 1] are clamped.
+    pub fn encode(&self, v: f32) -> u8 {
+        match *self {
+            | Transfer::Linear => (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8,
+            | Transfer::Gamma(gamma) => encode_gamma(v, gamma),
+            | Transfer::Srgb => linear_to_srgb(v),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct ResolveOptions {
+    pub exposure: f32, // in stops, the colors are scaled by 2^exposure before tone mapping
+    pub tone_mapping: ToneMapping,
+    pub transfer: Transfer,
+}
+
+impl Default for ResolveOptions {
+
+    fn default() -> ResolveOptions {
+        ResolveOptions { exposure: 0.0, tone_mapping: ToneMapping::AcesFilmic, transfer: Transfer::Srgb }
+    }
+}
+
+// Convert a 8-bit gamma encoded value to linear.
+pub fn decode_gamma(v: u8, gamma: f32) -> f32 {
+    (v as f32 / 255.0).powf(gamma)
+}
+
+// Convert a linear value in [0, 1] to 8-bit with gamma encoding.
+pub fn encode_gamma(v: f32, gamma: f32) -> u8 {
+    (v.clamp(0.0, 1.0).powf(1.0 / gamma) * 255.0 + 0.5) as u8
+}
+
+
+#[derive(Debug, Clone)]
+pub struct HdrImage {
+    data: Vec<Rgbaf>,
+    pub width : i32,
+    pub height: i32,
+}
+
+impl HdrImage {
+
+    pub fn new(width: i32, height: i32) -> HdrImage {
+        HdrImage::filled(width, height, Rgbaf::new(0.0, 0.0, 0.0, 1.0))
+    }
+
+    pub fn filled(width: i32, height: i32, color: Rgbaf) -> HdrImage {
+        // negative dimensions give an empty image, like `TgaImage::new`
+        let (width, height) = (width.max(0), height.max(0));
+        HdrImage { data: vec![color; width as usize * height as usize], width, height }
+    }
+
+    // Decode the pixels of a TgaImage to linear colors.
+    pub fn from_tga(image: &TgaImage, transfer: Transfer) -> HdrImage {
+
+        let bytes_per_pixel = image.format() as usize;
+        let data = image.raw_data().chunks_exact(bytes_per_pixel).map(|pixel| {
+            match image.format() {
+                | TgaFormat::Grayscale => {
+                    let v = transfer.decode(pixel[0]);
+                    Rgbaf::new(v, v, v, 1.0)
+                },
+                | TgaFormat::RGB  => Rgbaf::new(transfer.decode(pixel[0]), transfer.decode(pixel[1]), transfer.decode(pixel[2]), 1.0),
+                | TgaFormat::RGBA => Rgbaf::new(transfer.decode(pixel[0]), transfer.decode(pixel[1]), transfer.decode(pixel[2]), pixel[3] as f32 / 255.0),
+            }
+        }).collect();
+
+        HdrImage { data, width: image.width, height: image.height }
+    }
+
+    pub fn pixels(&self) -> &[Rgbaf] {
+        &self.data
+    }
+
+    // Copy to a typed float image, e.g. to run the `filter` functions for post effects.
+    pub fn to_image(&self) -> Image<Rgba32F> {
+        let pixels = self.data.iter().map(|c| Rgba32F([c.r, c.g, c.b, c.a])).collect();
+        Image::from_vec(self.width as usize, self.height as usize, pixels).expect("Pixel count must match the dimensions")
+    }
+
+    // Alpha is dropped, PFM files have no alpha channel.
+    pub fn to_pfm(&self) -> PfmImage {
+        PfmImage::from(self)
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<Rgbaf> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            None
+        } else {
+            Some(self.data[(x + y * self.width) as usize])
+        }
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, color: Rgbaf) {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            self.data[(x + y * self.width) as usize] = color;
+        }
+    }
+
+    pub fn flip_vertically(&mut self) {
+        let width = self.width as usize;
+        let half = self.height as usize / 2;
+        for j in 0..half {
+            let k = self.height as usize - 1 - j;
+            let (top, bottom) = self.data.split_at_mut(k * width);
+            top[(j * width)..((j + 1) * width)].swap_with_slice(&mut bottom[..width]);
+        }
+    }
+
+    // Largest luminance(Rec.709) of the image, which may be used as the white point of ReinhardExtended.
+    pub fn max_luminance(&self) -> f32 {
+        self.data.iter().map(|c| luminance(c.r, c.g, c.b)).fold(0.0, f32::max)
+    }
+
+    // Tone map and encode the image to 8-bit, alpha is kept linear.
+    pub fn resolve(&self, options: &ResolveOptions, format: TgaFormat) -> TgaImage {
+
+        let scale = 2.0_f32.powf(options.exposure);
+        let mut data = Vec::with_capacity(self.data.len() * format as usize);
+        for color in self.data.iter() {
+            let map = |v: f32| options.transfer.encode(options.tone_mapping.apply(v * scale));
+            match format {
+                | TgaFormat::Grayscale => data.push(map(luminance(color.r, color.g, color.b))),
+                | TgaFormat::RGB  => data.extend_from_slice(&[map(color.r), map(color.g), map(color.b)]),
+                | TgaFormat::RGBA => data.extend_from_slice(&[map(color.r), map(color.g), map(color.b), (color.a.clamp(0.0, 1.0) * 255.0 + 0.5) as u8]),
+            }
+        }
+
+        TgaImage::from_raw_data(self.width, self.height, format, data).expect("Pixel count must match the dimensions")
+    }
+}
+
+impl From<Image<Rgba32F>> for HdrImage {
+    fn from(image: Image<Rgba32F>) -> HdrImage {
+        let (width, height) = (image.width() as i32, image.height() as i32);
+        let data = image.into_vec().into_iter().map(|p| Rgbaf::new(p.0[0], p.0[1], p.0[2], p.0[3])).collect();
+        HdrImage { data, width, height }
+    }
+}
+
+impl RenderTarget<Rgbaf> for HdrImage {
+
+    fn width(&self) -> i32 { self.width }
+    fn height(&self) -> i32 { self.height }
+    fn set(&mut self, x: i32, y: i32, color: &Rgbaf) { HdrImage::set(self, x, y, *color) }
+    unsafe fn set_unchecked(&mut self, x: i32, y: i32, color: &Rgbaf) { *self.data.get_unchecked_mut((x + y * self.width) as usize) = *color }
+}
+
+impl RenderTarget<Color> for HdrImage {
+
+    fn width(&self) -> i32 { self.width }
+    fn height(&self) -> i32 { self.height }
+    fn set(&mut self, x: i32, y: i32, color: &Color) { HdrImage::set(self, x, y, Rgbaf::from(*color)) }
+    unsafe fn set_unchecked(&mut self, x: i32, y: i32, color: &Color) { *self.data.get_unchecked_mut((x + y * self.width) as usize) = Rgbaf::from(*color) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapping; 5] = [
+        ToneMapping::Clamp,
+        ToneMapping::Reinhard,
+        ToneMapping::ReinhardExtended { white: 4.0 },
+        ToneMapping::AcesFilmic,
+        ToneMapping::Exposure,
+    ];
+
+    #[test]
+    fn maps_black_to_black() {
+        for operator in OPERATORS.iter() {
+            assert_eq!(operator.apply(0.0), 0.0, "{:?}", operator);
+            assert_eq!(operator.apply(-1.0), 0.0, "{:?}", operator);
+        }
+    }
+
+    #[test]
+    fn is_monotonic() {
+        for operator in OPERATORS.iter() {
+            let mut previous = 0.0;
+            for i in 1..10_000 {
+                let v = operator.apply(i as f32 * 0.01);
+                assert!(v >= previous && v <= 1.0, "{:?} at {}", operator, i as f32 * 0.01);
+                previous = v;
+            }
+        }
+    }
+
+    #[test]
+    fn maps_white_to_one() {
+        for &white in &[0.5, 1.0, 4.0, 100.0] {
+            let v = ToneMapping::ReinhardExtended { white }.apply(white);
+            assert!((v - 1.0).abs() < 1e-6, "white {} maps to {}", white, v);
+        }
+    }
+
+    #[test]
+    fn degenerate_white_is_plain_reinhard() {
+        for &white in &[0.0, -1.0, f32::NAN] {
+            for &x in &[0.0, 0.5, 1.0, 100.0] {
+                let v = ToneMapping::ReinhardExtended { white }.apply(x);
+                assert!(v.is_finite(), "white {} maps {} to {}", white, x, v);
+                assert_eq!(v, ToneMapping::Reinhard.apply(x));
+            }
+        }
+    }
+
+    #[test]
+    fn clamps_bad_dimensions() {
+        let image = HdrImage::filled(-3, 4, Rgbaf::zero());
+        assert_eq!((image.width, image.height, image.pixels().len()), (0, 4, 0));
+        assert_eq!(image.get(0, 0), None);
+        assert_eq!(image.to_image().width(), 0);
+        assert_eq!(HdrImage::new(3, -1).pixels().len(), 0);
+    }
+}
@@ -0,0 +1,448 @@
+//!
+//! Images of typed pixels stored row by row from the top-left corner.
+//! TgaImage and the image codecs are layered over these, see `tga::TgaPixels`.
+//!
+
+use std::ops::{Index, IndexMut};
+
+
+/// A pixel made of `CHANNELS` values of type `Channel`.
+///
+/// # Safety
+/// Implementors must have the layout of `[Self::Channel; Self::CHANNELS]`(e.g. `#[repr(transparent)]` over such an array),
+/// so that the pixels of an image can be viewed as a flat slice of channels.
+pub unsafe trait Pixel: Copy + Default + PartialEq + std::fmt::Debug {
+    type Channel: Copy + Default + PartialEq + std::fmt::Debug;
+    const CHANNELS: usize;
+    const HAS_ALPHA: bool;
+
+    fn channels(&self) -> &[Self::Channel];
+    fn channels_mut(&mut self) -> &mut [Self::Channel];
+    fn from_channels(channels: &[Self::Channel]) -> Self;
+
+    // Convert to and from RGBA in [0, 1](unbounded for float pixels), used to convert between formats.
+    fn to_rgba(&self) -> [f32; 4];
+    fn from_rgba(rgba: [f32; 4]) -> Self;
+}
+
+fn quantize(v: f32) -> u8 {
+    (v * 255.0 + 0.5).clamp(0.0, 255.0) as u8
+}
+
+// Rec.709 luminance, every conversion of a color to gray goes through it.
+pub fn luminance(r: f32, g: f32, b: f32) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+macro_rules! define_pixel {
+    ($(#[$meta:meta])* $name:ident, $channel:ty, $channels:expr, alpha: $alpha:expr,
+     to_rgba: |$p:ident| $to_rgba:expr, from_rgba: |$c:ident| $from_rgba:expr) => {
+
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq)]
+        #[repr(transparent)]
+        pub struct $name(pub [$channel; $channels]);
+
+        unsafe impl Pixel for $name {
+            type Channel = $channel;
+            const CHANNELS: usize = $channels;
+            const HAS_ALPHA: bool = $alpha;
+
+            fn channels(&self) -> &[$channel] { &self.0 }
+            fn channels_mut(&mut self) -> &mut [$channel] { &mut self.0 }
+            fn from_channels(channels: &[$channel]) -> $name {
+                let mut pixel = $name::default();
+                pixel.0.copy_from_slice(channels);
+                pixel
+            }
+
+            fn to_rgba(&self) -> [f32; 4] {
+                let $p = &self.0;
+                $to_rgba
+            }
+            fn from_rgba($c: [f32; 4]) -> $name {
+                $name($from_rgba)
+            }
+        }
+    };
+}
+
+define_pixel!(
+    /// 8-bit grayscale.
+    Luma8, u8, 1, alpha: false,
+    to_rgba: |p| { let v = p[0] as f32 / 255.0; [v, v, v, 1.0] },
+    from_rgba: |c| [quantize(luminance(c[0], c[1], c[2]))]
+);
+define_pixel!(
+    /// 8-bit color in R, G, B order.
+    Rgb8, u8, 3, alpha: false,
+    to_rgba: |p| [p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0, 1.0],
+    from_rgba: |c| [quantize(c[0]), quantize(c[1]), quantize(c[2])]
+);
+define_pixel!(
+    /// 8-bit color with straight alpha in R, G, B, A order.
+    Rgba8, u8, 4, alpha: true,
+    to_rgba: |p| [p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0, p[3] as f32 / 255.0],
+    from_rgba: |c| [quantize(c[0]), quantize(c[1]), quantize(c[2]), quantize(c[3])]
+);
+define_pixel!(
+    /// Linear float color with alpha.
+    Rgba32F, f32, 4, alpha: true,
+    to_rgba: |p| *p,
+    from_rgba: |c| c
+);
+define_pixel!(
+    /// Float depth, converted to gray when converting to color formats.
+    Depth32F, f32, 1, alpha: false,
+    to_rgba: |p| [p[0], p[0], p[0], 1.0],
+    from_rgba: |c| [luminance(c[0], c[1], c[2])]
+);
+
+
+// A rectangle of pixels, its origin may be negative so that it can be clipped against images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width : i32,
+    pub height: i32,
+}
+
+impl Rect {
+
+    pub const fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    // The rectangle of an image of this size.
+    pub const fn of_size(width: i32, height: i32) -> Rect {
+        Rect::new(0, 0, width, height)
+    }
+
+    // Exclusive bounds.
+    pub const fn right(&self) -> i32 { self.x + self.width }
+    pub const fn bottom(&self) -> i32 { self.y + self.height }
+
+    pub const fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    pub const fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.right() && y < self.bottom()
+    }
+
+    pub const fn translated(&self, dx: i32, dy: i32) -> Rect {
+        Rect::new(self.x + dx, self.y + dy, self.width, self.height)
+    }
+
+    // The overlap of two rectangles, None if they don't overlap.
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let rect = Rect::new(x, y, self.right().min(other.right()) - x, self.bottom().min(other.bottom()) - y);
+        if rect.is_empty() { None } else { Some(rect) }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image<P: Pixel> {
+    pixels: Vec<P>,
+    width : usize,
+    height: usize,
+}
+
+impl<P: Pixel> Image<P> {
+
+    pub fn new(width: usize, height: usize) -> Image<P> {
+        Image::from_pixel(width, height, P::default())
+    }
+
+    pub fn from_pixel(width: usize, height: usize, pixel: P) -> Image<P> {
+        Image { pixels: vec![pixel; width * height], width, height }
+    }
+
+    // Return None if the number of pixels doesn't match the dimensions.
+    pub fn from_vec(width: usize, height: usize, pixels: Vec<P>) -> Option<Image<P>> {
+        if width.checked_mul(height) == Some(pixels.len()) {
+            Some(Image { pixels, width, height })
+        } else {
+            None
+        }
+    }
+
+    // Build an image from interleaved channels, None if their number doesn't match the dimensions.
+    pub fn from_raw(width: usize, height: usize, channels: &[P::Channel]) -> Option<Image<P>> {
+        if width.checked_mul(height).and_then(|n| n.checked_mul(P::CHANNELS)) != Some(channels.len()) {
+            return None
+        }
+        let pixels = channels.chunks_exact(P::CHANNELS).map(P::from_channels).collect();
+        Some(Image { pixels, width, height })
+    }
+
+    pub fn from_fn(width: usize, height: usize, mut f: impl FnMut(usize, usize) -> P) -> Image<P> {
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(f(x, y));
+            }
+        }
+        Image { pixels, width, height }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize { self.width }
+    #[inline]
+    pub fn height(&self) -> usize { self.height }
+
+    pub fn pixels(&self) -> &[P] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [P] {
+        &mut self.pixels
+    }
+
+    pub fn into_vec(self) -> Vec<P> {
+        self.pixels
+    }
+
+    // The interleaved channels of all pixels.
+    pub fn as_raw(&self) -> &[P::Channel] {
+        // Safety: `Pixel` guarantees that P has the layout of [P::Channel; P::CHANNELS]
+        unsafe { std::slice::from_raw_parts(self.pixels.as_ptr() as *const P::Channel, self.pixels.len() * P::CHANNELS) }
+    }
+
+    pub fn as_raw_mut(&mut self) -> &mut [P::Channel] {
+        // Safety: see `as_raw`
+        unsafe { std::slice::from_raw_parts_mut(self.pixels.as_mut_ptr() as *mut P::Channel, self.pixels.len() * P::CHANNELS) }
+    }
+
+    #[inline]
+    pub fn row(&self, y: usize) -> &[P] {
+        &self.pixels[(y * self.width)..((y + 1) * self.width)]
+    }
+
+    #[inline]
+    pub fn row_mut(&mut self, y: usize) -> &mut [P] {
+        &mut self.pixels[(y * self.width)..((y + 1) * self.width)]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[P]> {
+        // max(1) avoids a zero chunk size, images of width 0 have no pixels anyway
+        self.pixels.chunks_exact(self.width.max(1))
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [P]> {
+        self.pixels.chunks_exact_mut(self.width.max(1))
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> Option<&P> {
+        if x < self.width && y < self.height { Some(&self.pixels[x + y * self.width]) } else { None }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut P> {
+        if x < self.width && y < self.height { Some(&mut self.pixels[x + y * self.width]) } else { None }
+    }
+
+    /// Pixel (x, y) without bounds checking.
+    ///
+    /// # Safety
+    /// x must be less than the width and y less than the height.
+    #[inline]
+    pub unsafe fn get_unchecked(&self, x: usize, y: usize) -> &P {
+        self.pixels.get_unchecked(x + y * self.width)
+    }
+
+    /// # Safety
+    /// See `get_unchecked`.
+    #[inline]
+    pub unsafe fn get_unchecked_mut(&mut self, x: usize, y: usize) -> &mut P {
+        self.pixels.get_unchecked_mut(x + y * self.width)
+    }
+
+    pub fn fill(&mut self, pixel: P) {
+        self.pixels.iter_mut().for_each(|p| *p = pixel);
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::of_size(self.width as i32, self.height as i32)
+    }
+
+    // Fill the part of `rect` inside the image.
+    pub fn fill_rect(&mut self, rect: Rect, pixel: P) {
+        if let Some(rect) = rect.intersect(&self.bounds()) {
+            for y in rect.y..rect.bottom() {
+                self.row_mut(y as usize)[(rect.x as usize)..(rect.right() as usize)].fill(pixel);
+            }
+        }
+    }
+
+    // Copy the `source_rect` part of `source` with its top-left corner at (x, y), converting the pixels to this format.
+    // The parts falling outside of either image are skipped.
+    pub fn blit<Q: Pixel>(&mut self, x: i32, y: i32, source: &Image<Q>, source_rect: Rect) {
+
+        let (dx, dy) = (x - source_rect.x, y - source_rect.y);
+        let area = source_rect.intersect(&source.bounds()).and_then(|rect| rect.translated(dx, dy).intersect(&self.bounds()));
+        if let Some(area) = area {
+            for j in area.y..area.bottom() {
+                for i in area.x..area.right() {
+                    self[(i as usize, j as usize)] = P::from_rgba(source[((i - dx) as usize, (j - dy) as usize)].to_rgba());
+                }
+            }
+        }
+    }
+
+    pub fn flip_vertically(&mut self) {
+        let width = self.width;
+        for j in 0..(self.height / 2) {
+            let (top, bottom) = self.pixels.split_at_mut((self.height - 1 - j) * width);
+            top[(j * width)..((j + 1) * width)].swap_with_slice(&mut bottom[..width]);
+        }
+    }
+
+    pub fn flip_horizontally(&mut self) {
+        self.rows_mut().for_each(|row| row.reverse());
+    }
+
+    // Copy the part of the image inside `rect`, clipped to the image.
+    pub fn crop(&self, rect: Rect) -> Image<P> {
+        match rect.intersect(&Rect::of_size(self.width as i32, self.height as i32)) {
+            | Some(rect) => Image::from_fn(rect.width as usize, rect.height as usize, |x, y| self[(x + rect.x as usize, y + rect.y as usize)]),
+            | None => Image::new(0, 0),
+        }
+    }
+
+    // Swap rows and columns, pixel (x, y) moves to (y, x).
+    pub fn transpose(&self) -> Image<P> {
+        Image::from_fn(self.height, self.width, |x, y| self[(y, x)])
+    }
+
+    // Rotate by 90 degrees clockwise.
+    pub fn rotate90(&self) -> Image<P> {
+        Image::from_fn(self.height, self.width, |x, y| self[(y, self.height - 1 - x)])
+    }
+
+    pub fn rotate180(&self) -> Image<P> {
+        let mut pixels = self.pixels.clone();
+        pixels.reverse();
+        Image { pixels, width: self.width, height: self.height }
+    }
+
+    // Rotate by 90 degrees counterclockwise.
+    pub fn rotate270(&self) -> Image<P> {
+        Image::from_fn(self.height, self.width, |x, y| self[(self.width - 1 - y, x)])
+    }
+
+    pub fn map<Q: Pixel>(&self, f: impl FnMut(&P) -> Q) -> Image<Q> {
+        Image { pixels: self.pixels.iter().map(f).collect(), width: self.width, height: self.height }
+    }
+
+    // Convert the pixels to another format through RGBA.
+    pub fn convert<Q: Pixel>(&self) -> Image<Q> {
+        self.map(|p| Q::from_rgba(p.to_rgba()))
+    }
+}
+
+impl<P: Pixel> Index<(usize, usize)> for Image<P> {
+    type Output = P;
+
+    #[inline]
+    fn index(&self, (x, y): (usize, usize)) -> &P {
+        assert!(x < self.width, "x = {} is out of bounds(width = {})", x, self.width);
+        &self.pixels[x + y * self.width]
+    }
+}
+
+impl<P: Pixel> IndexMut<(usize, usize)> for Image<P> {
+
+    #[inline]
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut P {
+        assert!(x < self.width, "x = {} is out of bounds(width = {})", x, self.width);
+        &mut self.pixels[x + y * self.width]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(width: usize, height: usize) -> Image<Luma8> {
+        Image::from_fn(width, height, |x, y| Luma8([(x + y * width) as u8]))
+    }
+
+    #[test]
+    fn intersects_rectangles() {
+        let rect = Rect::new(-2, 1, 5, 4);
+        assert_eq!(rect.intersect(&Rect::of_size(10, 10)), Some(Rect::new(0, 1, 3, 4)));
+        assert_eq!(rect.intersect(&Rect::new(1, 2, 1, 1)), Some(Rect::new(1, 2, 1, 1)));
+        assert_eq!(rect.intersect(&rect), Some(rect));
+        // touching edges don't overlap
+        assert_eq!(rect.intersect(&Rect::new(3, 1, 2, 2)), None);
+        assert_eq!(rect.intersect(&Rect::new(-2, 5, 2, 2)), None);
+        assert_eq!(rect.intersect(&Rect::new(0, 0, 0, 10)), None);
+        assert_eq!(Rect::new(10, 10, -3, 5).intersect(&Rect::of_size(20, 20)), None);
+    }
+
+    #[test]
+    fn blit_clips_to_both_images() {
+        let source = numbered(4, 3);
+
+        let mut image = Image::<Luma8>::new(3, 3);
+        image.blit(-1, 1, &source, source.bounds());
+        assert_eq!(image.as_raw(), &[0, 0, 0, 1, 2, 3, 5, 6, 7]);
+
+        // the parts of `source_rect` outside of the source are skipped too
+        let mut image = Image::<Luma8>::new(3, 3);
+        image.blit(0, 0, &source, Rect::new(2, -1, 5, 3));
+        assert_eq!(image.as_raw(), &[0, 0, 0, 2, 3, 0, 6, 7, 0]);
+
+        let mut image = Image::<Luma8>::new(3, 3);
+        image.blit(3, 0, &source, source.bounds());
+        image.blit(-4, 0, &source, source.bounds());
+        assert_eq!(image.as_raw(), &[0; 9]);
+
+        // pixels are converted to the format of the destination
+        let mut color = Image::<Rgba8>::new(1, 1);
+        color.blit(0, 0, &source, Rect::new(3, 2, 1, 1));
+        assert_eq!(color[(0, 0)], Rgba8([11, 11, 11, 255]));
+    }
+
+    #[test]
+    fn accesses_rows() {
+        let mut image = numbered(3, 2);
+        assert_eq!(image.row(1), &[Luma8([3]), Luma8([4]), Luma8([5])]);
+        image.row_mut(0)[2] = Luma8([9]);
+        let rows: Vec<Vec<u8>> = image.rows().map(|row| row.iter().map(|p| p.0[0]).collect()).collect();
+        assert_eq!(rows, [[0, 1, 9], [3, 4, 5]]);
+        assert_eq!(image.get(2, 1), Some(&Luma8([5])));
+        assert_eq!(image.get(3, 0), None);
+        assert_eq!(image.get(0, 2), None);
+
+        image.rows_mut().for_each(|row| row.reverse());
+        assert_eq!(image.as_raw(), &[9, 1, 0, 5, 4, 3]);
+        assert_eq!(Image::<Luma8>::new(0, 5).rows().count(), 0);
+        assert_eq!(Image::<Luma8>::new(5, 0).rows().count(), 0);
+    }
+
+    #[test]
+    fn converts_between_pixel_formats() {
+        assert_eq!(Luma8([51]).to_rgba(), [0.2, 0.2, 0.2, 1.0]);
+        assert_eq!(Rgb8::from_rgba(Rgba8([1, 2, 3, 4]).to_rgba()), Rgb8([1, 2, 3]));
+        assert_eq!(Rgba8::from_rgba(Rgb8([1, 2, 3]).to_rgba()), Rgba8([1, 2, 3, 255]));
+        assert_eq!(Luma8::from_rgba(Rgb8([255, 255, 255]).to_rgba()), Luma8([255]));
+        assert_eq!(Luma8::from_rgba([0.0, 1.0, 0.0, 1.0]), Luma8([182]));
+        // float colors are clamped when quantized
+        assert_eq!(Rgba8::from_rgba(Rgba32F([2.0, -1.0, 0.5, 1.0]).to_rgba()), Rgba8([255, 0, 128, 255]));
+        assert_eq!(Depth32F([0.25]).to_rgba(), [0.25, 0.25, 0.25, 1.0]);
+        assert_eq!(Depth32F::from_rgba([1.0, 1.0, 1.0, 1.0]).0[0], luminance(1.0, 1.0, 1.0));
+
+        let image = Image::from_vec(2, 1, vec![Rgba8([10, 20, 30, 40]), Rgba8([50, 60, 70, 80])]).unwrap();
+        assert_eq!(image.convert::<Rgba32F>().convert::<Rgba8>(), image);
+        assert_eq!(image.convert::<Rgb8>().as_raw(), &[10, 20, 30, 50, 60, 70]);
+        assert!(Image::<Rgb8>::from_raw(2, 1, &[1, 2, 3, 4, 5]).is_none());
+        assert!(Image::<Rgb8>::from_vec(usize::MAX, 2, Vec::new()).is_none());
+    }
+}
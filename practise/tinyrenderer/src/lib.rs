@@ -1,9 +1,25 @@
 
+pub mod image;
 pub mod tga;
+pub mod netpbm;
+pub mod zlib;
+pub mod png;
+pub mod gif;
+pub mod compare;
+pub mod composite;
+pub mod resize;
+pub mod filter;
+pub mod view;
+pub mod text;
+pub mod terminal;
+pub mod hdr;
+pub mod color;
+pub mod sampler;
 pub mod bresenham;
 pub mod mesh;
 pub mod rasterization;
 pub mod camera;
+pub mod depth;
 pub mod shader;
 
 pub type Vec4f = vek::Vec4<f32>;
@@ -6,6 +6,8 @@ use std::io::prelude::*;
 
 use crate::{Vec2f, Vec3f};
 use crate::tga::{TgaImage, TgaColor};
+use crate::sampler::{Sampler, MipChain, Filter, AddressMode};
+use crate::color::Color;
 
 
 #[derive(Debug, Clone, Default)]
@@ -23,6 +25,12 @@ pub struct ObjMesh {
     pub diffuse_map : TgaImage,
     pub normal_map  : TgaImage,
     pub specular_map: TgaImage,
+    pub sampler: Sampler, // used for all the texture lookups, nearest texel clamped to the edges by default
+
+    // only built by `generate_mipmaps`, then rebuilt when a map is loaded
+    diffuse_mips : Option<MipChain>,
+    normal_mips  : Option<MipChain>,
+    specular_mips: Option<MipChain>,
 }
 
 impl ObjMesh {
@@ -97,6 +105,10 @@ impl ObjMesh {
             diffuse_map: TgaImage::unset(),
             normal_map: TgaImage::unset(),
             specular_map: TgaImage::unset(),
+            sampler: Sampler::new(Filter::Nearest, AddressMode::ClampToEdge),
+            diffuse_mips : None,
+            normal_mips  : None,
+            specular_mips: None,
         };
         Ok(mesh)
     }
@@ -104,50 +116,91 @@ impl ObjMesh {
     pub fn load_diffuse_map(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
         self.diffuse_map = TgaImage::from_path(path)?;
         self.diffuse_map.flip_vertically();
+        if self.diffuse_mips.is_some() {
+            self.diffuse_mips = Some(MipChain::new(&self.diffuse_map));
+        }
         Ok(())
     }
 
     pub fn load_normal_map(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
         self.normal_map = TgaImage::from_path(path)?;
         self.normal_map.flip_vertically();
+        if self.normal_mips.is_some() {
+            self.normal_mips = Some(MipChain::new_linear(&self.normal_map));
+        }
         Ok(())
     }
 
     pub fn load_specular_map(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
         self.specular_map = TgaImage::from_path(path)?;
         self.specular_map.flip_vertically();
+        if self.specular_mips.is_some() {
+            self.specular_mips = Some(MipChain::new_linear(&self.specular_map));
+        }
         Ok(())
     }
 
+    // Build the mip chains used by the `_grad` lookups, which sample the maps themselves until then.
+    // Call it again after assigning a map directly.
+    pub fn generate_mipmaps(&mut self) {
+        self.diffuse_mips  = Some(MipChain::new(&self.diffuse_map));
+        self.normal_mips   = Some(MipChain::new_linear(&self.normal_map));
+        self.specular_mips = Some(MipChain::new_linear(&self.specular_map));
+    }
+
     pub fn sample_diffuse(&self, uv: Vec2f) -> TgaColor {
-        self.diffuse_map.get(
-            (uv[0] * self.diffuse_map.width  as f32) as i32,
-            (uv[1] * self.diffuse_map.height as f32) as i32,
-        ).unwrap()
+        self.sampler.sample(&self.diffuse_map, uv)
     }
 
-    pub fn sample_normal(&self, uv: Vec2f) -> Vec3f {
-        let c = self.normal_map.get(
-            (uv[0] * self.normal_map.width  as f32) as i32,
-            (uv[1] * self.normal_map.height as f32) as i32,
-        ).unwrap();
+    // The diffuse map is sRGB encoded, this decodes it to linear for shading.
+    pub fn sample_diffuse_color(&self, uv: Vec2f) -> Color {
+        self.sampler.sample_color(&self.diffuse_map, uv)
+    }
 
-        // Why inverse the order???? -> https://github.com/ssloy/tinyrenderer/issues/44
-        // https://www.zhihu.com/question/23706933/answer/70432570
-        Vec3f::new(
-            (c[2] as f32 / 255.0) * 2.0 - 1.0,
-            (c[1] as f32 / 255.0) * 2.0 - 1.0,
-            (c[0] as f32 / 255.0) * 2.0 - 1.0,
-        )
+    pub fn sample_normal(&self, uv: Vec2f) -> Vec3f {
+        ObjMesh::decode_normal(self.sampler.sample(&self.normal_map, uv))
     }
 
     pub fn sample_specular(&self, uv: Vec2f) -> f32 {
-        let c = self.specular_map.get(
-            (uv[0] * self.specular_map.width  as f32) as i32,
-            (uv[1] * self.specular_map.height as f32) as i32,
-        ).unwrap();
+        self.sampler.sample(&self.specular_map, uv).luma() as f32
+    }
+
+    // The same lookups filtered over the mip chains, duv_dx and duv_dy are the derivatives of uv along the screen axes.
+    pub fn sample_diffuse_grad(&self, uv: Vec2f, duv_dx: Vec2f, duv_dy: Vec2f) -> TgaColor {
+        match &self.diffuse_mips {
+            | Some(mips) => self.sampler.sample_grad(mips, uv, duv_dx, duv_dy),
+            | None => self.sample_diffuse(uv),
+        }
+    }
 
-        c[0] as f32
+    pub fn sample_diffuse_color_grad(&self, uv: Vec2f, duv_dx: Vec2f, duv_dy: Vec2f) -> Color {
+        match &self.diffuse_mips {
+            | Some(mips) => self.sampler.sample_grad_color(mips, uv, duv_dx, duv_dy),
+            | None => self.sample_diffuse_color(uv),
+        }
+    }
+
+    pub fn sample_normal_grad(&self, uv: Vec2f, duv_dx: Vec2f, duv_dy: Vec2f) -> Vec3f {
+        match &self.normal_mips {
+            | Some(mips) => ObjMesh::decode_normal(self.sampler.sample_grad(mips, uv, duv_dx, duv_dy)),
+            | None => self.sample_normal(uv),
+        }
+    }
+
+    pub fn sample_specular_grad(&self, uv: Vec2f, duv_dx: Vec2f, duv_dy: Vec2f) -> f32 {
+        match &self.specular_mips {
+            | Some(mips) => self.sampler.sample_grad(mips, uv, duv_dx, duv_dy).luma() as f32,
+            | None => self.sample_specular(uv),
+        }
+    }
+
+    // Normal maps store x, y, z in the red, green and blue channels, mapped from [-1, 1] to [0, 255].
+    fn decode_normal(c: TgaColor) -> Vec3f {
+        Vec3f::new(
+            (c.r() as f32 / 255.0) * 2.0 - 1.0,
+            (c.g() as f32 / 255.0) * 2.0 - 1.0,
+            (c.b() as f32 / 255.0) * 2.0 - 1.0,
+        )
     }
 
     fn print_help_message(path: impl AsRef<Path>, vertices: &[Vertex], faces: &[[usize; 3]]) {
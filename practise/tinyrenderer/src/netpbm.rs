@@ -0,0 +1,639 @@
+//!
+//! Netpbm images: PBM/PGM/PPM in plain(ASCII) or raw(binary) form, and PFM for float images.
+//! See http://netpbm.sourceforge.net/doc/ppm.html and http://www.pauldebevec.com/Research/HDR/PFM/
+//!
+
+use std::path::Path;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use crate::tga::{TgaImage, TgaFormat};
+use crate::hdr::HdrImage;
+use crate::image::{Image, Rgba32F, luminance};
+
+
+#[derive(Debug)]
+pub enum NetpbmError {
+    UnsupportedFormat([u8; 2]), // the magic number
+    InvalidHeader(&'static str),
+    InvalidSample,
+    TruncatedData,
+    Io(std::io::Error),
+}
+
+pub type NetpbmResult<T> = Result<T, NetpbmError>;
+
+impl std::fmt::Display for NetpbmError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            | NetpbmError::UnsupportedFormat(magic) => write!(f, "unsupported netpbm format({})", String::from_utf8_lossy(magic)),
+            | NetpbmError::InvalidHeader(reason) => write!(f, "invalid netpbm header: {}", reason),
+            | NetpbmError::InvalidSample => write!(f, "sample is not a number or larger than the maximum value"),
+            | NetpbmError::TruncatedData => write!(f, "pixel data is truncated"),
+            | NetpbmError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for NetpbmError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            | NetpbmError::Io(e) => Some(e),
+            | _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for NetpbmError {
+    fn from(e: std::io::Error) -> NetpbmError {
+        NetpbmError::Io(e)
+    }
+}
+
+impl From<NetpbmError> for std::io::Error {
+    fn from(e: NetpbmError) -> std::io::Error {
+        match e {
+            | NetpbmError::Io(e) => e,
+            | _ => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetpbmEncoding {
+    Plain, // samples written as decimal numbers
+    Raw,   // samples written as bytes
+}
+
+// Split the header into tokens, skipping whitespaces and comments(from '#' to the end of line).
+struct HeaderReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+
+    fn new(bytes: &'a [u8]) -> HeaderReader<'a> {
+        HeaderReader { bytes, position: 0 }
+    }
+
+    fn next_token(&mut self) -> NetpbmResult<&'a [u8]> {
+
+        while let Some(&c) = self.bytes.get(self.position) {
+            if c == b'#' {
+                while self.bytes.get(self.position).is_some_and(|&c| c != b'\n' && c != b'\r') {
+                    self.position += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                self.position += 1;
+            } else {
+                break
+            }
+        }
+
+        let start = self.position;
+        while self.bytes.get(self.position).is_some_and(|c| !c.is_ascii_whitespace() && *c != b'#') {
+            self.position += 1;
+        }
+
+        if start == self.position {
+            Err(NetpbmError::TruncatedData)
+        } else {
+            Ok(&self.bytes[start..self.position])
+        }
+    }
+
+    fn next_number<T: std::str::FromStr>(&mut self, field: &'static str) -> NetpbmResult<T> {
+        let token = self.next_token()?;
+        std::str::from_utf8(token).ok()
+            .and_then(|token| token.parse().ok())
+            .ok_or(NetpbmError::InvalidHeader(field))
+    }
+
+    // Raw data starts after exactly one whitespace following the last header field.
+    fn raw_data(&self) -> &'a [u8] {
+        self.bytes.get((self.position + 1)..).unwrap_or(&[])
+    }
+}
+
+
+pub fn load_pnm(path: impl AsRef<Path>) -> NetpbmResult<TgaImage> {
+    decode_pnm(&std::fs::read(path)?)
+}
+
+pub fn read_pnm(mut reader: impl Read) -> NetpbmResult<TgaImage> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    decode_pnm(&bytes)
+}
+
+// Decode a PBM(P1/P4) or PGM(P2/P5) image to a grayscale image, or a PPM(P3/P6) image to a RGB image.
+// Samples with a maximum value other than 255 are rescaled to 8 bits.
+pub fn decode_pnm(bytes: &[u8]) -> NetpbmResult<TgaImage> {
+
+    let mut header = HeaderReader::new(bytes);
+    let magic = header.next_token()?;
+    let (channels, encoding, bitmap) = match magic {
+        | b"P1" => (1, NetpbmEncoding::Plain, true),
+        | b"P2" => (1, NetpbmEncoding::Plain, false),
+        | b"P3" => (3, NetpbmEncoding::Plain, false),
+        | b"P4" => (1, NetpbmEncoding::Raw, true),
+        | b"P5" => (1, NetpbmEncoding::Raw, false),
+        | b"P6" => (3, NetpbmEncoding::Raw, false),
+        | _ => return Err(NetpbmError::UnsupportedFormat([magic[0], magic.get(1).copied().unwrap_or(b' ')])),
+    };
+
+    let width : i32 = header.next_number("width")?;
+    let height: i32 = header.next_number("height")?;
+    // bitmaps have no maximum value, their bits are inverted below so that 1(black) becomes 0
+    let max_value: u32 = if bitmap { 1 } else { header.next_number("maximum value")? };
+    if width <= 0 || height <= 0 || width.checked_mul(height).is_none() {
+        return Err(NetpbmError::InvalidHeader("bad dimensions"))
+    }
+    if max_value == 0 || max_value > 65535 {
+        return Err(NetpbmError::InvalidHeader("maximum value must be in 1..65536"))
+    }
+
+    // the data size is checked before allocating, the header alone may claim gigabytes of pixels
+    let sample_count = (width * height) as usize * channels;
+    let samples: Vec<u32> = match encoding {
+        | NetpbmEncoding::Raw if bitmap => {
+            // rows are padded to whole bytes, the first pixel is the most significant bit
+            let row_bytes = (width as usize).div_ceil(8);
+            let data = header.raw_data();
+            if data.len() / row_bytes < height as usize {
+                return Err(NetpbmError::TruncatedData)
+            }
+            data.chunks_exact(row_bytes).take(height as usize)
+                .flat_map(|row| (0..width as usize).map(move |x| 1 - ((row[x / 8] >> (7 - x % 8)) & 1) as u32))
+                .collect()
+        },
+        | NetpbmEncoding::Raw => {
+            // samples take 2 bytes(big endian) when the maximum value doesn't fit in a byte
+            let sample_bytes = if max_value < 256 { 1 } else { 2 };
+            let data = header.raw_data();
+            if data.len() / sample_bytes < sample_count {
+                return Err(NetpbmError::TruncatedData)
+            }
+            data.chunks_exact(sample_bytes).take(sample_count)
+                .map(|sample| if sample_bytes == 1 { sample[0] as u32 } else { u16::from_be_bytes([sample[0], sample[1]]) as u32 })
+                .collect()
+        },
+        | NetpbmEncoding::Plain => {
+            // each sample takes at least one digit, bitmap samples don't need a separator
+            if bytes.len() - header.position < sample_count {
+                return Err(NetpbmError::TruncatedData)
+            }
+            let mut samples = Vec::with_capacity(sample_count);
+            if bitmap {
+                for &c in bytes[header.position..].iter().filter(|c| !c.is_ascii_whitespace()).take(sample_count) {
+                    match c {
+                        | b'0' => samples.push(1),
+                        | b'1' => samples.push(0),
+                        | _ => return Err(NetpbmError::InvalidSample),
+                    }
+                }
+                if samples.len() < sample_count {
+                    return Err(NetpbmError::TruncatedData)
+                }
+            } else {
+                for _ in 0..sample_count {
+                    samples.push(header.next_number::<u32>("sample").map_err(|e| match e {
+                        | NetpbmError::TruncatedData => e,
+                        | _ => NetpbmError::InvalidSample,
+                    })?);
+                }
+            }
+            samples
+        },
+    };
+
+    let mut data = Vec::with_capacity(sample_count);
+    for pixel in samples.chunks_exact(channels) {
+        if pixel.iter().any(|&v| v > max_value) {
+            return Err(NetpbmError::InvalidSample)
+        }
+        data.extend(pixel.iter().map(|&v| ((v * 255 + max_value / 2) / max_value) as u8));
+    }
+
+    let format = if channels == 1 { TgaFormat::Grayscale } else { TgaFormat::RGB };
+    TgaImage::from_raw_data(width, height, format, data).map_err(|_| NetpbmError::InvalidHeader("bad dimensions"))
+}
+
+pub fn save_ppm(image: &TgaImage, path: impl AsRef<Path>, encoding: NetpbmEncoding) -> NetpbmResult<()> {
+    write_ppm(image, File::create(path)?, encoding)
+}
+
+pub fn save_pgm(image: &TgaImage, path: impl AsRef<Path>, encoding: NetpbmEncoding) -> NetpbmResult<()> {
+    write_pgm(image, File::create(path)?, encoding)
+}
+
+// Write the image as PPM, alpha is dropped and grayscale is expanded to RGB.
+pub fn write_ppm(image: &TgaImage, writer: impl Write, encoding: NetpbmEncoding) -> NetpbmResult<()> {
+
+    let bytes_per_pixel = image.format() as usize;
+    let mut samples = Vec::with_capacity((image.width * image.height) as usize * 3);
+    for pixel in image.raw_data().chunks_exact(bytes_per_pixel) {
+        if image.format() == TgaFormat::Grayscale {
+            samples.extend_from_slice(&[pixel[0], pixel[0], pixel[0]]);
+        } else {
+            samples.extend_from_slice(&pixel[..3]);
+        }
+    }
+
+    write_samples(writer, if encoding == NetpbmEncoding::Plain { "P3" } else { "P6" }, image.width, image.height, &samples, encoding)
+}
+
+// Write the image as PGM, colors are converted to their luma.
+pub fn write_pgm(image: &TgaImage, writer: impl Write, encoding: NetpbmEncoding) -> NetpbmResult<()> {
+
+    let bytes_per_pixel = image.format() as usize;
+    let samples: Vec<u8> = image.raw_data().chunks_exact(bytes_per_pixel).map(|pixel| {
+        if image.format() == TgaFormat::Grayscale {
+            pixel[0]
+        } else {
+            (luminance(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32) + 0.5) as u8
+        }
+    }).collect();
+
+    write_samples(writer, if encoding == NetpbmEncoding::Plain { "P2" } else { "P5" }, image.width, image.height, &samples, encoding)
+}
+
+fn write_samples(mut writer: impl Write, magic: &str, width: i32, height: i32, samples: &[u8], encoding: NetpbmEncoding) -> NetpbmResult<()> {
+
+    // plain files should not have lines longer than 70 characters
+    const MAX_LINE_LENGTH: usize = 70;
+
+    let mut bytes = format!("{}\n{} {}\n255\n", magic, width, height).into_bytes();
+    match encoding {
+        | NetpbmEncoding::Raw => bytes.extend_from_slice(samples),
+        | NetpbmEncoding::Plain => {
+            let mut line_length = 0;
+            for sample in samples {
+                let sample = sample.to_string();
+                if line_length + sample.len() + 1 > MAX_LINE_LENGTH {
+                    bytes.push(b'\n');
+                    line_length = 0;
+                } else if line_length > 0 {
+                    bytes.push(b' ');
+                    line_length += 1;
+                }
+                bytes.extend_from_slice(sample.as_bytes());
+                line_length += sample.len();
+            }
+            bytes.push(b'\n');
+        },
+    }
+
+    writer.write_all(&bytes)?;
+    Ok(())
+}
+
+
+// A float image as stored in PFM files, either grayscale(1 channel) or RGB(3 channels).
+#[derive(Debug, Clone)]
+pub struct PfmImage {
+    pub width : usize,
+    pub height: usize,
+    pub channels: usize,
+    pub data: Vec<f32>, // row by row from the top-left corner
+}
+
+impl PfmImage {
+
+    pub fn new(width: usize, height: usize, channels: usize) -> PfmImage {
+        assert!(channels == 1 || channels == 3, "PFM image has either 1 or 3 channels");
+        PfmImage { width, height, channels, data: vec![0.0; width * height * channels] }
+    }
+
+    #[inline]
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height
+    }
+
+    // The samples of a pixel, None if it's outside of the image.
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> Option<&[f32]> {
+        if self.contains(x, y) {
+            // Safety: (x, y) is inside of the image
+            Some(unsafe { self.get_unchecked(x, y) })
+        } else {
+            None
+        }
+    }
+
+    // Write the samples of a pixel, pixels outside of the image are clipped(skipped).
+    // `v` must hold one sample per channel.
+    #[inline]
+    pub fn set(&mut self, x: usize, y: usize, v: &[f32]) {
+        assert_eq!(v.len(), self.channels, "Pixel must have one sample per channel");
+        if self.contains(x, y) {
+            // Safety: (x, y) is inside of the image and `v` has the length of a pixel
+            unsafe { self.set_unchecked(x, y, v) }
+        }
+    }
+
+    /// Read a pixel without bounds checking.
+    ///
+    /// # Safety
+    /// (x, y) must be inside of the image: x < width and y < height.
+    #[inline]
+    pub unsafe fn get_unchecked(&self, x: usize, y: usize) -> &[f32] {
+        let location = (x + y * self.width) * self.channels;
+        self.data.get_unchecked(location..(location + self.channels))
+    }
+
+    /// Write a pixel without bounds checking.
+    ///
+    /// # Safety
+    /// (x, y) must be inside of the image: x < width and y < height, and `v` must hold `channels` samples.
+    #[inline]
+    pub unsafe fn set_unchecked(&mut self, x: usize, y: usize, v: &[f32]) {
+        let location = (x + y * self.width) * self.channels;
+        self.data.get_unchecked_mut(location..(location + self.channels)).copy_from_slice(v);
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> NetpbmResult<PfmImage> {
+        PfmImage::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_reader(mut reader: impl Read) -> NetpbmResult<PfmImage> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        PfmImage::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> NetpbmResult<PfmImage> {
+
+        let mut header = HeaderReader::new(bytes);
+        let magic = header.next_token()?;
+        let channels = match magic {
+            | b"Pf" => 1,
+            | b"PF" => 3,
+            | _ => return Err(NetpbmError::UnsupportedFormat([magic[0], magic.get(1).copied().unwrap_or(b' ')])),
+        };
+
+        let width : usize = header.next_number("width")?;
+        let height: usize = header.next_number("height")?;
+        // the sign of scale gives the byte order, negative for little endian
+        let scale: f32 = header.next_number("scale")?;
+        if scale == 0.0 || !scale.is_finite() {
+            return Err(NetpbmError::InvalidHeader("scale must be a non-zero number"))
+        }
+
+        let sample_count = width.checked_mul(height)
+            .and_then(|count| count.checked_mul(channels))
+            .ok_or(NetpbmError::InvalidHeader("bad dimensions"))?;
+        let data = header.raw_data();
+        if data.len() / 4 < sample_count {
+            return Err(NetpbmError::TruncatedData)
+        }
+
+        let mut image = PfmImage::new(width, height, channels);
+        let row_samples = width * channels;
+        for (i, sample) in data.chunks_exact(4).take(sample_count).enumerate() {
+            let sample = [sample[0], sample[1], sample[2], sample[3]];
+            let v = if scale < 0.0 { f32::from_le_bytes(sample) } else { f32::from_be_bytes(sample) };
+            // rows are stored from bottom to top
+            let (row, column) = (i / row_samples, i % row_samples);
+            image.data[(height - 1 - row) * row_samples + column] = v;
+        }
+
+        Ok(image)
+    }
+
+    pub fn write_pfm_file(&self, path: impl AsRef<Path>) -> NetpbmResult<()> {
+        self.write_to(File::create(path)?)
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> NetpbmResult<()> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    // Encode as little endian PFM.
+    pub fn to_bytes(&self) -> Vec<u8> {
+
+        let magic = if self.channels == 1 { "Pf" } else { "PF" };
+        let mut bytes = format!("{}\n{} {}\n-1.0\n", magic, self.width, self.height).into_bytes();
+
+        let row_samples = self.width * self.channels;
+        if row_samples > 0 {
+            for row in self.data.chunks_exact(row_samples).rev() {
+                for v in row {
+                    bytes.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    // Map 8-bit samples to [0, 1], alpha is dropped.
+    pub fn from_tga(image: &TgaImage) -> PfmImage {
+
+        let bytes_per_pixel = image.format() as usize;
+        let channels = if image.format() == TgaFormat::Grayscale { 1 } else { 3 };
+
+        let mut data = Vec::with_capacity((image.width * image.height) as usize * channels);
+        for pixel in image.raw_data().chunks_exact(bytes_per_pixel) {
+            if channels == 1 {
+                data.push(pixel[0] as f32 / 255.0);
+            } else {
+                data.extend(pixel[..3].iter().map(|&v| v as f32 / 255.0));
+            }
+        }
+
+        PfmImage { width: image.width as usize, height: image.height as usize, channels, data }
+    }
+
+    // Clamp samples to [0, 1] and quantize them to 8 bits.
+    // The fields are public, so they are checked to agree with each other.
+    pub fn to_tga(&self) -> NetpbmResult<TgaImage> {
+
+        let format = match self.channels {
+            | 1 => TgaFormat::Grayscale,
+            | 3 => TgaFormat::RGB,
+            | _ => return Err(NetpbmError::InvalidHeader("PFM image has either 1 or 3 channels")),
+        };
+        if self.width > i32::MAX as usize || self.height > i32::MAX as usize {
+            return Err(NetpbmError::InvalidHeader("bad dimensions"))
+        }
+
+        let data = self.data.iter().map(|&v| (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8).collect();
+        TgaImage::from_raw_data(self.width as i32, self.height as i32, format, data).map_err(|_| NetpbmError::InvalidHeader("bad dimensions"))
+    }
+
+    pub fn to_hdr(&self) -> HdrImage {
+        HdrImage::from(self)
+    }
+
+    pub fn to_image(&self) -> Image<Rgba32F> {
+        Image::from(self)
+    }
+}
+
+// Alpha is dropped, PFM files have no alpha channel.
+impl From<&Image<Rgba32F>> for PfmImage {
+    fn from(image: &Image<Rgba32F>) -> PfmImage {
+        let data = image.pixels().iter().flat_map(|p| p.0[..3].iter().copied()).collect();
+        PfmImage { width: image.width(), height: image.height(), channels: 3, data }
+    }
+}
+
+// Grayscale samples are copied to the three color channels, alpha is opaque.
+impl From<&PfmImage> for Image<Rgba32F> {
+    fn from(image: &PfmImage) -> Image<Rgba32F> {
+        let pixels = image.data.chunks_exact(image.channels).map(|v| match *v {
+            | [r, g, b] => Rgba32F([r, g, b, 1.0]),
+            | _ => Rgba32F([v[0], v[0], v[0], 1.0]),
+        }).collect();
+        Image::from_vec(image.width, image.height, pixels).expect("Pixel count must match the dimensions")
+    }
+}
+
+impl From<&HdrImage> for PfmImage {
+    fn from(image: &HdrImage) -> PfmImage {
+        PfmImage::from(&image.to_image())
+    }
+}
+
+impl From<&PfmImage> for HdrImage {
+    fn from(image: &PfmImage) -> HdrImage {
+        HdrImage::from(image.to_image())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tga::TgaColor;
+
+    #[test]
+    fn clips_pixels_outside_of_the_image() {
+        let mut image = PfmImage::new(2, 1, 3);
+        image.set(1, 0, &[1.0, 2.0, 3.0]);
+        image.set(2, 0, &[4.0, 5.0, 6.0]);
+        image.set(0, 1, &[4.0, 5.0, 6.0]);
+        assert_eq!(image.data, [0.0, 0.0, 0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(image.get(1, 0), Some(&[1.0, 2.0, 3.0][..]));
+        assert_eq!(image.get(2, 0), None);
+        assert_eq!(image.get(0, 1), None);
+    }
+
+    #[test]
+    fn converts_to_float_images() {
+        let mut gray = PfmImage::new(2, 1, 1);
+        gray.set(1, 0, &[2.5]);
+        let hdr = gray.to_hdr();
+        assert_eq!(hdr.get(1, 0).map(|c| c.into_array()), Some([2.5, 2.5, 2.5, 1.0]));
+
+        let mut image = Image::new(2, 2);
+        image[(1, 1)] = Rgba32F([4.0, 0.5, 0.25, 0.5]);
+        let pfm = PfmImage::from(&image);
+        assert_eq!((pfm.width, pfm.height, pfm.channels), (2, 2, 3));
+        assert_eq!(pfm.get(1, 1), Some(&[4.0, 0.5, 0.25][..]));
+
+        let round_trip = HdrImage::from(image.clone()).to_pfm().to_image();
+        assert_eq!(round_trip[(1, 1)], Rgba32F([4.0, 0.5, 0.25, 1.0]));
+        assert_eq!(PfmImage::from_bytes(&pfm.to_bytes()).unwrap().data, pfm.data);
+    }
+
+    #[test]
+    fn decodes_plain_and_raw_images() {
+        let decode = |bytes: &[u8]| {
+            let image = decode_pnm(bytes).unwrap();
+            (image.width, image.height, image.format(), image.raw_data().to_vec())
+        };
+
+        let bitmap = (3, 2, TgaFormat::Grayscale, vec![0, 255, 0, 255, 0, 0]);
+        assert_eq!(decode(b"P1\n# comment\n3 2\n1 0 1\n011\n"), bitmap);
+        assert_eq!(decode(b"P4\n3 2\n\xa0\x60"), bitmap);
+
+        assert_eq!(decode(b"P2 2 1 15\n0 15\n"), (2, 1, TgaFormat::Grayscale, vec![0, 255]));
+        assert_eq!(decode(b"P5 2 1 65535\n\x00\x00\xff\xff"), (2, 1, TgaFormat::Grayscale, vec![0, 255]));
+        assert_eq!(decode(b"P3 1 1 255\n1 2 3\n"), (1, 1, TgaFormat::RGB, vec![1, 2, 3]));
+        assert_eq!(decode(b"P6 1 1 255\n\x01\x02\x03"), (1, 1, TgaFormat::RGB, vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn round_trips_written_images() {
+        let mut image = TgaImage::new(3, 2, TgaFormat::RGB);
+        image.set(0, 0, &TgaColor::from_rgb(255, 0, 0));
+        image.set(2, 1, &TgaColor::from_rgb(1, 2, 3));
+
+        for &encoding in &[NetpbmEncoding::Plain, NetpbmEncoding::Raw] {
+            let mut bytes = Vec::new();
+            write_ppm(&image, &mut bytes, encoding).unwrap();
+            assert_eq!(decode_pnm(&bytes).unwrap().raw_data(), image.raw_data());
+
+            let mut bytes = Vec::new();
+            write_pgm(&image, &mut bytes, encoding).unwrap();
+            let gray = decode_pnm(&bytes).unwrap();
+            assert_eq!(gray.format(), TgaFormat::Grayscale);
+            assert_eq!(gray.raw_data(), &[54, 0, 0, 0, 0, 2]);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_images() {
+        let truncated = |bytes: &[u8]| matches!(decode_pnm(bytes), Err(NetpbmError::TruncatedData));
+        assert!(truncated(b"P6 46340 46340 255\n\0\0\0"));
+        assert!(truncated(b"P5 46340 46340 255\n\0\0\0"));
+        assert!(truncated(b"P4 46340 46340\n\0\0\0"));
+        assert!(truncated(b"P3 46340 46340 255\n0 0 0"));
+        assert!(truncated(b"P1 46340 46340\n0 0 0"));
+        assert!(truncated(b"P5 2 1 65535\n\x00\x00\xff"));
+        assert!(truncated(b"P2 3 1 255\n0 1   "));
+        assert!(truncated(b"P1 2 2\n0 1 1   "));
+        assert!(truncated(b"P6 1 1"));
+
+        let invalid_header = |bytes: &[u8]| matches!(decode_pnm(bytes), Err(NetpbmError::InvalidHeader(_)));
+        assert!(invalid_header(b"P6 65536 65536 255\n"));
+        assert!(invalid_header(b"P6 99999999999 1 255\n"));
+        assert!(invalid_header(b"P6 0 1 255\n"));
+        assert!(invalid_header(b"P6 1 1 0\n\0\0\0"));
+        assert!(invalid_header(b"P6 1 1 65536\n\0\0\0\0\0\0"));
+
+        assert!(matches!(decode_pnm(b"P2 1 1 15\n16\n"), Err(NetpbmError::InvalidSample)));
+        assert!(matches!(decode_pnm(b"P1 1 1\n2\n"), Err(NetpbmError::InvalidSample)));
+        assert!(matches!(decode_pnm(b"P7 1 1 255\n\0"), Err(NetpbmError::UnsupportedFormat(magic)) if &magic == b"P7"));
+    }
+
+    #[test]
+    fn decodes_pfm_in_both_byte_orders() {
+        // rows are stored from bottom to top
+        let mut big_endian = b"Pf\n1 2\n1.0\n".to_vec();
+        big_endian.extend_from_slice(&0.25f32.to_be_bytes());
+        big_endian.extend_from_slice(&2.0f32.to_be_bytes());
+        let image = PfmImage::from_bytes(&big_endian).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (1, 2, 1));
+        assert_eq!(image.data, [2.0, 0.25]);
+
+        let mut little_endian = b"PF\n1 1\n-1.0\n".to_vec();
+        for v in &[0.5f32, 1.0, -1.0] {
+            little_endian.extend_from_slice(&v.to_le_bytes());
+        }
+        let image = PfmImage::from_bytes(&little_endian).unwrap();
+        assert_eq!(image.data, [0.5, 1.0, -1.0]);
+        assert_eq!(image.to_tga().unwrap().raw_data(), &[128, 255, 0]);
+
+        assert!(matches!(PfmImage::from_bytes(b"PF 46340 46340 -1.0\n\0\0\0\0"), Err(NetpbmError::TruncatedData)));
+        assert!(matches!(PfmImage::from_bytes(b"PF 4294967296 4294967296 -1.0\n"), Err(NetpbmError::InvalidHeader(_))));
+        assert!(matches!(PfmImage::from_bytes(b"Pf 1 1 0.0\n\0\0\0\0"), Err(NetpbmError::InvalidHeader(_))));
+        assert!(matches!(PfmImage::from_bytes(b"Pf 1 1 -1.0\n\0\0"), Err(NetpbmError::TruncatedData)));
+    }
+
+    #[test]
+    fn rejects_inconsistent_pfm_fields() {
+        let mut image = PfmImage::new(2, 2, 3);
+        assert!(image.to_tga().is_ok());
+        image.data.pop();
+        assert!(image.to_tga().is_err());
+        let image = PfmImage { width: 1, height: 1, channels: 2, data: vec![0.0; 2] };
+        assert!(image.to_tga().is_err());
+    }
+}
@@ -0,0 +1,628 @@
+//!
+//! PNG encoding and decoding on top of the zlib module, see https://www.w3.org/TR/PNG/
+//! The encoder writes 8-bit grayscale, RGB or RGBA images, the decoder accepts every standard color type,
+//! bit depth and Adam7 interlacing.
+//!
+
+use std::path::Path;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use crate::tga::{TgaImage, TgaFormat};
+use crate::zlib::{self, ZlibError};
+
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+// The size of IDAT chunks written by the encoder.
+const MAX_IDAT_LENGTH: usize = 1 << 16;
+// Refuse images whose decoded data would be larger than this(4 GiB).
+const MAX_DECODED_SIZE: u64 = 1 << 32;
+
+#[derive(Debug)]
+pub enum PngError {
+    InvalidSignature,
+    InvalidChunk(&'static str),
+    CrcMismatch([u8; 4]), // the chunk type
+    UnsupportedFormat(&'static str),
+    InvalidData(&'static str),
+    Zlib(ZlibError),
+    Io(std::io::Error),
+}
+
+pub type PngResult<T> = Result<T, PngError>;
+
+impl std::fmt::Display for PngError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            | PngError::InvalidSignature => write!(f, "not a png file"),
+            | PngError::InvalidChunk(reason) => write!(f, "invalid chunk: {}", reason),
+            | PngError::CrcMismatch(chunk_type) => write!(f, "crc mismatch in chunk {}", String::from_utf8_lossy(chunk_type)),
+            | PngError::UnsupportedFormat(reason) => write!(f, "unsupported png: {}", reason),
+            | PngError::InvalidData(reason) => write!(f, "invalid image data: {}", reason),
+            | PngError::Zlib(e) => write!(f, "{}", e),
+            | PngError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PngError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            | PngError::Zlib(e) => Some(e),
+            | PngError::Io(e) => Some(e),
+            | _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PngError {
+    fn from(e: std::io::Error) -> PngError {
+        PngError::Io(e)
+    }
+}
+
+impl From<ZlibError> for PngError {
+    fn from(e: ZlibError) -> PngError {
+        PngError::Zlib(e)
+    }
+}
+
+impl From<PngError> for std::io::Error {
+    fn from(e: PngError) -> std::io::Error {
+        match e {
+            | PngError::Io(e) => e,
+            | _ => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+
+// CRC-32 as used by PNG(polynomial 0xedb88320), the table is built at compile time.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0_u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0_u32, |crc, &byte| CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+
+pub fn load_png(path: impl AsRef<Path>) -> PngResult<TgaImage> {
+    decode_png(&std::fs::read(path)?)
+}
+
+pub fn read_png(mut reader: impl Read) -> PngResult<TgaImage> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    decode_png(&bytes)
+}
+
+pub fn save_png(image: &TgaImage, path: impl AsRef<Path>) -> PngResult<()> {
+    write_png(image, File::create(path)?)
+}
+
+pub fn write_png(image: &TgaImage, mut writer: impl Write) -> PngResult<()> {
+    writer.write_all(&encode_png(image)?)?;
+    Ok(())
+}
+
+pub fn encode_png(image: &TgaImage) -> PngResult<Vec<u8>> {
+
+    // IHDR can't describe an empty image
+    if image.width <= 0 || image.height <= 0 {
+        return Err(PngError::InvalidData("image must be at least 1x1"))
+    }
+
+    let bytes_per_pixel = image.format() as usize;
+    let color_type = match image.format() {
+        | TgaFormat::Grayscale => 0,
+        | TgaFormat::RGB  => 2,
+        | TgaFormat::RGBA => 6,
+    };
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(image.width as u32).to_be_bytes());
+    header.extend_from_slice(&(image.height as u32).to_be_bytes());
+    // 8-bit depth, deflate compression, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+    let pixels = image.raw_data();
+
+    let stride = image.width as usize * bytes_per_pixel;
+    let mut filtered = Vec::with_capacity((stride + 1) * image.height as usize);
+    let mut previous = vec![0; stride];
+    if stride > 0 {
+        for row in pixels.chunks_exact(stride) {
+            filter_row(row, &previous, bytes_per_pixel, &mut filtered);
+            previous.copy_from_slice(row);
+        }
+    }
+
+    let mut bytes = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut bytes, b"IHDR", &header);
+    for data in zlib::compress(&filtered).chunks(MAX_IDAT_LENGTH) {
+        write_chunk(&mut bytes, b"IDAT", data);
+    }
+    write_chunk(&mut bytes, b"IEND", &[]);
+    Ok(bytes)
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = bytes.len();
+    bytes.extend_from_slice(chunk_type);
+    bytes.extend_from_slice(data);
+    // the crc covers the chunk type and the data
+    let crc = crc32(&bytes[start..]);
+    bytes.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+// Try every filter type on the row and keep the one with the smallest sum of absolute differences.
+fn filter_row(row: &[u8], previous: &[u8], bytes_per_pixel: usize, output: &mut Vec<u8>) {
+
+    let mut best_filter = 0;
+    let mut best_row = row.to_vec();
+    let mut best_score = u64::MAX;
+    let mut candidate = vec![0; row.len()];
+
+    for filter in 0..5_u8 {
+        for i in 0..row.len() {
+            let a = if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 };
+            let b = previous[i];
+            let c = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+            let predictor = match filter {
+                | 0 => 0,
+                | 1 => a,
+                | 2 => b,
+                | 3 => ((a as u16 + b as u16) / 2) as u8,
+                | _ => paeth(a, b, c),
+            };
+            candidate[i] = row[i].wrapping_sub(predictor);
+        }
+
+        let score = candidate.iter().map(|&v| (v as i8).unsigned_abs() as u64).sum();
+        if score < best_score {
+            best_score = score;
+            best_filter = filter;
+            best_row.copy_from_slice(&candidate);
+        }
+    }
+
+    output.push(best_filter);
+    output.extend_from_slice(&best_row);
+}
+
+fn unfilter_row(filter: u8, row: &mut [u8], previous: &[u8], bytes_per_pixel: usize) -> PngResult<()> {
+
+    for i in 0..row.len() {
+        let a = if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 };
+        let b = previous[i];
+        let c = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+        let predictor = match filter {
+            | 0 => 0,
+            | 1 => a,
+            | 2 => b,
+            | 3 => ((a as u16 + b as u16) / 2) as u8,
+            | 4 => paeth(a, b, c),
+            | _ => return Err(PngError::InvalidData("unknown filter type")),
+        };
+        row[i] = row[i].wrapping_add(predictor);
+    }
+    Ok(())
+}
+
+
+struct PngHeader {
+    width : usize,
+    height: usize,
+    bit_depth : u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl PngHeader {
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            | 0 | 3 => 1,
+            | 4 => 2,
+            | 2 => 3,
+            | _ => 4,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    // Return the size of the sub-images of the interlace passes, or of the whole image.
+    fn passes(&self) -> Vec<(usize, usize, usize, usize, usize, usize)> {
+
+        // (x0, y0, dx, dy) of the Adam7 passes
+        const ADAM7: [(usize, usize, usize, usize); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+        let passes: &[(usize, usize, usize, usize)] = if self.interlaced { &ADAM7 } else { &[(0, 0, 1, 1)] };
+        passes.iter().filter_map(|&(x0, y0, dx, dy)| {
+            let width  = (self.width  + dx - 1 - x0) / dx;
+            let height = (self.height + dy - 1 - y0) / dy;
+            if self.width > x0 && self.height > y0 && width > 0 && height > 0 {
+                Some((x0, y0, dx, dy, width, height))
+            } else {
+                None
+            }
+        }).collect()
+    }
+}
+
+pub fn decode_png(bytes: &[u8]) -> PngResult<TgaImage> {
+
+    if bytes.len() < PNG_SIGNATURE.len() || bytes[..8] != PNG_SIGNATURE {
+        return Err(PngError::InvalidSignature)
+    }
+
+    let mut header = None;
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let mut transparency: Vec<u8> = Vec::new();
+    let mut compressed = Vec::new();
+
+    let mut position = PNG_SIGNATURE.len();
+    loop {
+        let chunk_header = bytes.get(position..(position + 8)).ok_or(PngError::InvalidChunk("missing IEND chunk"))?;
+        let length = u32::from_be_bytes([chunk_header[0], chunk_header[1], chunk_header[2], chunk_header[3]]) as usize;
+        let chunk_type = [chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]];
+        let data = position.checked_add(8 + length)
+            .and_then(|end| bytes.get((position + 8)..end))
+            .ok_or(PngError::InvalidChunk("chunk is truncated"))?;
+        let crc = bytes.get((position + 8 + length)..(position + 12 + length)).ok_or(PngError::InvalidChunk("chunk is truncated"))?;
+        if crc32(&bytes[(position + 4)..(position + 8 + length)]) != u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+            return Err(PngError::CrcMismatch(chunk_type))
+        }
+        position += 12 + length;
+
+        match &chunk_type {
+            | b"IHDR" => {
+                if data.len() != 13 {
+                    return Err(PngError::InvalidChunk("IHDR must be 13 bytes"))
+                }
+                header = Some(read_header(data)?);
+            },
+            | b"PLTE" => {
+                if !data.len().is_multiple_of(3) || data.len() > 256 * 3 {
+                    return Err(PngError::InvalidChunk("bad palette size"))
+                }
+                palette = data.chunks_exact(3).map(|entry| [entry[0], entry[1], entry[2]]).collect();
+            },
+            | b"tRNS" => transparency = data.to_vec(),
+            | b"IDAT" => compressed.extend_from_slice(data),
+            | b"IEND" => break,
+            | _ => {
+                // an unknown chunk may only be skipped if it's ancillary(lowercase first letter)
+                if chunk_type[0].is_ascii_uppercase() {
+                    return Err(PngError::UnsupportedFormat("unknown critical chunk"))
+                }
+            },
+        }
+    }
+
+    let header = header.ok_or(PngError::InvalidChunk("missing IHDR chunk"))?;
+    if header.color_type == 3 && palette.is_empty() {
+        return Err(PngError::InvalidChunk("missing PLTE chunk"))
+    }
+
+    // size of the filtered data, including the filter type byte of each row
+    let expected_size: u64 = header.passes().iter()
+        .map(|&(_, _, _, _, width, height)| ((width * header.bits_per_pixel()).div_ceil(8) as u64 + 1) * height as u64)
+        .sum();
+    if expected_size > MAX_DECODED_SIZE {
+        return Err(PngError::UnsupportedFormat("image is too large"))
+    }
+    let mut filtered = zlib::decompress_with_limit(&compressed, expected_size as usize)?;
+    if (filtered.len() as u64) < expected_size {
+        return Err(PngError::InvalidData("image data is truncated"))
+    }
+
+    let samples = read_samples(&header, &mut filtered)?;
+    to_tga_image(&header, &samples, &palette, &transparency)
+}
+
+fn read_header(data: &[u8]) -> PngResult<PngHeader> {
+
+    let width  = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    let (bit_depth, color_type, compression, filter, interlace) = (data[8], data[9], data[10], data[11], data[12]);
+
+    if width == 0 || height == 0 || width > i32::MAX as usize || height > i32::MAX as usize {
+        return Err(PngError::InvalidChunk("bad dimensions"))
+    }
+    let valid_depth = match color_type {
+        | 0 => [1, 2, 4, 8, 16].contains(&bit_depth),
+        | 3 => [1, 2, 4, 8].contains(&bit_depth),
+        | 2 | 4 | 6 => [8, 16].contains(&bit_depth),
+        | _ => false,
+    };
+    if !valid_depth {
+        return Err(PngError::UnsupportedFormat("invalid combination of color type and bit depth"))
+    }
+    if compression != 0 || filter != 0 || interlace > 1 {
+        return Err(PngError::UnsupportedFormat("unknown compression, filter or interlace method"))
+    }
+
+    Ok(PngHeader { width, height, bit_depth, color_type, interlaced: interlace == 1 })
+}
+
+// Undo the filters and gather the samples of every pixel(at the original bit depth) in the full image layout.
+fn read_samples(header: &PngHeader, filtered: &mut [u8]) -> PngResult<Vec<u16>> {
+
+    let channels = header.channels();
+    let bit_depth = header.bit_depth as usize;
+    // filters work on whole bytes, low bit depths use the previous byte
+    let filter_bytes = header.bits_per_pixel().div_ceil(8);
+
+    // the filtered data is already limited, but the samples take up to 16 times more room at low bit depths
+    let sample_count = header.width.checked_mul(header.height)
+        .and_then(|count| count.checked_mul(channels))
+        .filter(|&count| count.checked_mul(std::mem::size_of::<u16>()).is_some_and(|size| size as u64 <= MAX_DECODED_SIZE))
+        .ok_or(PngError::UnsupportedFormat("image is too large"))?;
+    let mut samples = vec![0_u16; sample_count];
+    let mut position = 0;
+    for (x0, y0, dx, dy, width, height) in header.passes() {
+        let stride = (width * header.bits_per_pixel()).div_ceil(8);
+        let mut previous = vec![0; stride];
+
+        for j in 0..height {
+            let filter = filtered[position];
+            let row = &mut filtered[(position + 1)..(position + 1 + stride)];
+            unfilter_row(filter, row, &previous, filter_bytes)?;
+            position += stride + 1;
+
+            let y = y0 + j * dy;
+            for i in 0..(width * channels) {
+                let v = match bit_depth {
+                    | 16 => u16::from_be_bytes([row[2 * i], row[2 * i + 1]]),
+                    | 8  => row[i] as u16,
+                    | _  => {
+                        // samples are packed from the most significant bit
+                        let bit = i * bit_depth;
+                        ((row[bit / 8] >> (8 - bit_depth - bit % 8)) & ((1 << bit_depth) - 1) as u8) as u16
+                    },
+                };
+                let x = x0 + (i / channels) * dx;
+                samples[(x + y * header.width) * channels + i % channels] = v;
+            }
+            previous.copy_from_slice(row);
+        }
+    }
+
+    Ok(samples)
+}
+
+fn to_tga_image(header: &PngHeader, samples: &[u16], palette: &[[u8; 3]], transparency: &[u8]) -> PngResult<TgaImage> {
+
+    let max_value = (1_u32 << header.bit_depth) - 1;
+    let to_u8 = |v: u16| (v as u32 * 255 / max_value) as u8;
+    // tRNS of grayscale/RGB images gives a single transparent color, at the bit depth of the image
+    let transparent_color: Vec<u16> = transparency.chunks_exact(2).map(|v| u16::from_be_bytes([v[0], v[1]])).collect();
+
+    let channels = header.channels();
+    let (format, data): (TgaFormat, Vec<u8>) = match header.color_type {
+        | 0 if transparent_color.is_empty() => (TgaFormat::Grayscale, samples.iter().map(|&v| to_u8(v)).collect()),
+        | 0 => (TgaFormat::RGBA, samples.iter().flat_map(|&v| {
+            let g = to_u8(v);
+            vec![g, g, g, if v == transparent_color[0] { 0 } else { 255 }]
+        }).collect()),
+        | 2 => {
+            let has_alpha = transparent_color.len() >= 3;
+            let format = if has_alpha { TgaFormat::RGBA } else { TgaFormat::RGB };
+            (format, samples.chunks_exact(channels).flat_map(|pixel| {
+                let mut color = vec![to_u8(pixel[0]), to_u8(pixel[1]), to_u8(pixel[2])];
+                if has_alpha {
+                    color.push(if pixel[..3] == transparent_color[..3] { 0 } else { 255 });
+                }
+                color
+            }).collect())
+        },
+        | 3 => {
+            let has_alpha = !transparency.is_empty();
+            let format = if has_alpha { TgaFormat::RGBA } else { TgaFormat::RGB };
+            let mut data = Vec::with_capacity(samples.len() * format as usize);
+            for &index in samples {
+                let entry = palette.get(index as usize).ok_or(PngError::InvalidData("palette index is out of range"))?;
+                data.extend_from_slice(entry);
+                if has_alpha {
+                    // entries past the end of tRNS are opaque
+                    data.push(transparency.get(index as usize).copied().unwrap_or(255));
+                }
+            }
+            (format, data)
+        },
+        | 4 => (TgaFormat::RGBA, samples.chunks_exact(channels).flat_map(|pixel| {
+            let g = to_u8(pixel[0]);
+            vec![g, g, g, to_u8(pixel[1])]
+        }).collect()),
+        | _ => (TgaFormat::RGBA, samples.iter().map(|&v| to_u8(v)).collect()),
+    };
+
+    Ok(TgaImage::from_raw_data(header.width as i32, header.height as i32, format, data).expect("Pixel count must match the dimensions"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use itertools::iproduct;
+
+    use super::*;
+    use crate::tga::TgaColor;
+
+    fn noise(length: usize, max_value: u16) -> Vec<u16> {
+        let mut state = 0x2545_f491_u32;
+        (0..length).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state % (max_value as u32 + 1)) as u16
+        }).collect()
+    }
+
+    // Encode samples given row by row, with no filtering so that every bit depth can be written.
+    fn encode(header: &PngHeader, samples: &[u16], chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+
+        let channels = header.channels();
+        let bit_depth = header.bit_depth as usize;
+        let mut filtered = Vec::new();
+        for (x0, y0, dx, dy, width, height) in header.passes() {
+            for j in 0..height {
+                let mut row = vec![0_u8; (width * header.bits_per_pixel()).div_ceil(8)];
+                for i in 0..(width * channels) {
+                    let x = x0 + (i / channels) * dx;
+                    let v = samples[(x + (y0 + j * dy) * header.width) * channels + i % channels];
+                    match bit_depth {
+                        | 16 => row[(2 * i)..(2 * i + 2)].copy_from_slice(&v.to_be_bytes()),
+                        | _  => {
+                            let bit = i * bit_depth;
+                            row[bit / 8] |= (v as u8) << (8 - bit_depth - bit % 8);
+                        },
+                    }
+                }
+                filtered.push(0);
+                filtered.extend_from_slice(&row);
+            }
+        }
+
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&(header.width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(header.height as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[header.bit_depth, header.color_type, 0, 0, header.interlaced as u8]);
+
+        let mut bytes = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut bytes, b"IHDR", &ihdr);
+        for (chunk_type, data) in chunks {
+            write_chunk(&mut bytes, chunk_type, data);
+        }
+        write_chunk(&mut bytes, b"IDAT", &zlib::compress(&filtered));
+        write_chunk(&mut bytes, b"IEND", &[]);
+        bytes
+    }
+
+    fn gradient(width: i32, height: i32, format: TgaFormat) -> TgaImage {
+        let mut image = TgaImage::new(width, height, format);
+        let noise = noise((width * height) as usize, 15);
+        for (x, y) in iproduct!(0..width, 0..height) {
+            let n = noise[(x + y * width) as usize] as i32;
+            image.set(x, y, &TgaColor::from_rgba((x * 7 + n) as u8, (y * 5) as u8, (x * y + n) as u8, (255 - x - n) as u8));
+        }
+        image
+    }
+
+    #[test]
+    fn round_trips_encoded_images() {
+        for &format in &[TgaFormat::Grayscale, TgaFormat::RGB, TgaFormat::RGBA] {
+            for &(width, height) in &[(1, 1), (37, 23), (64, 3)] {
+                let image = gradient(width, height, format);
+                let decoded = decode_png(&encode_png(&image).unwrap()).unwrap();
+                assert_eq!((decoded.width, decoded.height, decoded.format()), (width, height, format));
+                assert_eq!(decoded.raw_data(), image.raw_data());
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_to_encode_empty_images() {
+        assert!(matches!(encode_png(&TgaImage::new(0, 4, TgaFormat::RGB)), Err(PngError::InvalidData(_))));
+        assert!(matches!(encode_png(&TgaImage::new(4, 0, TgaFormat::RGB)), Err(PngError::InvalidData(_))));
+    }
+
+    #[test]
+    fn decodes_every_color_type_and_bit_depth() {
+        let color_types: [(u8, &[u8]); 5] = [(0, &[1, 2, 4, 8, 16]), (2, &[8, 16]), (3, &[1, 2, 4, 8]), (4, &[8, 16]), (6, &[8, 16])];
+        for &(color_type, bit_depths) in &color_types {
+            for &bit_depth in bit_depths {
+                for &interlaced in &[false, true] {
+                    let header = PngHeader { width: 13, height: 11, bit_depth, color_type, interlaced };
+                    let max_value = ((1_u32 << bit_depth) - 1) as u16;
+                    let samples = noise(header.width * header.height * header.channels(), max_value);
+                    let palette: Vec<u8> = if color_type == 3 { (0..=max_value).flat_map(|i| vec![i as u8, 255 - i as u8, (i / 2) as u8]).collect() } else { vec![] };
+                    let chunks: &[(&[u8; 4], &[u8])] = if color_type == 3 { &[(b"PLTE", &palette)] } else { &[] };
+
+                    let image = decode_png(&encode(&header, &samples, chunks)).unwrap();
+                    let to_u8 = |v: u16| (v as u32 * 255 / max_value as u32) as u8;
+                    let expected: Vec<u8> = match color_type {
+                        | 0 => samples.iter().map(|&v| to_u8(v)).collect(),
+                        | 3 => samples.iter().flat_map(|&v| palette[(3 * v as usize)..(3 * v as usize + 3)].to_vec()).collect(),
+                        | 4 => samples.chunks_exact(2).flat_map(|v| vec![to_u8(v[0]), to_u8(v[0]), to_u8(v[0]), to_u8(v[1])]).collect(),
+                        | _ => samples.iter().map(|&v| to_u8(v)).collect(),
+                    };
+                    assert_eq!(image.raw_data(), &expected[..], "color type {}, {} bits, interlaced: {}", color_type, bit_depth, interlaced);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn applies_transparency() {
+        let header = PngHeader { width: 3, height: 1, bit_depth: 2, color_type: 3, interlaced: false };
+        let palette = [10, 20, 30, 40, 50, 60, 70, 80, 90];
+        let image = decode_png(&encode(&header, &[0, 1, 2], &[(b"PLTE", &palette), (b"tRNS", &[0, 128])])).unwrap();
+        assert_eq!(image.raw_data(), &[10, 20, 30, 0, 40, 50, 60, 128, 70, 80, 90, 255]);
+
+        let header = PngHeader { width: 2, height: 1, bit_depth: 16, color_type: 0, interlaced: false };
+        let image = decode_png(&encode(&header, &[1000, 1001], &[(b"tRNS", &[0x03, 0xe8])])).unwrap();
+        assert_eq!(image.format(), TgaFormat::RGBA);
+        assert_eq!(image.raw_data().iter().skip(3).step_by(4).copied().collect::<Vec<_>>(), [0, 255]);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let bytes = encode_png(&gradient(16, 16, TgaFormat::RGB)).unwrap();
+        for length in 0..bytes.len() {
+            assert!(decode_png(&bytes[..length]).is_err(), "truncated to {} bytes", length);
+        }
+
+        // a valid stream holding one row less than the image
+        let header = PngHeader { width: 4, height: 2, bit_depth: 8, color_type: 0, interlaced: false };
+        let mut bytes = encode(&header, &[0; 8], &[]);
+        bytes[16..24].copy_from_slice(&[0, 0, 0, 4, 0, 0, 0, 3]);
+        let crc = crc32(&bytes[12..29]);
+        bytes[29..33].copy_from_slice(&crc.to_be_bytes());
+        assert!(matches!(decode_png(&bytes), Err(PngError::InvalidData(_))));
+    }
+
+    #[test]
+    fn rejects_corrupt_crc() {
+        let bytes = encode_png(&gradient(4, 4, TgaFormat::RGB)).unwrap();
+        // the width in IHDR, then the last byte of the IDAT crc
+        let mut corrupted = bytes.clone();
+        corrupted[19] ^= 1;
+        assert!(matches!(decode_png(&corrupted), Err(PngError::CrcMismatch(chunk_type)) if &chunk_type == b"IHDR"));
+        let mut corrupted = bytes.clone();
+        corrupted[bytes.len() - 13] ^= 1;
+        assert!(matches!(decode_png(&corrupted), Err(PngError::CrcMismatch(chunk_type)) if &chunk_type == b"IDAT"));
+    }
+
+    #[test]
+    fn limits_the_size_of_samples() {
+        // a 1-bit image whose filtered data is small enough, but whose samples aren't
+        let header = PngHeader { width: 1 << 20, height: 1 << 12, bit_depth: 1, color_type: 0, interlaced: false };
+        assert!(matches!(read_samples(&header, &mut []), Err(PngError::UnsupportedFormat(_))));
+    }
+}
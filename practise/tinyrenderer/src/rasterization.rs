@@ -1,5 +1,6 @@
 
 use crate::tga::{TgaColor, TgaImage};
+use crate::color::Color;
 use crate::{Vec2i, Vec3i, Vec2f, Vec3f, Vec4f};
 use crate::{veci2f, vecf2i};
 
@@ -28,17 +29,13 @@ pub fn line_sweeping_v1(image: &mut TgaImage, v0: Vec2i, v1: Vec2i, v2: Vec2i, c
             let mut x_v0_v2 = calc_x(y, v0, v2);
             if x_v0_v1 > x_v0_v2 { std::mem::swap(&mut x_v0_v1, &mut x_v0_v2); }
 
-            for x in x_v0_v1..x_v0_v2 {
-                image.set(x, y, color);
-            }
+            fill_span(image, y, x_v0_v1, x_v0_v2, color);
         } else {
             let mut x_v1_v2 = calc_x(y, v1, v2);
             let mut x_v0_v2 = calc_x(y, v0, v2);
 
             if x_v1_v2 > x_v0_v2 { std::mem::swap(&mut x_v1_v2, &mut x_v0_v2); }
-            for x in x_v1_v2..x_v0_v2 {
-                image.set(x, y, color);
-            }
+            fill_span(image, y, x_v1_v2, x_v0_v2, color);
         }
     }
 }
@@ -60,9 +57,7 @@ pub fn line_sweeping_v2(image: &mut TgaImage, mut v0: Vec2i, mut v1: Vec2i, mut
         let mut b_x = v0.x + ((v1.x - v0.x) as f32 * beta) as i32;
 
         if a_x > b_x { std::mem::swap(&mut a_x, &mut b_x); }
-        for x in a_x..b_x {
-            image.set(x, y, color);
-        }
+        fill_span(image, y, a_x, b_x, color);
     }
 
     let segment_height = (v2.y - v1.y) as f32;
@@ -74,9 +69,7 @@ pub fn line_sweeping_v2(image: &mut TgaImage, mut v0: Vec2i, mut v1: Vec2i, mut
         let mut b_x = v1.x + ((v2.x - v1.x) as f32 * beta)  as i32;
 
         if a_x > b_x { std::mem::swap(&mut a_x, &mut b_x); }
-        for x in a_x..b_x {
-            image.set(x, y, color);
-        }
+        fill_span(image, y, a_x, b_x, color);
     }
 }
 
@@ -118,7 +111,8 @@ pub fn line_sweeping_gouraud_shading(image: &mut TgaImage, zbuffer: &mut impl ZB
             if p.x >= image.width || p.y >= image.height || p.x < 0 || p.y < 0 { continue }
             if zbuffer.get(p.x as usize, p.y as usize) < p.z as f32 {
                 zbuffer.set(x as usize, p.y as usize, p.z as f32);
-                image.set(p.x, p.y, &(TgaColor::from_rgb(255, 255, 255) * p_intensity));
+                // Safety: p was checked to be inside of the image
+                unsafe { image.set_unchecked(p.x, p.y, &(TgaColor::from_rgb(255, 255, 255) * p_intensity)) }
             }
         }
     }
@@ -161,7 +155,8 @@ pub fn barycentric_rasterization_v1(image: &mut TgaImage, pts: [Vec2i; 3], color
         if bc_screen.x < 0.0 || bc_screen.y < 0.0 || bc_screen.z < 0.0 {
             continue
         } else {
-            image.set(x, y, color);
+            // Safety: the bounding box was clipped to the image
+            unsafe { image.set_unchecked(x, y, color) }
         }
     }
 }
@@ -170,6 +165,62 @@ pub trait ZBuffer {
     fn get(&self, i: usize, j: usize) -> f32;
     fn set(&mut self, i: usize, j: usize, v: f32);
 }
+
+// An image the pipeline can render into, colors of type `C`.
+pub trait RenderTarget<C> {
+    fn width(&self) -> i32;
+    fn height(&self) -> i32;
+    // Pixels outside of the target are clipped.
+    fn set(&mut self, x: i32, y: i32, color: &C);
+
+    /// Write a pixel the rasterizer has already clipped, without checking the bounds again.
+    ///
+    /// # Safety
+    /// (x, y) must be inside of the target: 0 <= x < width and 0 <= y < height.
+    unsafe fn set_unchecked(&mut self, x: i32, y: i32, color: &C) {
+        self.set(x, y, color)
+    }
+}
+
+impl RenderTarget<TgaColor> for TgaImage {
+    fn width(&self) -> i32 { self.width }
+    fn height(&self) -> i32 { self.height }
+    fn set(&mut self, x: i32, y: i32, color: &TgaColor) { TgaImage::set(self, x, y, color) }
+    unsafe fn set_unchecked(&mut self, x: i32, y: i32, color: &TgaColor) { TgaImage::set_unchecked(self, x, y, color) }
+}
+
+// Linear colors are clamped and sRGB encoded.
+impl RenderTarget<Color> for TgaImage {
+    fn width(&self) -> i32 { self.width }
+    fn height(&self) -> i32 { self.height }
+    fn set(&mut self, x: i32, y: i32, color: &Color) { TgaImage::set(self, x, y, &color.to_srgb8()) }
+    unsafe fn set_unchecked(&mut self, x: i32, y: i32, color: &Color) { TgaImage::set_unchecked(self, x, y, &color.to_srgb8()) }
+}
+
+// A target of the given size which discards the colors, for passes which only fill the z-buffer(e.g. shadow maps).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NullTarget {
+    pub width : i32,
+    pub height: i32,
+}
+
+impl<C> RenderTarget<C> for NullTarget {
+    fn width(&self) -> i32 { self.width }
+    fn height(&self) -> i32 { self.height }
+    fn set(&mut self, _x: i32, _y: i32, _color: &C) {}
+}
+
+// Fill the pixels [x0, x1) of row y, clipped to the image.
+fn fill_span(image: &mut TgaImage, y: i32, x0: i32, x1: i32, color: &TgaColor) {
+    if y < 0 || y >= image.height {
+        return
+    }
+    for x in x0.max(0)..x1.min(image.width) {
+        // Safety: (x, y) was clipped to the image
+        unsafe { image.set_unchecked(x, y, color) }
+    }
+}
+
 pub struct ZbufferEx {
     pub buffer: Vec<f32>,
     pub width: usize,
@@ -223,7 +274,8 @@ pub fn barycentric_rasterization_v2(image: &mut TgaImage, zbuffer: &mut impl ZBu
             let z: f32 = pts[0].z * bc_screen.x + pts[1].z * bc_screen.y + pts[2].z * bc_screen.z;
             if zbuffer.get(x as usize, y as usize) < z {
                 zbuffer.set(x as usize, y as usize, z);
-                image.set(x, y, color);
+                // Safety: the bounding box was clipped to the image
+                unsafe { image.set_unchecked(x, y, color) }
             }
         }
     }
@@ -262,7 +314,8 @@ pub fn barycentric_rasterization_diffuse(image: &mut TgaImage, zbuffer: &mut imp
                 // P = (1 - u - v)A + uB + vC
                 let uv: Vec2f = bc_screen.x * uvs[0] + bc_screen.y * uvs[1] + bc_screen.z * uvs[2];
                 let color = diffuse.get(uv.x as i32, uv.y as i32)? * intensity;
-                image.set(x, y, &color);
+                // Safety: the bounding box was clipped to the image
+                unsafe { image.set_unchecked(x, y, &color) }
             }
         }
     }
@@ -271,13 +324,13 @@ pub fn barycentric_rasterization_diffuse(image: &mut TgaImage, zbuffer: &mut imp
 }
 
 // barycentric rasterization
-pub fn triangle(image: &mut TgaImage, shader: &impl IShader, zbuffer: &mut impl ZBuffer, pts: [Vec4f; 3], max_depth: f32) {
+pub fn triangle<C>(image: &mut impl RenderTarget<C>, shader: &impl IShader<C>, zbuffer: &mut impl ZBuffer, pts: [Vec4f; 3], max_depth: f32) {
 
     use std::f32::{MAX, MIN};
     let mut bounding_box_min: Vec2f = Vec2f::new(MAX, MAX);
     let mut bounding_box_max: Vec2f = Vec2f::new(MIN, MIN);
 
-    let clamp: Vec2f = Vec2f::new((image.width - 1) as f32, (image.height - 1) as f32);
+    let clamp: Vec2f = Vec2f::new((image.width() - 1) as f32, (image.height() - 1) as f32);
     for i in 0..3 {
         bounding_box_min.x = f32::max(f32::min(bounding_box_min.x, pts[i].x / pts[i].w), 0.0);
         bounding_box_max.x = f32::min(f32::max(bounding_box_max.x, pts[i].x / pts[i].w), clamp.x);
@@ -289,22 +342,24 @@ pub fn triangle(image: &mut TgaImage, shader: &impl IShader, zbuffer: &mut impl
     let bounding_box_min = Vec2i::new(bounding_box_min.x as i32, bounding_box_min.y as i32);
     let bounding_box_max = Vec2i::new(bounding_box_max.x as i32, bounding_box_max.y as i32);
 
+    // the barycentric coordinates are linear in screen space, so their derivatives are the same for the whole triangle
+    let screen = [pts[0].homogenized().xyz(), pts[1].homogenized().xyz(), pts[2].homogenized().xyz()];
+    let bc_origin = barycentric(screen[0], screen[1], screen[2], Vec2i::new(0, 0));
+    let dbc_dx = barycentric(screen[0], screen[1], screen[2], Vec2i::new(1, 0)) - bc_origin;
+    let dbc_dy = barycentric(screen[0], screen[1], screen[2], Vec2i::new(0, 1)) - bc_origin;
+
     for (x, y) in iproduct!(bounding_box_min.x..=bounding_box_max.x, bounding_box_min.y..=bounding_box_max.y) {
-        let bc: Vec3f = barycentric(
-            pts[0].homogenized().xyz(),
-            pts[1].homogenized().xyz(),
-            pts[2].homogenized().xyz(),
-            Vec2i::new(x, y)
-        );
+        let bc: Vec3f = barycentric(screen[0], screen[1], screen[2], Vec2i::new(x, y));
         let z: f32 = pts[0].z * bc.x + pts[1].z * bc.y + pts[2].z * bc.z;
         let w: f32 = pts[0].w * bc.x + pts[1].w * bc.y + pts[2].w * bc.z;
         let fragment_depth = (z / w).max(0.0).min(max_depth);
 
         if bc.x < 0.0 || bc.y < 0.0 || bc.z < 0.0 || zbuffer.get(x as usize, y as usize) > fragment_depth {
             continue
-        } else if let Some(color) = shader.fragment(bc) {
+        } else if let Some(color) = shader.fragment_with_derivatives(bc, dbc_dx, dbc_dy) {
             zbuffer.set(x as usize, y as usize, fragment_depth);
-            image.set(x, y, &color);
+            // Safety: the bounding box was clipped to the image
+            unsafe { image.set_unchecked(x, y, &color) }
         }
     }
 }
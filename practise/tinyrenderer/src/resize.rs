@@ -0,0 +1,225 @@
+//!
+//! Image resampling with separable filters: the rows are resized first, then the columns.
+//! When downscaling, the filters are stretched to cover the source pixels of each destination pixel,
+//! see https://entropymine.com/imageworsener/resample/
+//!
+
+use crate::image::{Image, Pixel};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResizeFilter {
+    Nearest,    // the source pixel under the center of the destination pixel
+    Bilinear,   // triangle filter
+    CatmullRom, // bicubic B = 0, C = 1/2, sharp
+    Mitchell,   // bicubic B = C = 1/3, a compromise between blurring and ringing
+    Lanczos3,   // windowed sinc with 3 lobes, the sharpest but rings around edges
+}
+
+impl ResizeFilter {
+
+    // Radius of the kernel, in source pixels when upscaling.
+    fn support(&self) -> f32 {
+        match self {
+            | ResizeFilter::Nearest => 0.5,
+            | ResizeFilter::Bilinear => 1.0,
+            | ResizeFilter::CatmullRom | ResizeFilter::Mitchell => 2.0,
+            | ResizeFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    fn kernel(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            | ResizeFilter::Nearest => if x <= 0.5 { 1.0 } else { 0.0 },
+            | ResizeFilter::Bilinear => (1.0 - x).max(0.0),
+            | ResizeFilter::CatmullRom => cubic(x, 0.0, 0.5),
+            | ResizeFilter::Mitchell => cubic(x, 1.0 / 3.0, 1.0 / 3.0),
+            | ResizeFilter::Lanczos3 => if x < 3.0 { sinc(x) * sinc(x / 3.0) } else { 0.0 },
+        }
+    }
+}
+
+// Mitchell-Netravali cubic with parameters B and C.
+fn cubic(x: f32, b: f32, c: f32) -> f32 {
+    let (x2, x3) = (x * x, x * x * x);
+    let v = if x < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b)
+    } else if x < 2.0 {
+        (-b - 6.0 * c) * x3 + (6.0 * b + 30.0 * c) * x2 + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+    v / 6.0
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f32::consts::PI;
+        x.sin() / x
+    }
+}
+
+
+// The source pixels contributing to a destination pixel and their normalized weights.
+struct Contribution {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+fn contributions(source_size: usize, destination_size: usize, filter: ResizeFilter) -> Vec<Contribution> {
+
+    // pixels are kept when the size is, filters like Mitchell would blur them otherwise
+    if source_size == destination_size {
+        return (0..destination_size).map(|i| Contribution { start: i, weights: vec![1.0] }).collect()
+    }
+
+    let scale = source_size as f32 / destination_size as f32;
+    let filter_scale = scale.max(1.0);
+    let support = filter.support() * filter_scale;
+
+    (0..destination_size).map(|i| {
+        // center of the destination pixel in source coordinates
+        let center = (i as f32 + 0.5) * scale;
+        if filter == ResizeFilter::Nearest {
+            return Contribution { start: (center as usize).min(source_size - 1), weights: vec![1.0] }
+        }
+
+        // source pixels outside of the image are dropped, the remaining weights are normalized
+        let start = (center - support).floor().max(0.0) as usize;
+        let end = ((center + support).ceil() as usize).min(source_size);
+        let mut weights: Vec<f32> = (start..end).map(|j| filter.kernel((j as f32 + 0.5 - center) / filter_scale)).collect();
+        let sum: f32 = weights.iter().sum();
+        if sum != 0.0 {
+            weights.iter_mut().for_each(|w| *w /= sum);
+        }
+        Contribution { start, weights }
+    }).collect()
+}
+
+// Resize the image to width x height. Colors with alpha are filtered premultiplied, so that transparent pixels don't bleed.
+pub fn resize_image<P: Pixel>(image: &Image<P>, width: usize, height: usize, filter: ResizeFilter) -> Image<P> {
+
+    if image.width() == 0 || image.height() == 0 || width == 0 || height == 0 {
+        return Image::new(width, height)
+    }
+
+    let load = |p: &P| {
+        let [r, g, b, a] = p.to_rgba();
+        if P::HAS_ALPHA { [r * a, g * a, b * a, a] } else { [r, g, b, a] }
+    };
+    let store = |[r, g, b, a]: [f32; 4]| {
+        if P::HAS_ALPHA && a > 0.0 { P::from_rgba([r / a, g / a, b / a, a]) } else { P::from_rgba([r, g, b, a]) }
+    };
+    let accumulate = |contribution: &Contribution, texel: &dyn Fn(usize) -> [f32; 4]| {
+        let mut sum = [0.0; 4];
+        for (k, w) in contribution.weights.iter().enumerate() {
+            let t = texel(contribution.start + k);
+            for c in 0..4 {
+                sum[c] += w * t[c];
+            }
+        }
+        sum
+    };
+
+    // horizontal pass, width x source height
+    let columns = contributions(image.width(), width, filter);
+    let mut horizontal = Vec::with_capacity(width * image.height());
+    for row in image.rows() {
+        let row: Vec<[f32; 4]> = row.iter().map(load).collect();
+        horizontal.extend(columns.iter().map(|contribution| accumulate(contribution, &|x| row[x])));
+    }
+
+    // vertical pass
+    let rows = contributions(image.height(), height, filter);
+    let mut pixels = Vec::with_capacity(width * height);
+    for contribution in rows.iter() {
+        for x in 0..width {
+            pixels.push(store(accumulate(contribution, &|y| horizontal[x + y * width])));
+        }
+    }
+
+    Image::from_vec(width, height, pixels).expect("Pixel count must match the dimensions")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{Rect, Rgba8};
+
+    const FILTERS: [ResizeFilter; 5] = [ResizeFilter::Nearest, ResizeFilter::Bilinear, ResizeFilter::CatmullRom, ResizeFilter::Mitchell, ResizeFilter::Lanczos3];
+
+    fn noise(width: usize, height: usize) -> Image<Rgba8> {
+        let mut state = 3_u32;
+        Image::from_fn(width, height, |_, _| {
+            let mut next = || {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            };
+            Rgba8([next(), next(), next(), next().max(1)])
+        })
+    }
+
+    #[test]
+    fn keeps_the_image_at_the_same_size() {
+        let image = noise(7, 5);
+        for &filter in FILTERS.iter() {
+            assert_eq!(resize_image(&image, 7, 5, filter), image, "{:?}", filter);
+            // a kept axis isn't filtered either, columns of a constant color stay as they are
+            let columns = Image::from_fn(7, 5, |x, _| image[(x, 0)]);
+            let stretched = resize_image(&columns, 7, 11, filter);
+            assert!(stretched.rows().all(|row| row == columns.row(0)), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn constant_images_stay_constant() {
+        for &pixel in &[Rgba8([10, 100, 200, 255]), Rgba8([10, 100, 200, 128])] {
+            let image = Image::from_pixel(17, 13, pixel);
+            for &filter in FILTERS.iter() {
+                for &(width, height) in &[(5, 4), (1, 1), (16, 13), (40, 30)] {
+                    assert_eq!(resize_image(&image, width, height, filter), Image::from_pixel(width, height, pixel), "{:?} to {}x{}", filter, width, height);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_do_not_bleed() {
+        let image = Image::from_fn(8, 2, |x, _| if x < 4 { Rgba8([0, 0, 0, 0]) } else { Rgba8([255, 255, 255, 255]) });
+        for &filter in FILTERS.iter() {
+            for &width in &[3, 5, 16, 21] {
+                for pixel in resize_image(&image, width, 3, filter).pixels() {
+                    if pixel.0[3] > 0 {
+                        assert_eq!(&pixel.0[..3], &[255, 255, 255], "{:?} to {} pixels", filter, width);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crop_rotate_and_transpose_round_trip() {
+        let image = noise(5, 3);
+        assert_eq!(image.rotate90().rotate90().rotate90().rotate90(), image);
+        assert_eq!(image.rotate90().rotate270(), image);
+        assert_eq!(image.rotate90().rotate90(), image.rotate180());
+        assert_eq!(image.transpose().transpose(), image);
+        assert_eq!(image.rotate90(), { let mut flipped = image.transpose(); flipped.flip_horizontally(); flipped });
+        assert_eq!(image.rotate90()[(2, 0)], image[(0, 0)]);
+
+        assert_eq!(image.crop(image.bounds()), image);
+        assert_eq!(image.crop(Rect::new(-2, -2, 100, 100)), image);
+        let rect = Rect::new(1, 1, 3, 2);
+        assert_eq!(image.crop(rect).transpose(), image.transpose().crop(Rect::new(1, 1, 2, 3)));
+        assert_eq!(image.crop(Rect::new(5, 0, 2, 2)).pixels().len(), 0);
+
+        let mut restored = Image::new(5, 3);
+        restored.blit(0, 0, &image.crop(Rect::new(0, 0, 2, 3)), Rect::of_size(2, 3));
+        restored.blit(2, 0, &image.crop(Rect::new(2, 0, 3, 3)), Rect::of_size(3, 3));
+        assert_eq!(restored, image);
+    }
+}
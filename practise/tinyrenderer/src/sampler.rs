@@ -0,0 +1,461 @@
+//!
+//! Texture sampling with nearest/bilinear filtering, mipmapping and OpenGL-like addressing modes.
+//! Texel (i, j) covers uv in [i / width, (i + 1) / width) x [j / height, (j + 1) / height).
+//!
+
+use crate::Vec2f;
+use crate::tga::{TgaImage, TgaColor};
+use crate::color::{Color, srgb_to_linear, linear_to_srgb};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+// How the mip levels are selected and blended, trilinear filtering is Bilinear with MipmapMode::Linear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MipmapMode {
+    None,    // always sample the base level
+    Nearest, // sample the nearest level
+    Linear,  // blend the two nearest levels
+}
+
+// How texel coordinates outside of the texture are resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressMode {
+    Repeat,         // tile the texture
+    MirroredRepeat, // tile the texture, flipping every other tile
+    ClampToEdge,    // use the nearest edge texel
+    ClampToBorder,  // use the border color of the sampler
+}
+
+impl AddressMode {
+
+    // Map a texel coordinate into [0, size), or None for the border.
+    pub(crate) fn resolve(&self, i: i64, size: i64) -> Option<i64> {
+        match self {
+            | AddressMode::Repeat => Some(i.rem_euclid(size)),
+            | AddressMode::MirroredRepeat => {
+                let i = i.rem_euclid(2 * size);
+                Some(if i < size { i } else { 2 * size - 1 - i })
+            },
+            | AddressMode::ClampToEdge => Some(i.clamp(0, size - 1)),
+            | AddressMode::ClampToBorder => if i >= 0 && i < size { Some(i) } else { None },
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub mipmap: MipmapMode,
+    pub address_u: AddressMode,
+    pub address_v: AddressMode,
+    pub border_color: TgaColor,
+    // largest number of probes taken along the major axis of the pixel footprint, 1.0 disables anisotropic filtering
+    pub max_anisotropy: f32,
+}
+
+impl Default for Sampler {
+
+    fn default() -> Sampler {
+        Sampler { mipmap: MipmapMode::Linear, ..Sampler::new(Filter::Bilinear, AddressMode::Repeat) }
+    }
+}
+
+impl Sampler {
+
+    pub fn new(filter: Filter, address: AddressMode) -> Sampler {
+        Sampler { filter, mipmap: MipmapMode::None, address_u: address, address_v: address, border_color: TgaColor::from_rgba(0, 0, 0, 0), max_anisotropy: 1.0 }
+    }
+
+    // Fetch texel (x, y) after applying the address modes, in R, G, B, A order and in [0, 1].
+    // sRGB encoded color channels are decoded to linear when `srgb` is set, alpha is always linear.
+    fn fetch(&self, image: &TgaImage, x: i64, y: i64, srgb: bool) -> [f32; 4] {
+
+        let location = self.address_u.resolve(x, image.width as i64)
+            .zip(self.address_v.resolve(y, image.height as i64))
+            .map(|(x, y)| (x + y * image.width as i64) as usize);
+
+        let rgba = match location {
+            | Some(location) => {
+                let bytes_per_pixel = image.format() as usize;
+                let data = &image.raw_data()[(location * bytes_per_pixel)..((location + 1) * bytes_per_pixel)];
+                match data.len() {
+                    | 1 => [data[0], data[0], data[0], 255],
+                    | 3 => [data[0], data[1], data[2], 255],
+                    | _ => [data[0], data[1], data[2], data[3]],
+                }
+            },
+            | None => self.border_color.rgba(),
+        };
+
+        decode(rgba, srgb)
+    }
+
+    pub fn sample(&self, image: &TgaImage, uv: Vec2f) -> TgaColor {
+        to_color(self.texel(image, uv, false))
+    }
+
+    // Sample a sRGB encoded texture, the texels are decoded to linear before being filtered.
+    pub fn sample_color(&self, image: &TgaImage, uv: Vec2f) -> Color {
+        to_linear(self.texel(image, uv, true))
+    }
+
+    // Sample the mip chain at the level of detail given by the derivatives of uv along the screen x and y axes.
+    // With anisotropic filtering, elongated footprints are covered by several probes along their major axis,
+    // each one sampled at the level of detail of the minor axis.
+    pub fn sample_grad(&self, mips: &MipChain, uv: Vec2f, duv_dx: Vec2f, duv_dy: Vec2f) -> TgaColor {
+        to_color(self.texel_grad(mips, uv, duv_dx, duv_dy, false))
+    }
+
+    // `sample_grad` for sRGB encoded textures, see `sample_color`.
+    pub fn sample_grad_color(&self, mips: &MipChain, uv: Vec2f, duv_dx: Vec2f, duv_dy: Vec2f) -> Color {
+        to_linear(self.texel_grad(mips, uv, duv_dx, duv_dy, true))
+    }
+
+    // Sample the mip chain at an explicit level of detail, 0 is the base level.
+    pub fn sample_lod(&self, mips: &MipChain, uv: Vec2f, lod: f32) -> TgaColor {
+        to_color(self.texel_lod(mips, uv, lod, false))
+    }
+
+    pub fn sample_lod_color(&self, mips: &MipChain, uv: Vec2f, lod: f32) -> Color {
+        to_linear(self.texel_lod(mips, uv, lod, true))
+    }
+
+    fn border(&self, srgb: bool) -> [f32; 4] {
+        decode(self.border_color.rgba(), srgb)
+    }
+
+    fn texel(&self, image: &TgaImage, uv: Vec2f, srgb: bool) -> [f32; 4] {
+
+        if image.width <= 0 || image.height <= 0 || !uv.x.is_finite() || !uv.y.is_finite() {
+            return self.border(srgb)
+        }
+        self.filter_texels(image, uv, srgb)
+    }
+
+    fn texel_grad(&self, mips: &MipChain, uv: Vec2f, duv_dx: Vec2f, duv_dy: Vec2f, srgb: bool) -> [f32; 4] {
+
+        if self.max_anisotropy <= 1.0 || self.mipmap == MipmapMode::None {
+            return self.texel_lod(mips, uv, mips.lod(duv_dx, duv_dy), srgb)
+        }
+        if !self.is_sampleable(mips, uv) {
+            return self.border(srgb)
+        }
+
+        // footprint axes in texels
+        let base = mips.level(0);
+        let size = Vec2f::new(base.width as f32, base.height as f32);
+        let (length_x, length_y) = ((duv_dx * size).magnitude(), (duv_dy * size).magnitude());
+        let (major_axis, major, minor) = if length_x >= length_y { (duv_dx, length_x, length_y) } else { (duv_dy, length_y, length_x) };
+        if !major.is_finite() || major <= 0.0 {
+            return self.texel_lod(mips, uv, 0.0, srgb)
+        }
+
+        let probes = (major / minor.max(f32::MIN_POSITIVE)).min(self.max_anisotropy).ceil().max(1.0) as usize;
+        let lod = (major / probes as f32).log2().max(0.0);
+
+        let mut texel = [0.0; 4];
+        for i in 0..probes {
+            // probes are spread evenly over the major axis, centered on uv
+            let offset = (i as f32 + 0.5) / probes as f32 - 0.5;
+            let probe = self.filter_lod(mips, uv + major_axis * offset, lod, srgb);
+            for c in 0..4 {
+                texel[c] += probe[c] / probes as f32;
+            }
+        }
+        texel
+    }
+
+    fn texel_lod(&self, mips: &MipChain, uv: Vec2f, lod: f32, srgb: bool) -> [f32; 4] {
+
+        if !self.is_sampleable(mips, uv) {
+            return self.border(srgb)
+        }
+        self.filter_lod(mips, uv, lod, srgb)
+    }
+
+    fn is_sampleable(&self, mips: &MipChain, uv: Vec2f) -> bool {
+        let base = mips.level(0);
+        base.width > 0 && base.height > 0 && uv.x.is_finite() && uv.y.is_finite()
+    }
+
+    fn filter_lod(&self, mips: &MipChain, uv: Vec2f, lod: f32, srgb: bool) -> [f32; 4] {
+
+        let max_level = (mips.len() - 1) as f32;
+        let lod = if lod.is_nan() { 0.0 } else { lod.clamp(0.0, max_level) };
+        match self.mipmap {
+            | MipmapMode::None => self.filter_texels(mips.level(0), uv, srgb),
+            | MipmapMode::Nearest => self.filter_texels(mips.level(lod.round() as usize), uv, srgb),
+            | MipmapMode::Linear => {
+                let level = lod.floor();
+                let t = lod - level;
+                let fine = self.filter_texels(mips.level(level as usize), uv, srgb);
+                if t == 0.0 {
+                    return fine
+                }
+                let coarse = self.filter_texels(mips.level(level as usize + 1), uv, srgb);
+                let mut texel = [0.0; 4];
+                for i in 0..4 {
+                    texel[i] = fine[i] + (coarse[i] - fine[i]) * t;
+                }
+                texel
+            },
+        }
+    }
+
+    // Filter the texels around uv, the image must not be empty.
+    fn filter_texels(&self, image: &TgaImage, uv: Vec2f, srgb: bool) -> [f32; 4] {
+
+        // far away coordinates are brought closer so that the texel indices can't overflow
+        const LIMIT: f32 = (1 << 30) as f32;
+        let x = (uv.x * image.width  as f32).clamp(-LIMIT, LIMIT);
+        let y = (uv.y * image.height as f32).clamp(-LIMIT, LIMIT);
+        match self.filter {
+            | Filter::Nearest => self.fetch(image, x.floor() as i64, y.floor() as i64, srgb),
+            | Filter::Bilinear => {
+                // interpolate between the centers of the 4 nearest texels
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let t00 = self.fetch(image, x0, y0, srgb);
+                let t10 = self.fetch(image, x0 + 1, y0, srgb);
+                let t01 = self.fetch(image, x0, y0 + 1, srgb);
+                let t11 = self.fetch(image, x0 + 1, y0 + 1, srgb);
+
+                let mut texel = [0.0; 4];
+                for i in 0..4 {
+                    let top    = t00[i] + (t10[i] - t00[i]) * tx;
+                    let bottom = t01[i] + (t11[i] - t01[i]) * tx;
+                    texel[i] = top + (bottom - top) * ty;
+                }
+                texel
+            },
+        }
+    }
+}
+
+fn decode(rgba: [u8; 4], srgb: bool) -> [f32; 4] {
+    let color = |v: u8| if srgb { srgb_to_linear(v) } else { v as f32 / 255.0 };
+    [color(rgba[0]), color(rgba[1]), color(rgba[2]), rgba[3] as f32 / 255.0]
+}
+
+fn to_color(texel: [f32; 4]) -> TgaColor {
+    let [r, g, b, a] = texel.map(|v| (v * 255.0 + 0.5).clamp(0.0, 255.0) as u8);
+    TgaColor::from_rgba(r, g, b, a)
+}
+
+fn to_linear(texel: [f32; 4]) -> Color {
+    Color::rgba(texel[0], texel[1], texel[2], texel[3])
+}
+
+
+// A texture and its successively halved copies, down to 1x1.
+#[derive(Debug, Clone)]
+pub struct MipChain {
+    levels: Vec<TgaImage>,
+}
+
+impl MipChain {
+
+    // Build the chain of a sRGB encoded texture with a 2x2 box filter, the last row/column of odd sized levels
+    // is folded into its neighbour. Colors are averaged in linear space, or the levels would darken, alpha as is.
+    pub fn new(base: &TgaImage) -> MipChain {
+        MipChain::build(base, true)
+    }
+
+    // Build the chain of a texture holding data rather than colors(e.g. normal or specular maps), averaging its bytes.
+    pub fn new_linear(base: &TgaImage) -> MipChain {
+        MipChain::build(base, false)
+    }
+
+    fn build(base: &TgaImage, srgb: bool) -> MipChain {
+
+        let mut levels = vec![base.clone()];
+        loop {
+            // an empty image has a single level, which samples as the border color
+            let previous = levels.last().unwrap();
+            if previous.width.max(previous.height) <= 1 || previous.width.min(previous.height) <= 0 {
+                break
+            }
+            levels.push(downsample(previous, srgb));
+        }
+        MipChain { levels }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    pub fn level(&self, i: usize) -> &TgaImage {
+        &self.levels[i.min(self.levels.len() - 1)]
+    }
+
+    // Level of detail for a pixel footprint, from the uv derivatives along the screen axes(OpenGL 4.6, 8.14.1).
+    pub fn lod(&self, duv_dx: Vec2f, duv_dy: Vec2f) -> f32 {
+        let base = self.level(0);
+        let size = Vec2f::new(base.width as f32, base.height as f32);
+        let rho = (duv_dx * size).magnitude().max((duv_dy * size).magnitude());
+        if rho > 0.0 { rho.log2().max(0.0) } else { 0.0 }
+    }
+}
+
+impl Default for MipChain {
+
+    fn default() -> MipChain {
+        MipChain { levels: vec![TgaImage::unset()] }
+    }
+}
+
+fn downsample(image: &TgaImage, srgb: bool) -> TgaImage {
+
+    let bytes_per_pixel = image.format() as usize;
+    // the fourth byte is alpha, which is always linear
+    let is_color = |c: usize| srgb && c < 3;
+    let decode = |v: u8, c: usize| if is_color(c) { srgb_to_linear(v) } else { v as f32 / 255.0 };
+    let encode = |v: f32, c: usize| if is_color(c) { linear_to_srgb(v) } else { (v * 255.0 + 0.5) as u8 };
+    let (width, height) = ((image.width / 2).max(1), (image.height / 2).max(1));
+    let data = image.raw_data();
+
+    let mut result = Vec::with_capacity((width * height) as usize * bytes_per_pixel);
+    for y in 0..height {
+        // source rows/columns covered by this texel, 3 of them for the last texel of an odd size
+        let rows = (2 * y)..(if y == height - 1 { image.height } else { 2 * y + 2 }).max(2 * y + 1);
+        for x in 0..width {
+            let columns = (2 * x)..(if x == width - 1 { image.width } else { 2 * x + 2 }).max(2 * x + 1);
+
+            let mut sum = [0.0_f32; 4];
+            let mut count = 0;
+            for j in rows.clone() {
+                for i in columns.clone() {
+                    let location = (i + j * image.width) as usize * bytes_per_pixel;
+                    for c in 0..bytes_per_pixel {
+                        sum[c] += decode(data[location + c], c);
+                    }
+                    count += 1;
+                }
+            }
+            result.extend((0..bytes_per_pixel).map(|c| encode(sum[c] / count as f32, c)));
+        }
+    }
+
+    TgaImage::from_raw_data(width, height, image.format(), result).expect("Pixel count must match the dimensions")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tga::TgaFormat;
+
+    fn checkerboard(width: i32, height: i32) -> TgaImage {
+        let mut image = TgaImage::new(width, height, TgaFormat::RGBA);
+        for y in 0..height {
+            for x in 0..width {
+                let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+                image.set(x, y, &TgaColor::from_rgba(v, v, v, v));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn keeps_a_single_level_for_empty_images() {
+        for &(width, height) in &[(0, 0), (0, 5), (7, 0), (-1, 3)] {
+            let mips = MipChain::new(&TgaImage::new(width, height, TgaFormat::RGB));
+            assert_eq!(mips.len(), 1, "{}x{}", width, height);
+            let texel = Sampler::default().sample_lod(&mips, Vec2f::new(0.5, 0.5), 2.0);
+            assert_eq!(texel.rgba(), [0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn halves_down_to_one_texel() {
+        let mips = MipChain::new(&checkerboard(5, 3));
+        let sizes: Vec<(i32, i32)> = (0..mips.len()).map(|i| (mips.level(i).width, mips.level(i).height)).collect();
+        assert_eq!(sizes, [(5, 3), (2, 1), (1, 1)]);
+        assert_eq!(MipChain::new(&checkerboard(1, 1)).len(), 1);
+    }
+
+    #[test]
+    fn averages_colors_in_linear_space() {
+        // half black and half white is a linear gray of 0.5, while alpha is averaged as is
+        let level = MipChain::new(&checkerboard(2, 2)).level(1).get(0, 0).unwrap();
+        assert_eq!(linear_to_srgb(0.5), 188);
+        assert_eq!(level.rgba(), [188, 188, 188, 128]);
+
+        let level = MipChain::new_linear(&checkerboard(2, 2)).level(1).get(0, 0).unwrap();
+        assert_eq!(level.rgba(), [128, 128, 128, 128]);
+    }
+
+    const ADDRESS_MODES: [AddressMode; 4] = [AddressMode::Repeat, AddressMode::MirroredRepeat, AddressMode::ClampToEdge, AddressMode::ClampToBorder];
+
+    fn numbered(width: i32, height: i32) -> TgaImage {
+        let mut image = TgaImage::new(width, height, TgaFormat::RGBA);
+        for y in 0..height {
+            for x in 0..width {
+                image.set(x, y, &TgaColor::from_rgba((x * 40) as u8, (y * 40) as u8, 200, 255));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn resolves_texel_coordinates() {
+        let n = 4;
+        let coordinates = [-1, 0, n - 1, n, 2 * n];
+        let resolve = |mode: AddressMode| coordinates.iter().map(|&i| mode.resolve(i, n)).collect::<Vec<_>>();
+        assert_eq!(resolve(AddressMode::Repeat), [Some(3), Some(0), Some(3), Some(0), Some(0)]);
+        assert_eq!(resolve(AddressMode::MirroredRepeat), [Some(0), Some(0), Some(3), Some(3), Some(0)]);
+        assert_eq!(resolve(AddressMode::ClampToEdge), [Some(0), Some(0), Some(3), Some(3), Some(3)]);
+        assert_eq!(resolve(AddressMode::ClampToBorder), [None, Some(0), Some(3), None, None]);
+    }
+
+    #[test]
+    fn samples_uvs_on_and_outside_of_the_edges() {
+        let image = numbered(3, 2);
+        let uvs = [(1.0, 1.0), (0.0, 1.0), (1.0, 0.0), (-3.7, 5.2), (2.0, -1.0), (1e30, -1e30), (f32::MAX, f32::MIN)];
+        for &filter in &[Filter::Nearest, Filter::Bilinear] {
+            for &mode in ADDRESS_MODES.iter() {
+                let sampler = Sampler::new(filter, mode);
+                for &(u, v) in uvs.iter() {
+                    sampler.sample(&image, Vec2f::new(u, v));
+                }
+            }
+        }
+
+        let at_one = |mode: AddressMode| Sampler::new(Filter::Nearest, mode).sample(&image, Vec2f::new(1.0, 1.0));
+        assert_eq!(at_one(AddressMode::Repeat), image.try_get(0, 0).unwrap());
+        assert_eq!(at_one(AddressMode::MirroredRepeat), image.try_get(2, 1).unwrap());
+        assert_eq!(at_one(AddressMode::ClampToEdge), image.try_get(2, 1).unwrap());
+        assert_eq!(at_one(AddressMode::ClampToBorder).rgba(), [0, 0, 0, 0]);
+
+        // tiles repeat the texture
+        let tiled = Sampler::new(Filter::Nearest, AddressMode::Repeat).sample(&image, Vec2f::new(-2.0 + 0.5, 3.0 + 0.75));
+        assert_eq!(tiled, image.try_get(1, 1).unwrap());
+    }
+
+    #[test]
+    fn bilinear_returns_the_texel_at_its_center() {
+        let image = numbered(3, 2);
+        for &mode in ADDRESS_MODES.iter() {
+            let sampler = Sampler::new(Filter::Bilinear, mode);
+            for y in 0..2 {
+                for x in 0..3 {
+                    let uv = Vec2f::new((x as f32 + 0.5) / 3.0, (y as f32 + 0.5) / 2.0);
+                    assert_eq!(sampler.sample(&image, uv), image.try_get(x, y).unwrap(), "{:?} at ({}, {})", mode, x, y);
+                }
+            }
+        }
+    }
+}
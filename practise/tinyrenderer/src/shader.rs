@@ -1,8 +1,14 @@
-
 use crate::{Vec3f, Vec4f};
 use crate::tga::TgaColor;
 
-pub trait IShader {
+// `C` is the color written by the fragment shader, e.g. `hdr::Rgbaf` to render into a HdrImage.
+pub trait IShader<C = TgaColor> {
     fn vertex(&mut self, vertex_idx: usize, nthvert: usize) -> Vec4f;
-    fn fragment(&self, barycentric: Vec3f) -> Option<TgaColor>;
+    fn fragment(&self, barycentric: Vec3f) -> Option<C>;
+
+    // Called by the rasterizer with the derivatives of the barycentric coordinates along the screen x and y axes,
+    // which give the uv derivatives for mipmapping(see `camera::sample_barycentric_uv`).
+    fn fragment_with_derivatives(&self, barycentric: Vec3f, _dbc_dx: Vec3f, _dbc_dy: Vec3f) -> Option<C> {
+        self.fragment(barycentric)
+    }
 }
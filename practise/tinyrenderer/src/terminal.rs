@@ -0,0 +1,243 @@
+//!
+//! Preview images in a terminal, e.g. to check renders over ssh without copying files around.
+//! Each character cell shows two pixels stacked vertically with the upper half block '▀',
+//! its foreground is the upper pixel and its background the lower one, both as 24-bit ANSI colors.
+//! Terminals without true color get an ASCII ramp of the luma instead.
+//!
+
+use std::io::Write;
+
+use crate::tga::{TgaImage, TgaColor, TgaFormat};
+use crate::resize::ResizeFilter;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewMode {
+    TrueColor, // two pixels per cell with 24-bit colors
+    Ascii,     // one pixel per cell from the grayscale ramp, with no escape sequences at all
+}
+
+impl PreviewMode {
+
+    // True color when the terminal advertises it through COLORTERM, ASCII otherwise.
+    pub fn detect() -> PreviewMode {
+        match std::env::var("COLORTERM").as_deref() {
+            | Ok("truecolor") | Ok("24bit") => PreviewMode::TrueColor,
+            | _ => PreviewMode::Ascii,
+        }
+    }
+}
+
+// From dark to light, for text on a dark background.
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+#[derive(Debug, Clone)]
+pub struct PreviewOptions {
+    pub mode: PreviewMode,
+    pub max_columns: i32,       // the image is downscaled to fit, never upscaled
+    pub max_rows: i32,
+    pub filter: ResizeFilter,   // used for the downscaling
+    pub background: TgaColor,   // transparent pixels are blended over it
+    pub invert: bool,           // reverse the ASCII ramp, for terminals with a light background
+}
+
+impl Default for PreviewOptions {
+
+    fn default() -> PreviewOptions {
+        PreviewOptions {
+            mode: PreviewMode::TrueColor,
+            max_columns: 80,
+            max_rows: 40,
+            filter: ResizeFilter::Bilinear,
+            background: TgaColor::from_rgb(0, 0, 0),
+            invert: false,
+        }
+    }
+}
+
+impl PreviewOptions {
+
+    // Detect the mode, and fit in the size given by the COLUMNS and LINES variables when the shell exports them.
+    pub fn for_terminal() -> PreviewOptions {
+        let size = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<i32>().ok()).filter(|&v| v > 0);
+        let default = PreviewOptions::default();
+        PreviewOptions {
+            mode: PreviewMode::detect(),
+            max_columns: size("COLUMNS").unwrap_or(default.max_columns),
+            // keep a line for the prompt
+            max_rows: size("LINES").map(|lines| (lines - 1).max(1)).unwrap_or(default.max_rows),
+            ..default
+        }
+    }
+
+    pub fn ascii() -> PreviewOptions {
+        PreviewOptions { mode: PreviewMode::Ascii, ..PreviewOptions::default() }
+    }
+
+    // Size in pixels the image is shown at. A cell is about twice as tall as wide, so in ASCII mode
+    // every other row is dropped to keep the aspect ratio, while half blocks already have square pixels.
+    fn fit(&self, width: i32, height: i32) -> (i32, i32) {
+        // (pixel rows per cell, pixel rows per image row)
+        let (cell_rows, aspect) = match self.mode {
+            | PreviewMode::TrueColor => (2.0, 1.0),
+            | PreviewMode::Ascii => (1.0, 0.5),
+        };
+        let scale = (self.max_columns.max(1) as f32 / width as f32)
+            .min(self.max_rows.max(1) as f32 * cell_rows / (height as f32 * aspect))
+            .min(1.0);
+        let width  = (width as f32 * scale).round() as i32;
+        let height = (height as f32 * scale * aspect).round() as i32;
+        (width.max(1), height.max(1))
+    }
+}
+
+// Write the preview of the image, each line ends with a newline.
+pub fn write_preview(mut writer: impl Write, image: &TgaImage, options: &PreviewOptions) -> std::io::Result<()> {
+
+    if image.width <= 0 || image.height <= 0 {
+        return Ok(())
+    }
+
+    let (width, height) = options.fit(image.width, image.height);
+    let resized;
+    let image = if (width, height) != (image.width, image.height) {
+        resized = image.resize(width, height, options.filter);
+        &resized
+    } else {
+        image
+    };
+    let pixel = |x: i32, y: i32| flatten(&image.get(x, y).expect("Pixel must be inside of the image"), &options.background);
+
+    let mut line = String::new();
+    match options.mode {
+        | PreviewMode::TrueColor => for y in (0..height).step_by(2) {
+            line.clear();
+            // only emit the colors that changed since the previous cell
+            let mut current: (Option<[u8; 3]>, Option<[u8; 3]>) = (None, None);
+            for x in 0..width {
+                let upper = pixel(x, y);
+                let lower = if y + 1 < height { Some(pixel(x, y + 1)) } else { None };
+                if current.0 != Some(upper) {
+                    line.push_str(&format!("\x1b[38;2;{};{};{}m", upper[0], upper[1], upper[2]));
+                }
+                match lower {
+                    | Some(lower) if current.1 != Some(lower) => line.push_str(&format!("\x1b[48;2;{};{};{}m", lower[0], lower[1], lower[2])),
+                    // the last row of an odd height keeps the terminal background below
+                    | None if x == 0 => line.push_str("\x1b[49m"),
+                    | _ => {},
+                }
+                current = (Some(upper), lower);
+                line.push('▀');
+            }
+            line.push_str("\x1b[0m\n");
+            writer.write_all(line.as_bytes())?;
+        },
+        | PreviewMode::Ascii => for y in 0..height {
+            line.clear();
+            for x in 0..width {
+                let [r, g, b] = pixel(x, y);
+                let luma = TgaColor::from_rgb(r, g, b).luma() as usize;
+                let level = luma * (ASCII_RAMP.len() - 1) / 255;
+                let level = if options.invert { ASCII_RAMP.len() - 1 - level } else { level };
+                line.push(ASCII_RAMP[level] as char);
+            }
+            line.push('\n');
+            writer.write_all(line.as_bytes())?;
+        },
+    }
+    writer.flush()
+}
+
+// The preview as a string, see `write_preview`.
+pub fn preview(image: &TgaImage, options: &PreviewOptions) -> String {
+    let mut buffer = Vec::new();
+    write_preview(&mut buffer, image, options).expect("Writing to memory can't fail");
+    String::from_utf8(buffer).expect("Preview must be valid utf-8")
+}
+
+// Print the preview to the standard output.
+pub fn print_preview(image: &TgaImage, options: &PreviewOptions) -> std::io::Result<()> {
+    write_preview(std::io::stdout().lock(), image, options)
+}
+
+// Blend the color over the background with its alpha, colors without alpha are opaque.
+fn flatten(color: &TgaColor, background: &TgaColor) -> [u8; 3] {
+    let [r, g, b] = color.rgb();
+    if color.format() != TgaFormat::RGBA {
+        return [r, g, b]
+    }
+    let alpha = color.a() as u32;
+    let blend = |c: u8, bg: u8| ((c as u32 * alpha + bg as u32 * (255 - alpha) + 127) / 255) as u8;
+    let [br, bg, bb] = background.rgb();
+    [blend(r, br), blend(g, bg), blend(b, bb)]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(left: TgaColor, right: TgaColor, height: i32) -> TgaImage {
+        let mut image = TgaImage::new(2, height, right.format());
+        for y in 0..height {
+            image.set(0, y, &left);
+            image.set(1, y, &right);
+        }
+        image
+    }
+
+    #[test]
+    fn draws_ascii_ramps() {
+        let image = columns(TgaColor::from_rgb(0, 0, 0), TgaColor::from_rgb(255, 255, 255), 2);
+        let options = PreviewOptions { filter: ResizeFilter::Nearest, ..PreviewOptions::ascii() };
+        // every other row is dropped to keep the aspect ratio of the cells
+        assert_eq!(preview(&image, &options), " @\n");
+        assert_eq!(preview(&image, &PreviewOptions { invert: true, ..options.clone() }), "@ \n");
+
+        // transparent pixels show the background
+        let gray = columns(TgaColor::from_greyscale(128), TgaColor::from_rgba(255, 255, 255, 0), 4);
+        assert_eq!(preview(&gray, &options), "= \n= \n");
+    }
+
+    #[test]
+    fn draws_half_blocks() {
+        let red = TgaColor::from_rgb(255, 0, 0);
+        let blue = TgaColor::from_rgb(0, 0, 255);
+        let mut image = columns(red.clone(), red.clone(), 2);
+        image.set(1, 1, &blue);
+        assert_eq!(preview(&image, &PreviewOptions::default()),
+            "\x1b[38;2;255;0;0m\x1b[48;2;255;0;0m▀\x1b[48;2;0;0;255m▀\x1b[0m\n");
+
+        // the last row of an odd height keeps the terminal background
+        let odd = columns(red.clone(), red, 3);
+        let text = preview(&odd, &PreviewOptions::default());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], "\x1b[38;2;255;0;0m\x1b[49m▀▀\x1b[0m");
+        assert!(text.ends_with("\x1b[49m▀▀\x1b[0m\n"));
+    }
+
+    #[test]
+    fn fits_without_upscaling() {
+        for &mode in &[PreviewMode::TrueColor, PreviewMode::Ascii] {
+            let options = PreviewOptions { mode, max_columns: 80, max_rows: 40, ..PreviewOptions::default() };
+            for &(width, height) in &[(1, 1), (3, 2), (80, 80), (800, 100), (10, 1000), (79, 3)] {
+                let (w, h) = options.fit(width, height);
+                assert!(w <= width && h <= height, "{:?} {}x{} fits as {}x{}", mode, width, height, w, h);
+                assert!((1..=80).contains(&w) && h >= 1);
+            }
+        }
+        assert_eq!(PreviewOptions::default().fit(3, 2), (3, 2));
+        assert_eq!(PreviewOptions::ascii().fit(3, 2), (3, 1));
+        assert_eq!(PreviewOptions::default().fit(800, 100), (80, 10));
+    }
+
+    #[test]
+    fn empty_images_have_no_preview() {
+        for &(width, height) in &[(0, 0), (0, 5), (5, 0), (-3, 2)] {
+            let image = TgaImage::new(width, height, TgaFormat::RGB);
+            assert_eq!(preview(&image, &PreviewOptions::default()), "");
+            assert_eq!(preview(&image, &PreviewOptions::ascii()), "");
+        }
+    }
+}
@@ -0,0 +1,610 @@
+//!
+//! Bitmap font text rendering for debug overlays(frame numbers, timings, ...).
+//! A 5x7 font covering printable ASCII is embedded, BDF(https://adobe-type-tools.github.io/font-tech-notes/pdfs/5005.BDF_Spec.pdf)
+//! and PSF 1/2(https://www.win.tue.nl/~aeb/linux/kbd/font-formats-1.html) fonts can be loaded from disk.
+//! Pixels are written with the clipping `RenderTarget::set`, so text may run off the image or be drawn into views.
+//!
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::image::Rect;
+use crate::tga::TgaColor;
+use crate::rasterization::RenderTarget;
+
+
+#[derive(Debug)]
+pub enum FontError {
+    InvalidFormat(&'static str),
+    Io(std::io::Error),
+}
+
+pub type FontResult<T> = Result<T, FontError>;
+
+impl std::fmt::Display for FontError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            | FontError::InvalidFormat(reason) => write!(f, "invalid font: {}", reason),
+            | FontError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for FontError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            | FontError::Io(e) => Some(e),
+            | _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for FontError {
+    fn from(e: std::io::Error) -> FontError {
+        FontError::Io(e)
+    }
+}
+
+impl From<FontError> for std::io::Error {
+    fn from(e: FontError) -> std::io::Error {
+        match e {
+            | FontError::Io(e) => e,
+            | _ => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Glyph {
+    pub width : i32,
+    pub height: i32,
+    pub x_offset: i32, // from the pen position to the left of the bitmap
+    pub y_offset: i32, // from the baseline up to the bottom of the bitmap
+    pub advance : i32, // pen movement after the glyph
+    bitmap: Vec<bool>, // row by row from the top-left corner
+}
+
+impl Glyph {
+
+    pub fn is_set(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height && self.bitmap[(x + y * self.width) as usize]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BitmapFont {
+    glyphs: Vec<Glyph>,
+    index: HashMap<char, usize>, // several characters may share a glyph, e.g. through a psf unicode table
+    pub ascent : i32, // pixels above the baseline
+    pub descent: i32, // pixels below the baseline
+}
+
+impl BitmapFont {
+
+    // The embedded 5x7 font, printable ASCII only.
+    pub fn embedded() -> &'static BitmapFont {
+        static FONT: std::sync::OnceLock<BitmapFont> = std::sync::OnceLock::new();
+        FONT.get_or_init(|| {
+            let glyphs = FONT_5X7.iter().map(|columns| {
+                let bitmap = (0..7).flat_map(|y| columns.iter().map(move |column| column >> y & 1 == 1)).collect();
+                Glyph { width: 5, height: 7, x_offset: 0, y_offset: 0, advance: 6, bitmap }
+            }).collect();
+            let index = (0..FONT_5X7.len()).map(|i| ((b' ' + i as u8) as char, i)).collect();
+            BitmapFont { glyphs, index, ascent: 7, descent: 1 }
+        })
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> FontResult<BitmapFont> {
+        BitmapFont::from_bytes(&std::fs::read(path)?)
+    }
+
+    // Load a PSF 1/2 or BDF font, told apart by their magic numbers.
+    pub fn from_bytes(bytes: &[u8]) -> FontResult<BitmapFont> {
+        if bytes.starts_with(&PSF1_MAGIC) || bytes.starts_with(&PSF2_MAGIC) {
+            BitmapFont::from_psf(bytes)
+        } else if bytes.starts_with(b"STARTFONT") {
+            BitmapFont::from_bdf(std::str::from_utf8(bytes).map_err(|_| FontError::InvalidFormat("bdf fonts must be text"))?)
+        } else {
+            Err(FontError::InvalidFormat("neither a psf nor a bdf font"))
+        }
+    }
+
+    pub fn from_bdf(source: &str) -> FontResult<BitmapFont> {
+
+        fn numbers<const N: usize>(fields: &[&str]) -> FontResult<[i32; N]> {
+            let mut values = [0; N];
+            for (value, field) in values.iter_mut().zip(fields.iter().skip(1)) {
+                *value = field.parse().map_err(|_| FontError::InvalidFormat("bad number in bdf font"))?;
+            }
+            if fields.len() <= N {
+                return Err(FontError::InvalidFormat("missing values in bdf font"))
+            }
+            Ok(values)
+        }
+
+        let mut glyphs = Vec::new();
+        let mut index = HashMap::new();
+        let (mut ascent, mut descent) = (None, None);
+        let mut bounding_box = [0; 4];
+
+        // state of the current glyph
+        let mut encoding: Option<i32> = None;
+        let mut advance = 0;
+        let mut bbx = [0; 4];
+        let mut bitmap_rows: Option<Vec<&str>> = None;
+
+        for line in source.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if let Some(rows) = bitmap_rows.as_mut() {
+                if fields.first() != Some(&"ENDCHAR") {
+                    rows.push(line.trim());
+                    continue
+                }
+            }
+            match fields.first().copied() {
+                | Some("FONTBOUNDINGBOX") => bounding_box = numbers::<4>(&fields)?,
+                | Some("FONT_ASCENT") => ascent = Some(numbers::<1>(&fields)?[0]),
+                | Some("FONT_DESCENT") => descent = Some(numbers::<1>(&fields)?[0]),
+                | Some("STARTCHAR") => {
+                    encoding = None;
+                    advance = bounding_box[0];
+                    bbx = bounding_box;
+                },
+                | Some("ENCODING") => encoding = Some(numbers::<1>(&fields)?[0]),
+                | Some("DWIDTH") => advance = numbers::<2>(&fields)?[0],
+                | Some("BBX") => bbx = numbers::<4>(&fields)?,
+                | Some("BITMAP") => bitmap_rows = Some(Vec::new()),
+                | Some("ENDCHAR") => {
+                    let rows = bitmap_rows.take().ok_or(FontError::InvalidFormat("ENDCHAR without BITMAP"))?;
+                    let [width, height, x_offset, y_offset] = bbx;
+                    if width < 0 || height < 0 || width > MAX_GLYPH_SIZE || height > MAX_GLYPH_SIZE {
+                        return Err(FontError::InvalidFormat("bad glyph bounding box"))
+                    }
+                    if rows.len() != height as usize {
+                        return Err(FontError::InvalidFormat("glyph bitmap doesn't match its bounding box"))
+                    }
+                    let mut bitmap = Vec::with_capacity(width as usize * height as usize);
+                    for row in rows {
+                        // each row is hex, padded to whole bytes, the leftmost pixel is the most significant bit
+                        let hex = |digit: u8| (digit as char).to_digit(16).map(|v| v as u8);
+                        let bytes: Vec<u8> = row.as_bytes().chunks_exact(2)
+                            .map(|pair| Some(hex(pair[0])? << 4 | hex(pair[1])?))
+                            .collect::<Option<_>>()
+                            .ok_or(FontError::InvalidFormat("bad hex in glyph bitmap"))?;
+                        bitmap.extend((0..width as usize).map(|x| bytes.get(x / 8).is_some_and(|b| b >> (7 - x % 8) & 1 == 1)));
+                    }
+                    // glyphs without an encoding(-1) or with an invalid code point are dropped
+                    if let Some(c) = encoding.and_then(|e| char::from_u32(e as u32)) {
+                        index.insert(c, glyphs.len());
+                        glyphs.push(Glyph { width, height, x_offset, y_offset, advance, bitmap });
+                    }
+                },
+                | _ => {},
+            }
+        }
+
+        if index.is_empty() {
+            return Err(FontError::InvalidFormat("bdf font has no glyphs"))
+        }
+        // without the properties, the font bounding box gives the extent around the baseline
+        let ascent = ascent.unwrap_or(bounding_box[1] + bounding_box[3]);
+        let descent = descent.unwrap_or(-bounding_box[3]);
+        Ok(BitmapFont { glyphs, index, ascent, descent })
+    }
+
+    pub fn from_psf(bytes: &[u8]) -> FontResult<BitmapFont> {
+
+        let truncated = FontError::InvalidFormat("psf font is truncated");
+        let u32_at = |offset: usize| bytes.get(offset..(offset + 4)).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+
+        // (glyph count, bytes per glyph, width, height, glyphs offset, unicode table)
+        let (count, glyph_size, width, height, offset, unicode) = if bytes.starts_with(&PSF1_MAGIC) {
+            let mode = *bytes.get(2).ok_or(truncated)?;
+            let height = *bytes.get(3).ok_or(FontError::InvalidFormat("psf font is truncated"))? as usize;
+            let count = if mode & 0x01 != 0 { 512 } else { 256 };
+            (count, height, 8, height, 4, if mode & 0x06 != 0 { Some(PsfUnicode::Psf1) } else { None })
+        } else {
+            let field = |i: usize| u32_at(4 * i).ok_or(FontError::InvalidFormat("psf font is truncated"));
+            let (header_size, flags, count, glyph_size, height, width) = (field(2)?, field(3)?, field(4)?, field(5)?, field(6)?, field(7)?);
+            let min_glyph_size = height.checked_mul(width.div_ceil(8)).ok_or(FontError::InvalidFormat("bad psf glyph dimensions"))?;
+            if glyph_size < min_glyph_size {
+                return Err(FontError::InvalidFormat("psf glyphs are smaller than their dimensions"))
+            }
+            (count, glyph_size, width, height, header_size, if flags & 0x01 != 0 { Some(PsfUnicode::Psf2) } else { None })
+        };
+
+        // empty glyphs would let any count pass the size check below
+        if width == 0 || height == 0 || glyph_size == 0 || width > MAX_GLYPH_SIZE as usize || height > MAX_GLYPH_SIZE as usize {
+            return Err(FontError::InvalidFormat("bad psf glyph dimensions"))
+        }
+        let glyph_data = count.checked_mul(glyph_size)
+            .and_then(|size| offset.checked_add(size))
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or(FontError::InvalidFormat("psf font is truncated"))?;
+
+        // without a table, glyphs are indexed by their Latin-1 code
+        let count = if unicode.is_some() { count } else { count.min(256) };
+        let row_bytes = width.div_ceil(8);
+        let glyphs = glyph_data.chunks_exact(glyph_size).take(count).map(|data| {
+            let bitmap = (0..height).flat_map(|y| (0..width).map(move |x| data[y * row_bytes + x / 8] >> (7 - x % 8) & 1 == 1)).collect();
+            Glyph { width: width as i32, height: height as i32, x_offset: 0, y_offset: 0, advance: width as i32, bitmap }
+        }).collect();
+
+        let index = match unicode {
+            | Some(format) => {
+                let table = &bytes[(offset + glyph_data.len())..];
+                format.parse(table, count).into_iter().enumerate()
+                    .flat_map(|(i, code_points)| code_points.into_iter().map(move |c| (c, i)))
+                    .collect()
+            },
+            | None => (0..count).map(|i| (i as u8 as char, i)).collect(),
+        };
+
+        // the whole cell is above the baseline
+        Ok(BitmapFont { glyphs, index, ascent: height as i32, descent: 0 })
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.index.get(&c).map(|&i| &self.glyphs[i])
+    }
+
+    pub fn line_height(&self) -> i32 {
+        self.ascent + self.descent
+    }
+
+    // Missing characters are drawn as '?' when the font has it, or skipped.
+    fn glyph_or_fallback(&self, c: char) -> Option<&Glyph> {
+        self.glyph(c).or_else(|| self.glyph('?'))
+    }
+
+    // Size of the text in pixels at the given scale, lines are separated by '\n'.
+    pub fn measure(&self, text: &str, scale: i32) -> (i32, i32) {
+        let width = text.lines().map(|line| line.chars().filter_map(|c| self.glyph_or_fallback(c)).map(|g| g.advance).sum::<i32>()).max().unwrap_or(0);
+        let lines = text.split('\n').count() as i32;
+        (width * scale, lines * self.line_height() * scale)
+    }
+}
+
+// Larger BDF bounding boxes are rejected rather than allocated, no bitmap font comes close.
+const MAX_GLYPH_SIZE: i32 = 1024;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+
+#[derive(Debug, Clone, Copy)]
+enum PsfUnicode {
+    Psf1, // little endian u16 code points, 0xfffe starts sequences, 0xffff ends the glyph
+    Psf2, // utf-8 code points, 0xfe starts sequences, 0xff ends the glyph
+}
+
+impl PsfUnicode {
+
+    // The code points of each glyph, multi code point sequences are skipped.
+    fn parse(&self, table: &[u8], count: usize) -> Vec<Vec<char>> {
+        // each entry takes at least one byte, a bad count must not size the allocation
+        let mut result = Vec::with_capacity(count.min(table.len()));
+        let mut rest = table;
+        while result.len() < count && !rest.is_empty() {
+            let mut code_points = Vec::new();
+            let mut in_sequence = false;
+            match self {
+                | PsfUnicode::Psf1 => {
+                    let mut entries = rest.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+                    let mut consumed = 0;
+                    for entry in entries.by_ref() {
+                        consumed += 2;
+                        match entry {
+                            | 0xffff => break,
+                            | 0xfffe => in_sequence = true,
+                            | _ if !in_sequence => code_points.extend(char::from_u32(entry as u32)),
+                            | _ => {},
+                        }
+                    }
+                    rest = &rest[consumed.min(rest.len())..];
+                },
+                | PsfUnicode::Psf2 => {
+                    let end = rest.iter().position(|&b| b == 0xff).unwrap_or(rest.len());
+                    let entry = &rest[..end];
+                    let singles = entry.split(|&b| b == 0xfe).next().unwrap_or(&[]);
+                    code_points.extend(String::from_utf8_lossy(singles).chars().filter(|&c| c != char::REPLACEMENT_CHARACTER));
+                    rest = &rest[(end + 1).min(rest.len())..];
+                },
+            }
+            result.push(code_points);
+        }
+        result
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct TextStyle {
+    pub color: TgaColor,
+    pub scale: i32,                  // each font pixel covers scale x scale image pixels
+    pub background: Option<TgaColor>, // filled box behind the text
+    pub padding: i32,                // around the text inside of the background box, in image pixels
+}
+
+impl Default for TextStyle {
+
+    fn default() -> TextStyle {
+        TextStyle { color: TgaColor::from_rgb(255, 255, 255), scale: 1, background: None, padding: 2 }
+    }
+}
+
+impl TextStyle {
+
+    pub fn new(color: TgaColor) -> TextStyle {
+        TextStyle { color, ..TextStyle::default() }
+    }
+}
+
+// Draw text with the top-left corner of its first line at (x, y), lines are separated by '\n'.
+// Return the covered rectangle, including the background box, before clipping.
+pub fn draw_text(image: &mut impl RenderTarget<TgaColor>, x: i32, y: i32, text: &str, font: &BitmapFont, style: &TextStyle) -> Rect {
+
+    let scale = style.scale.max(1);
+    let (width, height) = font.measure(text, scale);
+    let mut area = Rect::new(x, y, width, height);
+
+    if let Some(background) = &style.background {
+        area = Rect::new(x - style.padding, y - style.padding, width + 2 * style.padding, height + 2 * style.padding);
+        fill(image, area, background);
+    }
+
+    for (i, line) in text.split('\n').enumerate() {
+        let baseline = y + (i as i32 * font.line_height() + font.ascent) * scale;
+        let mut pen = x;
+        for c in line.chars() {
+            let Some(glyph) = font.glyph_or_fallback(c) else {
+                continue
+            };
+            let top = baseline - (glyph.y_offset + glyph.height) * scale;
+            let left = pen + glyph.x_offset * scale;
+            for gy in 0..glyph.height {
+                for gx in 0..glyph.width {
+                    if glyph.is_set(gx, gy) {
+                        fill(image, Rect::new(left + gx * scale, top + gy * scale, scale, scale), &style.color);
+                    }
+                }
+            }
+            pen += glyph.advance * scale;
+        }
+    }
+    area
+}
+
+fn fill(image: &mut impl RenderTarget<TgaColor>, rect: Rect, color: &TgaColor) {
+    for y in rect.y..rect.bottom() {
+        for x in rect.x..rect.right() {
+            image.set(x, y, color);
+        }
+    }
+}
+
+
+// Classic 5x7 LCD font for ' '..='~', one byte per column from left to right, the least significant bit is the top row.
+#[rustfmt::skip]
+const FONT_5X7: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5f, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7f, 0x14, 0x7f, 0x14], // ' ' ! " #
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], // $ % & '
+    [0x00, 0x1c, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1c, 0x00], [0x14, 0x08, 0x3e, 0x08, 0x14], [0x08, 0x08, 0x3e, 0x08, 0x08], // ( ) * +
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02], // , - . /
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], [0x00, 0x42, 0x7f, 0x40, 0x00], [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4b, 0x31], // 0 1 2 3
+    [0x18, 0x14, 0x12, 0x7f, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39], [0x3c, 0x4a, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03], // 4 5 6 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1e], [0x00, 0x36, 0x36, 0x00, 0x00], [0x00, 0x56, 0x36, 0x00, 0x00], // 8 9 : ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14], [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], // < = > ?
+    [0x32, 0x49, 0x79, 0x41, 0x3e], [0x7e, 0x11, 0x11, 0x11, 0x7e], [0x7f, 0x49, 0x49, 0x49, 0x36], [0x3e, 0x41, 0x41, 0x41, 0x22], // @ A B C
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], [0x7f, 0x49, 0x49, 0x49, 0x41], [0x7f, 0x09, 0x09, 0x09, 0x01], [0x3e, 0x41, 0x49, 0x49, 0x7a], // D E F G
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], [0x00, 0x41, 0x7f, 0x41, 0x00], [0x20, 0x40, 0x41, 0x3f, 0x01], [0x7f, 0x08, 0x14, 0x22, 0x41], // H I J K
+    [0x7f, 0x40, 0x40, 0x40, 0x40], [0x7f, 0x02, 0x0c, 0x02, 0x7f], [0x7f, 0x04, 0x08, 0x10, 0x7f], [0x3e, 0x41, 0x41, 0x41, 0x3e], // L M N O
+    [0x7f, 0x09, 0x09, 0x09, 0x06], [0x3e, 0x41, 0x51, 0x21, 0x5e], [0x7f, 0x09, 0x19, 0x29, 0x46], [0x46, 0x49, 0x49, 0x49, 0x31], // P Q R S
+    [0x01, 0x01, 0x7f, 0x01, 0x01], [0x3f, 0x40, 0x40, 0x40, 0x3f], [0x1f, 0x20, 0x40, 0x20, 0x1f], [0x3f, 0x40, 0x38, 0x40, 0x3f], // T U V W
+    [0x63, 0x14, 0x08, 0x14, 0x63], [0x07, 0x08, 0x70, 0x08, 0x07], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7f, 0x41, 0x41, 0x00], // X Y Z [
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7f, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40], // \ ] ^ _
+    [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78], [0x7f, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], // ` a b c
+    [0x38, 0x44, 0x44, 0x48, 0x7f], [0x38, 0x54, 0x54, 0x54, 0x18], [0x08, 0x7e, 0x09, 0x01, 0x02], [0x0c, 0x52, 0x52, 0x52, 0x3e], // d e f g
+    [0x7f, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7d, 0x40, 0x00], [0x20, 0x40, 0x44, 0x3d, 0x00], [0x7f, 0x10, 0x28, 0x44, 0x00], // h i j k
+    [0x00, 0x41, 0x7f, 0x40, 0x00], [0x7c, 0x04, 0x18, 0x04, 0x78], [0x7c, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], // l m n o
+    [0x7c, 0x14, 0x14, 0x14, 0x08], [0x08, 0x14, 0x14, 0x18, 0x7c], [0x7c, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20], // p q r s
+    [0x04, 0x3f, 0x44, 0x40, 0x20], [0x3c, 0x40, 0x40, 0x20, 0x7c], [0x1c, 0x20, 0x40, 0x20, 0x1c], [0x3c, 0x40, 0x30, 0x40, 0x3c], // t u v w
+    [0x44, 0x28, 0x10, 0x28, 0x44], [0x0c, 0x50, 0x50, 0x50, 0x3c], [0x44, 0x64, 0x54, 0x4c, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], // x y z {
+    [0x00, 0x00, 0x7f, 0x00, 0x00], [0x00, 0x41, 0x36, 0x08, 0x00], [0x08, 0x04, 0x08, 0x10, 0x08],                                 // | } ~
+];
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tga::{TgaImage, TgaFormat};
+
+    const BDF: &str = "STARTFONT 2.1
+FONT -test-
+SIZE 8 75 75
+FONTBOUNDINGBOX 4 6 0 -1
+STARTPROPERTIES 2
+FONT_ASCENT 5
+FONT_DESCENT 1
+ENDPROPERTIES
+CHARS 2
+STARTCHAR A
+ENCODING 65
+SWIDTH 500 0
+DWIDTH 5 0
+BBX 3 4 1 0
+BITMAP
+40
+A0
+E0
+A0
+ENDCHAR
+STARTCHAR unencoded
+ENCODING -1
+BBX 1 1 0 0
+BITMAP
+80
+ENDCHAR
+ENDFONT
+";
+
+    fn rows(glyph: &Glyph) -> Vec<String> {
+        (0..glyph.height).map(|y| (0..glyph.width).map(|x| if glyph.is_set(x, y) { '#' } else { '.' }).collect()).collect()
+    }
+
+    #[test]
+    fn parses_bdf() {
+        let font = BitmapFont::from_bytes(BDF.as_bytes()).unwrap();
+        assert_eq!((font.ascent, font.descent), (5, 1));
+        assert_eq!(font.index.len(), 1);
+
+        let glyph = font.glyph('A').unwrap();
+        assert_eq!((glyph.width, glyph.height, glyph.x_offset, glyph.y_offset, glyph.advance), (3, 4, 1, 0, 5));
+        assert_eq!(rows(glyph), [".#.", "#.#", "###", "#.#"]);
+
+        // the font bounding box stands in for the missing properties
+        let font = BitmapFont::from_bdf(&BDF.replace("FONT_ASCENT 5\n", "").replace("FONT_DESCENT 1\n", "")).unwrap();
+        assert_eq!((font.ascent, font.descent), (5, 1));
+    }
+
+    #[test]
+    fn rejects_malformed_bdf() {
+        let malformed = [
+            BDF.replace("E0\n", "Aé\n"),
+            BDF.replace("E0\n", "é0\n"),
+            BDF.replace("E0\n", "G0\n"),
+            BDF.replace("E0\n", ""),
+            BDF.replace("BBX 3 4 1 0", "BBX 2147483647 4 1 0"),
+            BDF.replace("BBX 3 4 1 0", "BBX 100000 100000 1 0"),
+            BDF.replace("BBX 3 4 1 0", "BBX 3 -4 1 0"),
+            BDF.replace("BBX 3 4 1 0", "BBX 3 4"),
+            BDF.replace("ENCODING 65", "ENCODING -1"),
+        ];
+        for source in malformed.iter() {
+            assert!(matches!(BitmapFont::from_bdf(source), Err(FontError::InvalidFormat(_))), "{}", source);
+        }
+    }
+
+    fn psf1(mode: u8, unicode_table: &[u16]) -> Vec<u8> {
+        let count = if mode & 0x01 != 0 { 512 } else { 256 };
+        let mut bytes = vec![0x36, 0x04, mode, 8];
+        for i in 0..count {
+            let glyph: [u8; 8] = if i == 65 { [0x18, 0x24, 0x42, 0x7e, 0x42, 0x42, 0x00, 0x00] } else { [i as u8; 8] };
+            bytes.extend_from_slice(&glyph);
+        }
+        bytes.extend(unicode_table.iter().flat_map(|v| v.to_le_bytes().to_vec()));
+        bytes
+    }
+
+    #[test]
+    fn parses_psf1() {
+        let font = BitmapFont::from_bytes(&psf1(0, &[])).unwrap();
+        assert_eq!((font.ascent, font.descent), (8, 0));
+        let glyph = font.glyph('A').unwrap();
+        assert_eq!((glyph.width, glyph.height, glyph.advance), (8, 8, 8));
+        assert_eq!(rows(glyph)[..4], ["...##...", "..#..#..", ".#....#.", ".######."]);
+        // without a unicode table, glyphs are Latin-1
+        assert_eq!(rows(font.glyph('é').unwrap())[0], "###.#..#");
+
+        // glyph 0 is '€' and 'E', glyph 1 only a sequence, glyph 2 is 'A'
+        let font = BitmapFont::from_bytes(&psf1(0x02, &[0x20ac, 0x45, 0xffff, 0xfffe, 0x41, 0x301, 0xffff, 0x41, 0xffff])).unwrap();
+        assert_eq!(font.index.len(), 3);
+        assert!(font.glyph('€').is_some() && font.glyph('E').is_some());
+        assert_eq!(rows(font.glyph('A').unwrap())[0], "......#.");
+
+        assert!(BitmapFont::from_bytes(&psf1(0, &[])[..1000]).is_err());
+        assert!(BitmapFont::from_psf(&[0x36, 0x04]).is_err());
+    }
+
+    #[test]
+    fn parses_psf2() {
+        // version, header size, flags(unicode table), count, bytes per glyph, height, width
+        let mut bytes = psf2_header([0, 32, 1, 2, 6, 3, 10]);
+        bytes.extend_from_slice(&[0xff, 0xc0, 0x80, 0x40, 0xff, 0xc0]);
+        bytes.extend_from_slice(&[0; 6]);
+        bytes.extend_from_slice("ß".as_bytes());
+        bytes.extend_from_slice(&[0xfe, b'x', 0xff, b'y', 0xff]);
+
+        let font = BitmapFont::from_bytes(&bytes).unwrap();
+        assert_eq!(font.index.len(), 2);
+        assert_eq!(rows(font.glyph('ß').unwrap()), ["##########", "#........#", "##########"]);
+        assert_eq!(rows(font.glyph('y').unwrap())[0], "..........");
+        assert!(font.glyph('x').is_none());
+
+        assert!(BitmapFont::from_bytes(&bytes[..40]).is_err());
+        // glyphs must hold their rows
+        bytes[20] = 5;
+        assert!(BitmapFont::from_bytes(&bytes).is_err());
+    }
+
+    fn psf2_header(fields: [u32; 7]) -> Vec<u8> {
+        let mut bytes = vec![0x72, 0xb5, 0x4a, 0x86];
+        for &v in &fields {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn rejects_malformed_psf() {
+        // a huge count with empty glyphs, which used to size the unicode table allocation
+        let mut bytes = psf2_header([0, 32, 1, 0xffff_ffff, 0, 0, 0]);
+        bytes.extend_from_slice(&[0; 6]);
+        assert!(matches!(BitmapFont::from_bytes(&bytes), Err(FontError::InvalidFormat(_))));
+
+        for &(glyph_size, height, width) in &[(0, 1, 8), (1, 0, 8), (1, 1, 0), (0, 0, 0), (u32::MAX, u32::MAX, u32::MAX)] {
+            let mut bytes = psf2_header([0, 32, 0, 1, glyph_size, height, width]);
+            bytes.extend_from_slice(&[0xff; 64]);
+            assert!(BitmapFont::from_bytes(&bytes).is_err(), "{}x{} in {} bytes", width, height, glyph_size);
+        }
+        // more glyphs than the data holds
+        let mut bytes = psf2_header([0, 32, 0, 0xffff_ffff, 1, 1, 8]);
+        bytes.extend_from_slice(&[0xff; 64]);
+        assert!(BitmapFont::from_bytes(&bytes).is_err());
+
+        assert!(BitmapFont::from_bytes(&[0x36, 0x04, 0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn shares_glyphs_between_code_points() {
+        let mut bytes = psf2_header([0, 32, 1, 1, 1, 1, 8]);
+        bytes.push(0x80);
+        // a long table mapping many characters to the only glyph
+        let characters: String = ('a'..='z').chain('A'..='Z').collect();
+        bytes.extend_from_slice(characters.as_bytes());
+        bytes.push(0xff);
+
+        let font = BitmapFont::from_bytes(&bytes).unwrap();
+        assert_eq!((font.glyphs.len(), font.index.len()), (1, 52));
+        assert!(font.glyph('q').unwrap().is_set(0, 0));
+        assert!(std::ptr::eq(font.glyph('a').unwrap(), font.glyph('Z').unwrap()));
+    }
+
+    #[test]
+    fn clips_text_to_the_image() {
+        let font = BitmapFont::embedded();
+        let style = TextStyle { scale: 2, background: Some(TgaColor::from_rgb(0, 0, 255)), ..TextStyle::default() };
+        let text = "Hi!\nclip";
+
+        let mut full = TgaImage::new(80, 60, TgaFormat::RGB);
+        let area = draw_text(&mut full, 20, 15, text, font, &style);
+        let (width, height) = font.measure(text, 2);
+        assert_eq!(area, Rect::new(18, 13, width + 4, height + 4));
+
+        // the same text partly outside of smaller images matches the corresponding part of the full one
+        for &(x, y, w, h) in &[(25, 20, 10, 8), (0, 0, 22, 16), (40, 30, 40, 30), (19, 14, 1, 1)] {
+            let mut clipped = TgaImage::new(w, h, TgaFormat::RGB);
+            let area = draw_text(&mut clipped, 20 - x, 15 - y, text, font, &style);
+            assert_eq!(area, Rect::new(18 - x, 13 - y, width + 4, height + 4));
+            assert_eq!(clipped.raw_data(), full.crop(Rect::new(x, y, w, h)).raw_data(), "{:?}", (x, y, w, h));
+        }
+
+        // nothing is drawn far away
+        let mut image = TgaImage::new(8, 8, TgaFormat::RGB);
+        draw_text(&mut image, -1000, 1000, text, font, &style);
+        draw_text(&mut image, i32::MAX / 4, -5, text, font, &TextStyle::default());
+        assert!(image.raw_data().iter().all(|&v| v == 0));
+    }
+}
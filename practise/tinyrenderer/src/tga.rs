@@ -1,12 +1,79 @@
 
 use std::ops::{Index, IndexMut, Mul};
+use std::collections::HashMap;
 use std::path::Path;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{Read, Write, Cursor};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use itertools::iproduct;
 
+use crate::image::{Image, Pixel, Rect, Luma8, Rgb8, Rgba8};
+use crate::resize::{ResizeFilter, resize_image};
+
+
+#[derive(Debug)]
+pub enum TgaError {
+    TruncatedHeader,
+    TruncatedData,
+    UnsupportedDatatype(u8),
+    UnsupportedPixelDepth(u8), // bits per pixel
+    BadDimensions { width: i32, height: i32 },
+    RleOverflow { pixel_count: usize }, // the run-length packets describe more pixels than the image has
+    InvalidColorMap(&'static str),
+    InvalidMetadata(&'static str),
+    UnsupportedEncoding(&'static str),
+    OutOfBounds { x: i32, y: i32 },
+    Io(std::io::Error),
+}
+
+pub type TgaResult<T> = Result<T, TgaError>;
+
+impl std::fmt::Display for TgaError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            | TgaError::TruncatedHeader => write!(f, "tga header is truncated"),
+            | TgaError::TruncatedData => write!(f, "pixel data is truncated"),
+            | TgaError::UnsupportedDatatype(code) => write!(f, "unsupported tga datatype({})", code),
+            | TgaError::UnsupportedPixelDepth(bits) => write!(f, "unsupported pixel depth({} bits)", bits),
+            | TgaError::BadDimensions { width, height } => write!(f, "bad image dimensions({}x{})", width, height),
+            | TgaError::RleOverflow { pixel_count } => write!(f, "run-length data overflows the image of {} pixels", pixel_count),
+            | TgaError::InvalidColorMap(reason) => write!(f, "invalid color map: {}", reason),
+            | TgaError::InvalidMetadata(reason) => write!(f, "invalid metadata: {}", reason),
+            | TgaError::UnsupportedEncoding(reason) => write!(f, "unsupported encoding: {}", reason),
+            | TgaError::OutOfBounds { x, y } => write!(f, "pixel location({}, {}) is out of bound", x, y),
+            | TgaError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TgaError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            | TgaError::Io(e) => Some(e),
+            | _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for TgaError {
+    fn from(e: std::io::Error) -> TgaError {
+        TgaError::Io(e)
+    }
+}
+
+// So that tga errors can still be propagated by the functions returning `std::io::Result`.
+impl From<TgaError> for std::io::Error {
+    fn from(e: TgaError) -> std::io::Error {
+        match e {
+            | TgaError::Io(e) => e,
+            | _ => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
 
 // #[repr(packed)] is equivalent to #[repr(packed(1))]
 #[repr(packed)]
@@ -69,7 +136,341 @@ impl TgaHeader {
 }
 
 
+// The palette of a color-mapped image, entries keep the byte order of the pixel data(BGR/BGRA).
+struct TgaColorMap {
+    origin: usize,
+    bytes_per_entry: usize,
+    entries: Vec<u8>,
+}
+
+impl TgaColorMap {
+
+    fn empty() -> TgaColorMap {
+        TgaColorMap { origin: 0, bytes_per_entry: 0, entries: vec![] }
+    }
+
+    fn read_colormap(reader: &mut impl Read, header: &TgaHeader) -> TgaResult<TgaColorMap> {
+
+        let origin = header.colormap_origin as u16 as usize;
+        let length = header.colormap_length as u16 as usize;
+        let bytes_per_entry = (header.colormap_depth as usize + 7) >> 3;
+
+        let mut entries = vec![0; length * bytes_per_entry];
+        reader.read_exact(&mut entries)?;
+
+        // 15/16-bit palettes are expanded right away, so that the image data expands to a format we can store
+        if bytes_per_entry == 2 {
+            let with_alpha = header.image_descriptor & 0x0f != 0;
+            let entries = high_color_to_true_color(&entries, with_alpha);
+            let bytes_per_entry = if with_alpha { TgaFormat::RGBA } else { TgaFormat::RGB } as usize;
+            return Ok(TgaColorMap { origin, bytes_per_entry, entries })
+        }
+
+        Ok(TgaColorMap { origin, bytes_per_entry, entries })
+    }
+
+    // Build a palette from the distinct colors of `data`, return the palette and the index of each pixel.
+    fn build(data: &[u8], bytes_per_pixel: usize) -> TgaResult<(TgaColorMap, Vec<u8>)> {
+
+        const MAX_COLORMAP_LENGTH: usize = 256;
+        let mut lookup: HashMap<&[u8], usize> = HashMap::new();
+        let mut entries = Vec::new();
+        let mut indices = Vec::with_capacity(data.len() / bytes_per_pixel);
+
+        for pixel in data.chunks_exact(bytes_per_pixel) {
+            let next_index = lookup.len();
+            let index = *lookup.entry(pixel).or_insert(next_index);
+            if index == next_index {
+                if index >= MAX_COLORMAP_LENGTH {
+                    return Err(TgaError::UnsupportedEncoding("too many colors for a color-mapped image"))
+                }
+                entries.extend_from_slice(pixel);
+            }
+            indices.push(index as u8);
+        }
+
+        Ok((TgaColorMap { origin: 0, bytes_per_entry: bytes_per_pixel, entries }, indices))
+    }
+
+    fn length(&self) -> usize {
+        self.entries.len().checked_div(self.bytes_per_entry).unwrap_or(0)
+    }
+
+    // Replace each color map index(1 or 2 bytes, little endian) by its palette entry.
+    fn expand(&self, indices: &[u8], index_bytes: usize) -> TgaResult<Vec<u8>> {
+
+        if self.bytes_per_entry != TgaFormat::RGB as usize && self.bytes_per_entry != TgaFormat::RGBA as usize {
+            return Err(TgaError::InvalidColorMap("unsupported color map depth"))
+        }
+
+        let mut data = Vec::with_capacity(indices.len() / index_bytes * self.bytes_per_entry);
+        for index in indices.chunks_exact(index_bytes) {
+            let index = if index_bytes == 1 { index[0] as usize } else { u16::from_le_bytes([index[0], index[1]]) as usize };
+            let entry = index.checked_sub(self.origin)
+                .filter(|&entry| entry < self.length())
+                .ok_or(TgaError::InvalidColorMap("color map index is out of range"))?;
+
+            let location = entry * self.bytes_per_entry;
+            data.extend_from_slice(&self.entries[location..(location + self.bytes_per_entry)]);
+        }
+
+        Ok(data)
+    }
+}
+
+
+const TGA_SIGNATURE: &[u8; 18] = b"TRUEVISION-XFILE.\0";
+const EXTENSION_AREA_SIZE: u16 = 495;
+
+// Return the offsets of the extension area and developer directory if the data ends with a TGA 2.0 footer.
+fn read_footer(bytes: &[u8]) -> Option<(u32, u32)> {
+
+    let footer = bytes.len().checked_sub(26).map(|start| &bytes[start..])?;
+    if &footer[8..] != TGA_SIGNATURE {
+        return None
+    }
+
+    let extension_offset = u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]]);
+    let developer_offset = u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]);
+    Some((extension_offset, developer_offset))
+}
+
+// Strings of the extension area are stored in fixed size fields and terminated by '\0'.
+fn read_fixed_string(reader: &mut impl Read, field_size: usize) -> std::io::Result<String> {
+
+    let mut field = vec![0; field_size];
+    reader.read_exact(&mut field)?;
+    let end = field.iter().position(|&c| c == 0).unwrap_or(field_size);
+    Ok(String::from_utf8_lossy(&field[..end]).into_owned())
+}
+
+fn write_fixed_string(writer: &mut impl Write, s: &str, field_size: usize) -> std::io::Result<()> {
+
+    // keep at least one byte for the terminator
+    let length = s.len().min(field_size - 1);
+    writer.write_all(&s.as_bytes()[..length])?;
+    writer.write_all(&vec![0; field_size - length])
+}
+
+
+// Optional data of a tga file besides its pixels.
+#[derive(Debug, Clone, Default)]
+pub struct TgaMetadata {
+    pub image_id: Vec<u8>, // at most 255 bytes
+    pub extension: Option<Box<TgaExtension>>,
+    pub developer_fields: Vec<TgaDeveloperField>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TgaTimestamp {
+    pub year  : u16,
+    pub month : u16,
+    pub day   : u16,
+    pub hour  : u16,
+    pub minute: u16,
+    pub second: u16,
+}
+
+// The extension area of TGA 2.0, see http://www.dca.fa.unibo.it/~ferrari/TGA_SPEC.pdf
+#[derive(Debug, Clone, Default)]
+pub struct TgaExtension {
+    pub author_name: String,
+    pub author_comments: String, // at most 4 lines of 80 characters
+    pub timestamp: Option<TgaTimestamp>,
+    pub job_name: String,
+    pub job_time: (u16, u16, u16), // hours, minutes, seconds
+    pub software_id: String,
+    pub software_version: (u16, u8), // version number * 100 and version letter
+    pub key_color: u32, // A:R:G:B
+    pub pixel_aspect_ratio: Option<(u16, u16)>, // numerator, denominator
+    pub gamma: Option<(u16, u16)>, // numerator, denominator
+    pub attributes_type: u8,
+    pub postage_stamp: Option<TgaImage>, // same format as the image, at most 255x255
+}
+
+impl TgaExtension {
+
+    pub fn gamma_value(&self) -> Option<f32> {
+        self.gamma.map(|(numerator, denominator)| numerator as f32 / denominator as f32)
+    }
+
+    fn read_extension(bytes: &[u8], offset: u32, header: &TgaHeader, colormap: &TgaColorMap) -> TgaResult<TgaExtension> {
+
+        // a zero denominator means the ratio is not specified
+        fn read_ratio(reader: &mut impl Read) -> std::io::Result<Option<(u16, u16)>> {
+            let numerator   = reader.read_u16::<LittleEndian>()?;
+            let denominator = reader.read_u16::<LittleEndian>()?;
+            Ok(if denominator == 0 { None } else { Some((numerator, denominator)) })
+        }
+
+        let mut reader = Cursor::new(bytes);
+        reader.set_position(offset as u64);
+
+        if reader.read_u16::<LittleEndian>()? < EXTENSION_AREA_SIZE {
+            return Err(TgaError::InvalidMetadata("invalid size of extension area"))
+        }
+
+        let author_name = read_fixed_string(&mut reader, 41)?;
+        let mut comment_lines = Vec::with_capacity(4);
+        for _ in 0..4 {
+            comment_lines.push(read_fixed_string(&mut reader, 81)?);
+        }
+        while comment_lines.last().is_some_and(|line| line.is_empty()) {
+            comment_lines.pop();
+        }
+
+        let timestamp = TgaTimestamp {
+            month : reader.read_u16::<LittleEndian>()?,
+            day   : reader.read_u16::<LittleEndian>()?,
+            year  : reader.read_u16::<LittleEndian>()?,
+            hour  : reader.read_u16::<LittleEndian>()?,
+            minute: reader.read_u16::<LittleEndian>()?,
+            second: reader.read_u16::<LittleEndian>()?,
+        };
+        let job_name = read_fixed_string(&mut reader, 41)?;
+        let job_time = (
+            reader.read_u16::<LittleEndian>()?,
+            reader.read_u16::<LittleEndian>()?,
+            reader.read_u16::<LittleEndian>()?,
+        );
+        let software_id = read_fixed_string(&mut reader, 41)?;
+        let software_version = (reader.read_u16::<LittleEndian>()?, reader.read_u8()?);
+        let key_color = reader.read_u32::<LittleEndian>()?;
+        let pixel_aspect_ratio = read_ratio(&mut reader)?;
+        let gamma = read_ratio(&mut reader)?;
+        let _color_correction_offset = reader.read_u32::<LittleEndian>()?;
+        let postage_stamp_offset = reader.read_u32::<LittleEndian>()?;
+        let _scan_line_offset = reader.read_u32::<LittleEndian>()?;
+        let attributes_type = reader.read_u8()?;
+
+        let postage_stamp = if postage_stamp_offset != 0 {
+            reader.set_position(postage_stamp_offset as u64);
+            let width  = reader.read_u8()? as i32;
+            let height = reader.read_u8()? as i32;
+
+            if width == 0 || height == 0 {
+                None
+            } else {
+                let (data, bytes_per_pixel) = TgaImage::read_pixels(&mut reader, header, colormap, width, height, false)?;
+                Some(TgaImage::from_file_order(width, height, bytes_per_pixel, data))
+            }
+        } else {
+            None
+        };
+
+        let extension = TgaExtension {
+            author_name,
+            author_comments: comment_lines.join("\n"),
+            timestamp: if timestamp == TgaTimestamp::default() { None } else { Some(timestamp) },
+            job_name, job_time, software_id, software_version, key_color,
+            pixel_aspect_ratio, gamma, attributes_type, postage_stamp,
+        };
+        Ok(extension)
+    }
+
+    // Append the extension area and the encoded postage stamp(width, height, pixels), return the offset of the area.
+    fn write_extension(&self, bytes: &mut Vec<u8>, postage_stamp: Option<(u8, u8, Vec<u8>)>) -> TgaResult<u32> {
+
+        let offset = bytes.len() as u32;
+        let postage_stamp_offset = if postage_stamp.is_some() { offset + EXTENSION_AREA_SIZE as u32 } else { 0 };
+
+        bytes.write_u16::<LittleEndian>(EXTENSION_AREA_SIZE)?;
+        write_fixed_string(bytes, &self.author_name, 41)?;
+        let mut comment_lines = self.author_comments.lines();
+        for _ in 0..4 {
+            write_fixed_string(bytes, comment_lines.next().unwrap_or(""), 81)?;
+        }
+
+        let timestamp = self.timestamp.unwrap_or_default();
+        for &v in &[timestamp.month, timestamp.day, timestamp.year, timestamp.hour, timestamp.minute, timestamp.second] {
+            bytes.write_u16::<LittleEndian>(v)?;
+        }
+        write_fixed_string(bytes, &self.job_name, 41)?;
+        for &v in &[self.job_time.0, self.job_time.1, self.job_time.2] {
+            bytes.write_u16::<LittleEndian>(v)?;
+        }
+        write_fixed_string(bytes, &self.software_id, 41)?;
+        bytes.write_u16::<LittleEndian>(self.software_version.0)?;
+        bytes.write_u8(self.software_version.1)?;
+        bytes.write_u32::<LittleEndian>(self.key_color)?;
+        for &(numerator, denominator) in &[self.pixel_aspect_ratio.unwrap_or((0, 0)), self.gamma.unwrap_or((0, 0))] {
+            bytes.write_u16::<LittleEndian>(numerator)?;
+            bytes.write_u16::<LittleEndian>(denominator)?;
+        }
+        bytes.write_u32::<LittleEndian>(0)?; // color correction table is not supported
+        bytes.write_u32::<LittleEndian>(postage_stamp_offset)?;
+        bytes.write_u32::<LittleEndian>(0)?; // neither is scan line table
+        bytes.write_u8(self.attributes_type)?;
+
+        if let Some((width, height, data)) = postage_stamp {
+            bytes.write_u8(width)?;
+            bytes.write_u8(height)?;
+            bytes.write_all(&data)?;
+        }
+
+        Ok(offset)
+    }
+}
+
+// A tagged field of the developer area, the meaning of `data` is up to the application.
 #[derive(Debug, Clone)]
+pub struct TgaDeveloperField {
+    pub tag: u16,
+    pub data: Vec<u8>,
+}
+
+impl TgaDeveloperField {
+
+    fn read_developer_area(bytes: &[u8], offset: u32) -> TgaResult<Vec<TgaDeveloperField>> {
+
+        let mut reader = Cursor::new(bytes);
+        reader.set_position(offset as u64);
+
+        let field_count = reader.read_u16::<LittleEndian>()?;
+        let mut fields = Vec::with_capacity(field_count as usize);
+        for _ in 0..field_count {
+            let tag    = reader.read_u16::<LittleEndian>()?;
+            let offset = reader.read_u32::<LittleEndian>()? as usize;
+            let size   = reader.read_u32::<LittleEndian>()? as usize;
+
+            let data = offset.checked_add(size)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or(TgaError::InvalidMetadata("developer field is out of the file"))?;
+            fields.push(TgaDeveloperField { tag, data: data.to_vec() });
+        }
+
+        Ok(fields)
+    }
+
+    // Append the data of each field followed by the developer directory, return the offset of the directory.
+    fn write_developer_area(fields: &[TgaDeveloperField], bytes: &mut Vec<u8>) -> TgaResult<u32> {
+
+        if fields.is_empty() {
+            return Ok(0)
+        }
+
+        let mut offsets = Vec::with_capacity(fields.len());
+        for field in fields {
+            offsets.push(bytes.len() as u32);
+            bytes.write_all(&field.data)?;
+        }
+
+        let directory_offset = bytes.len() as u32;
+        bytes.write_u16::<LittleEndian>(fields.len() as u16)?;
+        for (field, offset) in fields.iter().zip(offsets) {
+            bytes.write_u16::<LittleEndian>(field.tag)?;
+            bytes.write_u32::<LittleEndian>(offset)?;
+            bytes.write_u32::<LittleEndian>(field.data.len() as u32)?;
+        }
+
+        Ok(directory_offset)
+    }
+}
+
+
+// An 8-bit color, the channels are stored in the order of tga files: color[0] is blue, color[1] green,
+// color[2] red and color[3] alpha. Prefer the named constructors and accessors to indexing.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TgaColor {
     bgra: [u8; 4],
     format: TgaFormat,
@@ -84,27 +485,69 @@ impl TgaColor {
         }
     }
 
+    // Opaque color.
     pub const fn from_rgb(r: u8, g: u8, b: u8) -> TgaColor {
         TgaColor {
-            bgra: [r, g, b, 0],
+            bgra: [b, g, r, 255],
             format: TgaFormat::RGB,
         }
     }
 
     pub const fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> TgaColor {
         TgaColor {
-            bgra: [r, g, b, a],
+            bgra: [b, g, r, a],
             format: TgaFormat::RGBA,
         }
     }
 
-    #[allow(unused)]
-    pub fn from_greyscale(v: u8) -> TgaColor {
+    pub const fn from_bgr(b: u8, g: u8, r: u8) -> TgaColor {
+        TgaColor::from_rgb(r, g, b)
+    }
+
+    pub const fn from_bgra(b: u8, g: u8, r: u8, a: u8) -> TgaColor {
+        TgaColor::from_rgba(r, g, b, a)
+    }
+
+    pub const fn from_greyscale(v: u8) -> TgaColor {
         TgaColor {
-            bgra: [v, 0, 0, 0],
+            bgra: [v, v, v, 255],
             format: TgaFormat::Grayscale,
         }
     }
+
+    pub const fn r(&self) -> u8 { self.bgra[2] }
+    pub const fn g(&self) -> u8 { self.bgra[1] }
+    pub const fn b(&self) -> u8 { self.bgra[0] }
+    pub const fn a(&self) -> u8 { self.bgra[3] }
+
+    pub const fn rgb(&self) -> [u8; 3] {
+        [self.bgra[2], self.bgra[1], self.bgra[0]]
+    }
+
+    pub const fn rgba(&self) -> [u8; 4] {
+        [self.bgra[2], self.bgra[1], self.bgra[0], self.bgra[3]]
+    }
+
+    pub const fn bgr(&self) -> [u8; 3] {
+        [self.bgra[0], self.bgra[1], self.bgra[2]]
+    }
+
+    pub const fn bgra(&self) -> [u8; 4] {
+        self.bgra
+    }
+
+    // The format the color was made for, e.g. colors read from a grayscale image are Grayscale.
+    pub const fn format(&self) -> TgaFormat {
+        self.format
+    }
+
+    // Luma of the color, the same as converting it to a Luma8 pixel, the value itself for grayscale colors.
+    pub fn luma(&self) -> u8 {
+        match self.format {
+            | TgaFormat::Grayscale => self.bgra[0],
+            | _ => Luma8::from_rgba(Rgb8([self.r(), self.g(), self.b()]).to_rgba()).0[0],
+        }
+    }
 }
 
 impl Index<usize> for TgaColor {
@@ -134,7 +577,7 @@ impl Mul<f32> for TgaColor {
                 (self.bgra[0] as f32 * rhs) as u8,
                 (self.bgra[1] as f32 * rhs) as u8,
                 (self.bgra[2] as f32 * rhs) as u8,
-                (self.bgra[3] as f32 * rhs) as u8,
+                self.bgra[3], // alpha isn't a light intensity
             ],
             format: self.format,
         }
@@ -142,7 +585,7 @@ impl Mul<f32> for TgaColor {
 }
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TgaFormat {
     Grayscale = 1,
     RGB       = 3,
@@ -150,226 +593,606 @@ pub enum TgaFormat {
 }
 
 
+// How the pixels are stored in a written tga file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TgaEncoding {
+    TrueColor,   // same bytes per pixel as the image
+    ColorMapped, // 8-bit indices into a palette of at most 256 colors
+    HighColor,   // 16-bit A1R5G5B5
+}
+
+// Expand 16-bit A1R5G5B5 pixels to BGR, or BGRA if the attribute bit is used as alpha.
+fn high_color_to_true_color(data: &[u8], with_alpha: bool) -> Vec<u8> {
+
+    // replicate the high bits into the low bits so that 0x1f maps to 255
+    fn expand_5bits(v: u16) -> u8 {
+        let v = (v & 0x1f) as u8;
+        (v << 3) | (v >> 2)
+    }
+
+    let bytes_per_pixel = if with_alpha { TgaFormat::RGBA } else { TgaFormat::RGB } as usize;
+    let mut result = Vec::with_capacity(data.len() / 2 * bytes_per_pixel);
+    for pixel in data.chunks_exact(2) {
+        let v = u16::from_le_bytes([pixel[0], pixel[1]]);
+        result.extend_from_slice(&[expand_5bits(v), expand_5bits(v >> 5), expand_5bits(v >> 10)]);
+        if with_alpha {
+            result.push(if v & 0x8000 != 0 { 255 } else { 0 });
+        }
+    }
+    result
+}
+
+// Pack grayscale/BGR/BGRA pixels into 16-bit A1R5G5B5, the alpha bit is set when alpha >= 128.
+fn true_color_to_high_color(data: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+
+    let mut result = Vec::with_capacity(data.len() / bytes_per_pixel * 2);
+    for pixel in data.chunks_exact(bytes_per_pixel) {
+        let (b, g, r) = if bytes_per_pixel == TgaFormat::Grayscale as usize {
+            (pixel[0], pixel[0], pixel[0])
+        } else {
+            (pixel[0], pixel[1], pixel[2])
+        };
+        let a = bytes_per_pixel != TgaFormat::RGBA as usize || pixel[3] >= 128;
+
+        let v = ((b as u16) >> 3) | (((g as u16) >> 3) << 5) | (((r as u16) >> 3) << 10) | if a { 0x8000 } else { 0 };
+        result.extend_from_slice(&v.to_le_bytes());
+    }
+    result
+}
+
+
+// Apply an expression to the typed image of any format, giving pixels of the same format.
+macro_rules! map_pixels {
+    ($pixels:expr, $image:ident => $e:expr) => {
+        match $pixels {
+            | TgaPixels::Grayscale($image) => TgaPixels::Grayscale($e),
+            | TgaPixels::RGB($image)  => TgaPixels::RGB($e),
+            | TgaPixels::RGBA($image) => TgaPixels::RGBA($e),
+        }
+    };
+}
+pub(crate) use map_pixels;
+
+// The typed pixels of a TgaImage, colors are stored in R, G, B(, A) order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TgaPixels {
+    Grayscale(Image<Luma8>),
+    RGB(Image<Rgb8>),
+    RGBA(Image<Rgba8>),
+}
+
+impl TgaPixels {
+
+    fn new(width: usize, height: usize, format: TgaFormat) -> TgaPixels {
+        match format {
+            | TgaFormat::Grayscale => TgaPixels::Grayscale(Image::new(width, height)),
+            | TgaFormat::RGB  => TgaPixels::RGB(Image::new(width, height)),
+            | TgaFormat::RGBA => TgaPixels::RGBA(Image::new(width, height)),
+        }
+    }
+
+    fn from_raw(width: usize, height: usize, format: TgaFormat, data: &[u8]) -> Option<TgaPixels> {
+        match format {
+            | TgaFormat::Grayscale => Image::from_raw(width, height, data).map(TgaPixels::Grayscale),
+            | TgaFormat::RGB  => Image::from_raw(width, height, data).map(TgaPixels::RGB),
+            | TgaFormat::RGBA => Image::from_raw(width, height, data).map(TgaPixels::RGBA),
+        }
+    }
+
+    pub fn format(&self) -> TgaFormat {
+        match self {
+            | TgaPixels::Grayscale(_) => TgaFormat::Grayscale,
+            | TgaPixels::RGB(_)  => TgaFormat::RGB,
+            | TgaPixels::RGBA(_) => TgaFormat::RGBA,
+        }
+    }
+
+    pub fn as_raw(&self) -> &[u8] {
+        match self {
+            | TgaPixels::Grayscale(image) => image.as_raw(),
+            | TgaPixels::RGB(image)  => image.as_raw(),
+            | TgaPixels::RGBA(image) => image.as_raw(),
+        }
+    }
+
+    pub fn as_raw_mut(&mut self) -> &mut [u8] {
+        match self {
+            | TgaPixels::Grayscale(image) => image.as_raw_mut(),
+            | TgaPixels::RGB(image)  => image.as_raw_mut(),
+            | TgaPixels::RGBA(image) => image.as_raw_mut(),
+        }
+    }
+}
+
+
 #[derive(Debug, Clone)]
 pub struct TgaImage {
-    data: Vec<u8>,
+    pixels: TgaPixels,
     pub width : i32,
     pub height: i32,
-    bytes_per_pixel: usize,
+    pub metadata: TgaMetadata,
 }
 
 impl TgaImage {
 
     pub fn unset() -> TgaImage {
-        TgaImage { data: vec![], width: 0, height: 0, bytes_per_pixel: 0 }
+        TgaImage::new(0, 0, TgaFormat::RGB)
     }
 
     pub fn new(width: i32, height: i32, format: TgaFormat) -> TgaImage {
-        
-        let bytes_per_pixel = format as usize;
-        let nbytes = (width * height) as usize * bytes_per_pixel;
-
         TgaImage {
-            data: vec![0; nbytes],
-            width, height, bytes_per_pixel,
+            pixels: TgaPixels::new(width.max(0) as usize, height.max(0) as usize, format),
+            width: width.max(0),
+            height: height.max(0),
+            metadata: TgaMetadata::default(),
+        }
+    }
+
+    // Wrap pixels stored row by row from the top-left corner, in RGB/RGBA order for color images.
+    pub fn from_raw_data(width: i32, height: i32, format: TgaFormat, data: Vec<u8>) -> TgaResult<TgaImage> {
+
+        if width < 0 || height < 0 {
+            return Err(TgaError::BadDimensions { width, height })
         }
+        let pixels = TgaPixels::from_raw(width as usize, height as usize, format, &data)
+            .ok_or(TgaError::BadDimensions { width, height })?;
+        Ok(TgaImage { pixels, width, height, metadata: TgaMetadata::default() })
     }
 
-    pub fn from_path(path: impl AsRef<Path>) -> std::io::Result<TgaImage> {
+    pub fn from_pixels(pixels: TgaPixels) -> TgaImage {
+        let (width, height) = match &pixels {
+            | TgaPixels::Grayscale(image) => (image.width(), image.height()),
+            | TgaPixels::RGB(image)  => (image.width(), image.height()),
+            | TgaPixels::RGBA(image) => (image.width(), image.height()),
+        };
+        TgaImage { pixels, width: width as i32, height: height as i32, metadata: TgaMetadata::default() }
+    }
+
+    // Build the image from pixels in the byte order of tga files(BGR/BGRA), the length of `data` must match.
+    fn from_file_order(width: i32, height: i32, bytes_per_pixel: usize, mut data: Vec<u8>) -> TgaImage {
+        let format = TgaImage::format_of(bytes_per_pixel);
+        if bytes_per_pixel >= 3 {
+            data.chunks_exact_mut(bytes_per_pixel).for_each(|pixel| pixel.swap(0, 2));
+        }
+        TgaImage::from_raw_data(width, height, format, data).expect("Pixel count must match the dimensions")
+    }
+
+    // The pixels in the byte order of tga files.
+    fn file_order_data(&self) -> Vec<u8> {
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let mut data = self.raw_data().to_vec();
+        if bytes_per_pixel >= 3 {
+            data.chunks_exact_mut(bytes_per_pixel).for_each(|pixel| pixel.swap(0, 2));
+        }
+        data
+    }
+
+    fn format_of(bytes_per_pixel: usize) -> TgaFormat {
+        match bytes_per_pixel {
+            | 1 => TgaFormat::Grayscale,
+            | 3 => TgaFormat::RGB,
+            | _ => TgaFormat::RGBA,
+        }
+    }
 
-        let mut file = File::open(path)?;
-        let header = TgaHeader::read_header(&mut file)
-            .expect("An error occurred while reading header!");
+    // The pixels row by row from the top-left corner, in RGB/RGBA order for color images.
+    pub fn raw_data(&self) -> &[u8] {
+        self.pixels.as_raw()
+    }
+
+    pub fn pixels(&self) -> &TgaPixels {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut TgaPixels {
+        &mut self.pixels
+    }
+
+    pub fn into_pixels(self) -> TgaPixels {
+        self.pixels
+    }
+
+    pub fn format(&self) -> TgaFormat {
+        self.pixels.format()
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        self.format() as usize
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> TgaResult<TgaImage> {
+        // the whole file is kept in memory, since the extension area is located by the footer at its end
+        let bytes = std::fs::read(path)?;
+        TgaImage::from_bytes(&bytes)
+    }
+
+    pub fn from_reader(mut reader: impl Read) -> TgaResult<TgaImage> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        TgaImage::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> TgaResult<TgaImage> {
+
+        let mut reader = Cursor::new(bytes);
+        let header = TgaHeader::read_header(&mut reader)
+            .map_err(|_| TgaError::TruncatedHeader)?;
 
         let width  = header.width  as i32;
         let height = header.height as i32;
-        let bytes_per_pixel = header.bits_per_pixel >> 3;
 
-        if width == 0 || height == 0 ||
-            (bytes_per_pixel != TgaFormat::Grayscale as u8 && bytes_per_pixel != TgaFormat::RGB as u8 && bytes_per_pixel != TgaFormat::RGBA as u8) {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "An error occured while reading the data!"))
+        if width <= 0 || height <= 0 {
+            return Err(TgaError::BadDimensions { width, height })
         }
 
-        let bytes_per_pixel = bytes_per_pixel as usize;
-        let data = if header.datatypecode == 3 || header.datatypecode == 2 {
-            let mut data = vec![0; (width * height) as usize * bytes_per_pixel];
-            let _bytes_read = file.read_exact(&mut data)?;
-            data
-        } else if header.datatypecode == 10 || header.datatypecode == 11 {
-            TgaImage::load_rle_data(&mut file, width, height, bytes_per_pixel)?
+        // the color map data follows right after the image id field
+        let mut image_id = vec![0; header.id_length as usize];
+        reader.read_exact(&mut image_id)?;
+        let colormap = if header.colormaptype == 1 {
+            TgaColorMap::read_colormap(&mut reader, &header)?
         } else {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("Unknown file format({})", header.datatypecode)))
+            TgaColorMap::empty()
         };
 
-        println!("Read TGA file: {}x{}/{}", width, height, bytes_per_pixel);
+        let rle = header.datatypecode & 0x08 != 0;
+        let (data, bytes_per_pixel) = TgaImage::read_pixels(&mut reader, &header, &colormap, width, height, rle)?;
 
-        let mut tga_image = TgaImage { data, width, height, bytes_per_pixel };
+        let mut tga_image = TgaImage::from_file_order(width, height, bytes_per_pixel, data);
+        tga_image.metadata.image_id = image_id;
 
-        if header.image_descriptor & 0x20 == 0 {
-            tga_image.flip_vertically();
+        // TGA 2.0 files end with a footer pointing to the extension and developer areas
+        if let Some((extension_offset, developer_offset)) = read_footer(bytes) {
+            if extension_offset != 0 {
+                let extension = TgaExtension::read_extension(bytes, extension_offset, &header, &colormap)?;
+                tga_image.metadata.extension = Some(Box::new(extension));
+            }
+            if developer_offset != 0 {
+                tga_image.metadata.developer_fields = TgaDeveloperField::read_developer_area(bytes, developer_offset)?;
+            }
         }
-        if header.image_descriptor & 0x10 != 0 {
-            tga_image.flip_horizontally()?;
+
+        // many writers leave the alpha bits at 0 for 32-bit pixels, so the fourth byte is kept as alpha,
+        // unless the attributes type of the extension says it holds no alpha(0) or undefined data(1, 2)
+        let undefined_alpha = tga_image.metadata.extension.as_ref().is_some_and(|extension| extension.attributes_type <= 2);
+        if header.image_descriptor & 0x0f == 0 && undefined_alpha {
+            tga_image.make_opaque();
         }
 
+        tga_image.apply_orientation(header.image_descriptor)?;
         Ok(tga_image)
     }
 
-    pub fn flip_vertically(&mut self) {
+    // Set the alpha of every pixel to 255, the postage stamp included.
+    fn make_opaque(&mut self) {
+        if let TgaPixels::RGBA(image) = &mut self.pixels {
+            image.as_raw_mut().chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+        }
+        if let Some(stamp) = self.metadata.extension.as_mut().and_then(|extension| extension.postage_stamp.as_mut()) {
+            stamp.make_opaque();
+        }
+    }
+
+    // Read the pixel data of the image(or of its postage stamp) and convert it to one of the formats of `TgaFormat`.
+    fn read_pixels(reader: &mut Cursor<&[u8]>, header: &TgaHeader, colormap: &TgaColorMap, width: i32, height: i32, rle: bool) -> TgaResult<(Vec<u8>, usize)> {
+
+        let (data, bytes_per_pixel) = match header.datatypecode & !0x08 {
+            | 1 => {
+                // color-mapped images store an index per pixel, expand them to true color with the palette
+                let index_bytes = (header.bits_per_pixel >> 3) as usize;
+                if header.colormaptype != 1 {
+                    return Err(TgaError::InvalidColorMap("color-mapped image without color map"))
+                }
+                if index_bytes != 1 && index_bytes != 2 {
+                    return Err(TgaError::UnsupportedPixelDepth(header.bits_per_pixel))
+                }
 
-        let bytes_per_line = self.width as usize * self.bytes_per_pixel;
+                let indices = TgaImage::read_pixel_bytes(reader, width, height, index_bytes, rle)?;
+                (colormap.expand(&indices, index_bytes)?, colormap.bytes_per_entry)
+            },
+            | 2 | 3 => {
+                // 15-bit images still take 2 bytes per pixel
+                let bytes_per_pixel = (header.bits_per_pixel as usize + 7) >> 3;
+                let is_high_color = bytes_per_pixel == 2 && header.datatypecode & !0x08 == 2;
+                if !is_high_color && bytes_per_pixel != TgaFormat::Grayscale as usize && bytes_per_pixel != TgaFormat::RGB as usize && bytes_per_pixel != TgaFormat::RGBA as usize {
+                    return Err(TgaError::UnsupportedPixelDepth(header.bits_per_pixel))
+                }
 
-        let height = self.height as usize;
-        let half = height >> 1;
+                (TgaImage::read_pixel_bytes(reader, width, height, bytes_per_pixel, rle)?, bytes_per_pixel)
+            },
+            | _ => return Err(TgaError::UnsupportedDatatype(header.datatypecode)),
+        };
 
-        for j in 0..half {
-            let l1 = j * bytes_per_line;
-            let l2 = (height - 1 - j) * bytes_per_line;
+        // the lower 4 bits of image descriptor give the number of attribute(alpha) bits per pixel
+        let alpha_bits = header.image_descriptor & 0x0f;
+        let result = match bytes_per_pixel {
+            | 2 => {
+                let with_alpha = alpha_bits != 0;
+                let format = if with_alpha { TgaFormat::RGBA } else { TgaFormat::RGB };
+                (high_color_to_true_color(&data, with_alpha), format as usize)
+            },
+            | _ => (data, bytes_per_pixel),
+        };
+        Ok(result)
+    }
 
-            unsafe {
-                std::ptr::swap_nonoverlapping(&mut self.data[l1], &mut self.data[l2], bytes_per_line);
-            }
+    // Read the raw or run-length encoded bytes of `width * height` pixels.
+    fn read_pixel_bytes(reader: &mut Cursor<&[u8]>, width: i32, height: i32, bytes_per_pixel: usize, rle: bool) -> TgaResult<Vec<u8>> {
+
+        // refuse to allocate for more pixels than the remaining data can describe,
+        // a run-length packet takes at least 1 + bytes_per_pixel bytes and describes at most 128 pixels
+        let pixel_count = (width * height) as usize;
+        let remaining = (reader.get_ref().len() as u64).saturating_sub(reader.position()) as usize;
+        let min_size = if rle { pixel_count.div_ceil(128) * (1 + bytes_per_pixel) } else { pixel_count * bytes_per_pixel };
+        if remaining < min_size {
+            return Err(TgaError::TruncatedData)
+        }
+
+        if rle {
+            TgaImage::load_rle_data(reader, width, height, bytes_per_pixel)
+        } else {
+            let mut data = vec![0; pixel_count * bytes_per_pixel];
+            reader.read_exact(&mut data)?;
+            Ok(data)
         }
     }
 
-    pub fn flip_horizontally(&mut self) -> std::io::Result<()> {
+    // Bring the pixels to the top-left origin used in memory, the postage stamp shares the orientation of the image.
+    fn apply_orientation(&mut self, image_descriptor: u8) -> TgaResult<()> {
 
-        let half = self.width >> 1;
-        for (i, j) in iproduct!(0..half, 0..self.height) {
-            let c1 = self.get(i, j)?;
-            let c2 = self.get(self.width - 1 - i, j)?;
-            self.set(i, j, &c2);
-            self.set(self.width - 1 - i, j, &c1);
+        if image_descriptor & 0x20 == 0 {
+            self.flip_vertically();
+        }
+        if image_descriptor & 0x10 != 0 {
+            self.flip_horizontally()?;
+        }
+        if let Some(stamp) = self.metadata.extension.as_mut().and_then(|extension| extension.postage_stamp.as_mut()) {
+            stamp.apply_orientation(image_descriptor)?;
+        }
+        Ok(())
+    }
 
+    pub fn flip_vertically(&mut self) {
+        match &mut self.pixels {
+            | TgaPixels::Grayscale(image) => image.flip_vertically(),
+            | TgaPixels::RGB(image)  => image.flip_vertically(),
+            | TgaPixels::RGBA(image) => image.flip_vertically(),
         }
+    }
 
+    pub fn flip_horizontally(&mut self) -> TgaResult<()> {
+        match &mut self.pixels {
+            | TgaPixels::Grayscale(image) => image.flip_horizontally(),
+            | TgaPixels::RGB(image)  => image.flip_horizontally(),
+            | TgaPixels::RGBA(image) => image.flip_horizontally(),
+        }
         Ok(())
     }
 
-    pub fn write_tga_file(&self, path: impl AsRef<Path>, rle: bool) -> std::io::Result<()> {
+    // Copy the part of the image inside `rect`, clipped to the image.
+    pub fn crop(&self, rect: Rect) -> TgaImage {
+        TgaImage::from_pixels(map_pixels!(&self.pixels, image => image.crop(rect)))
+    }
 
-        let mut file = File::create(path)?;
+    pub fn transpose(&self) -> TgaImage {
+        TgaImage::from_pixels(map_pixels!(&self.pixels, image => image.transpose()))
+    }
+
+    // Rotate by 90 degrees clockwise.
+    pub fn rotate90(&self) -> TgaImage {
+        TgaImage::from_pixels(map_pixels!(&self.pixels, image => image.rotate90()))
+    }
+
+    pub fn rotate180(&self) -> TgaImage {
+        TgaImage::from_pixels(map_pixels!(&self.pixels, image => image.rotate180()))
+    }
+
+    // Rotate by 90 degrees counterclockwise.
+    pub fn rotate270(&self) -> TgaImage {
+        TgaImage::from_pixels(map_pixels!(&self.pixels, image => image.rotate270()))
+    }
+
+    pub fn resize(&self, width: i32, height: i32, filter: ResizeFilter) -> TgaImage {
+        let (width, height) = (width.max(0) as usize, height.max(0) as usize);
+        TgaImage::from_pixels(map_pixels!(&self.pixels, image => resize_image(image, width, height, filter)))
+    }
+
+    // Downscale the image(nearest neighbour) so that it fits in a postage stamp, 64x64 is recommended by the spec.
+    // The stamp stores its size in bytes, so `max_size` is clamped to 1..=255. Empty images give an empty stamp.
+    pub fn make_postage_stamp(&self, max_size: i32) -> TgaImage {
+
+        if self.width <= 0 || self.height <= 0 {
+            return TgaImage::new(0, 0, self.format())
+        }
+
+        let scale = (max_size.clamp(1, 255) as f32 / self.width.max(self.height) as f32).min(1.0);
+        let width  = ((self.width  as f32 * scale) as i32).clamp(1, 255);
+        let height = ((self.height as f32 * scale) as i32).clamp(1, 255);
+
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let (source_width, source_height) = (self.width as usize, self.height as usize);
+        let mut data = Vec::with_capacity((width * height) as usize * bytes_per_pixel);
+        for (j, i) in iproduct!(0..height as usize, 0..width as usize) {
+            let location = (i * source_width / width as usize + j * source_height / height as usize * source_width) * bytes_per_pixel;
+            data.extend_from_slice(&self.raw_data()[location..(location + bytes_per_pixel)]);
+        }
+
+        TgaImage::from_raw_data(width, height, self.format(), data).expect("Pixel count must match the dimensions")
+    }
+
+    pub fn write_tga_file(&self, path: impl AsRef<Path>, rle: bool) -> TgaResult<()> {
+        self.write_tga_file_as(path, rle, TgaEncoding::TrueColor)
+    }
+
+    // Write the image as a color-mapped tga file, the palette is built from the distinct colors of the image.
+    pub fn write_tga_file_colormapped(&self, path: impl AsRef<Path>, rle: bool) -> TgaResult<()> {
+        self.write_tga_file_as(path, rle, TgaEncoding::ColorMapped)
+    }
+
+    pub fn write_tga_file_as(&self, path: impl AsRef<Path>, rle: bool, encoding: TgaEncoding) -> TgaResult<()> {
+        self.write_to(File::create(path)?, rle, encoding)
+    }
+
+    pub fn write_to(&self, mut writer: impl Write, rle: bool, encoding: TgaEncoding) -> TgaResult<()> {
+        // the offsets of the footer are only known once everything is encoded
+        let bytes = self.to_bytes(rle, encoding)?;
+        writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    pub fn to_bytes(&self, rle: bool, encoding: TgaEncoding) -> TgaResult<Vec<u8>> {
+
+        // the header stores the dimensions as 16-bit signed values
+        let max_size = i16::MAX as i32;
+        if self.width < 0 || self.height < 0 || self.width > max_size || self.height > max_size {
+            return Err(TgaError::BadDimensions { width: self.width, height: self.height })
+        }
+        let stamp = self.metadata.extension.as_ref().and_then(|extension| extension.postage_stamp.as_ref());
+        if let Some(stamp) = stamp {
+            if stamp.format() != self.format() || stamp.width > 255 || stamp.height > 255 {
+                return Err(TgaError::InvalidMetadata("postage stamp must be at most 255x255 and have the same format as the image"))
+            }
+        }
+        if self.metadata.image_id.len() > 255 {
+            return Err(TgaError::InvalidMetadata("image id can't be longer than 255 bytes"))
+        }
+
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let data = self.file_order_data();
+        let stamp_data = stamp.map(|stamp| stamp.file_order_data());
+
+        let (colormap, indices) = if encoding == TgaEncoding::ColorMapped {
+            if bytes_per_pixel == TgaFormat::Grayscale as usize {
+                return Err(TgaError::UnsupportedEncoding("grayscale image can't be color-mapped"))
+            }
+            // the postage stamp is written with the same palette as the image
+            match stamp_data.as_ref() {
+                | Some(stamp_data) => TgaColorMap::build(&[&data[..], &stamp_data[..]].concat(), bytes_per_pixel)?,
+                | None => TgaColorMap::build(&data, bytes_per_pixel)?,
+            }
+        } else {
+            (TgaColorMap::empty(), vec![])
+        };
+
+        let has_alpha = bytes_per_pixel == TgaFormat::RGBA as usize;
+        let (datatypecode, bits_per_pixel, alpha_bits) = match encoding {
+            | TgaEncoding::TrueColor if bytes_per_pixel == TgaFormat::Grayscale as usize => (3, 8, 0),
+            | TgaEncoding::TrueColor   => (2, (bytes_per_pixel << 3) as u8, if has_alpha { 8 } else { 0 }),
+            | TgaEncoding::ColorMapped => (1, 8, if has_alpha { 8 } else { 0 }),
+            | TgaEncoding::HighColor   => (2, 16, if has_alpha { 1 } else { 0 }),
+        };
 
         let header = TgaHeader {
-            id_length   : 0,
-            colormaptype: 0,
-            datatypecode: if self.bytes_per_pixel == TgaFormat::Grayscale as _ {
-                if rle { 11 } else { 3 }
-            } else {
-                if rle { 10 } else { 2 }
-            },
+            id_length   : self.metadata.image_id.len() as u8,
+            colormaptype: if encoding == TgaEncoding::ColorMapped { 1 } else { 0 },
+            datatypecode: if rle { datatypecode + 8 } else { datatypecode },
             colormap_origin: 0,
-            colormap_length: 0,
-            colormap_depth : 0,
+            colormap_length: colormap.length() as i16,
+            colormap_depth : (colormap.bytes_per_entry << 3) as u8,
             x_origin: 0,
             y_origin: 0,
             width : self.width  as i16,
             height: self.height as i16,
-            bits_per_pixel: (self.bytes_per_pixel << 3) as u8,
-            image_descriptor: 0x20,
+            bits_per_pixel,
+            image_descriptor: 0x20 | alpha_bits,
+        };
+
+        let mut bytes = Vec::new();
+        header.write_header(&mut bytes)?;
+        bytes.write_all(&self.metadata.image_id)?;
+        bytes.write_all(&colormap.entries)?;
+
+        // convert pixels to the bytes stored in file, `indices` also holds the indices of the postage stamp
+        let pixel_count = (self.width * self.height) as usize;
+        let encode_pixels = |data: &[u8], indices: &[u8]| -> (Vec<u8>, usize) {
+            match encoding {
+                | TgaEncoding::TrueColor   => (data.to_vec(), bytes_per_pixel),
+                | TgaEncoding::ColorMapped => (indices.to_vec(), 1),
+                | TgaEncoding::HighColor   => (true_color_to_high_color(data, bytes_per_pixel), 2),
+            }
         };
-        
-        header.write_header(&mut file)
-            .expect("Can't dump the tga file!");
 
+        let (encoded, encoded_bytes_per_pixel) = encode_pixels(&data, indices.get(..pixel_count).unwrap_or(&[]));
         if rle {
-            self.unload_rle_data(&mut file)?
+            TgaImage::unload_rle_data(&mut bytes, &encoded, encoded_bytes_per_pixel)?
         } else {
-//            for i in 0..100 {
-//                for j in 0..100 {
-//                    let r = self.data[(i * 100 + j) * 3 + 0];
-//                    let g = self.data[(i * 100 + j) * 3 + 1];
-//                    let b = self.data[(i * 100 + j) * 3 + 2];
-//                    print!("({}, {}, {}) ", r, g, b);
-//                }
-//                println!();
-//            }
-            file.write(&self.data)
-                .expect("Can't unload raw data!");
-        }
-
-        let developer_area_ref: [u8; 4] = [0, 0, 0, 0];
-        file.write(&developer_area_ref)?;
-        let extension_area_ref: [u8; 4] = [0, 0, 0, 0];
-        file.write(&extension_area_ref)?;
-        let footer: [u8; 18] = [b'T', b'R', b'U', b'E', b'V', b'I', b'S', b'I', b'O', b'N', b'-', b'X', b'F', b'I', b'L', b'E', b'.', b'\0'];
-        file.write(&footer)?;
-        
-        Ok(())
+            bytes.write_all(&encoded)?;
+        }
+
+        let developer_area_ref = TgaDeveloperField::write_developer_area(&self.metadata.developer_fields, &mut bytes)?;
+        let extension_area_ref = match self.metadata.extension.as_ref() {
+            | Some(extension) => {
+                // the postage stamp is never compressed
+                let stamp = stamp.zip(stamp_data.as_ref())
+                    .map(|(stamp, stamp_data)| (stamp.width as u8, stamp.height as u8, encode_pixels(stamp_data, indices.get(pixel_count..).unwrap_or(&[])).0));
+                extension.write_extension(&mut bytes, stamp)?
+            },
+            | None => 0,
+        };
+
+        bytes.write_u32::<LittleEndian>(extension_area_ref)?;
+        bytes.write_u32::<LittleEndian>(developer_area_ref)?;
+        bytes.write_all(TGA_SIGNATURE)?;
+
+        Ok(bytes)
     }
 
-    fn load_rle_data(file: &mut impl Read, width: i32, height: i32, bytes_per_pixel: usize) -> std::io::Result<Vec<u8>> {
+    fn load_rle_data(file: &mut impl Read, width: i32, height: i32, bytes_per_pixel: usize) -> TgaResult<Vec<u8>> {
 
+        // the buffer grows with the decoded packets rather than being allocated from the header,
+        // so that a short stream can't claim gigabytes with a few bytes of header
         let pixel_count = (width * height) as usize;
-        let mut data = vec![0; pixel_count * bytes_per_pixel];
+        let mut data = Vec::new();
         let mut current_pixel = 0;
-        let mut current_byte = 0;
-        let mut color_buffer = TgaColor::zeros();
+        let mut color_buffer = vec![0; bytes_per_pixel];
 
-        loop {
-            let mut chunk_header = file.read_u8()?;
-            
-            if chunk_header < 128 {
-                chunk_header += 1;
-                for _ in 0..chunk_header {
-                    for i in 0..bytes_per_pixel {
-                        color_buffer[i] = file.read_u8()?;
-                    }
-                    for i in 0..bytes_per_pixel {
-                        data[current_byte] = color_buffer[i];
-                        current_byte += 1;
-                    }
-                    current_pixel += 1;
-                    if current_pixel > pixel_count {
-                        return Err(std::io::Error::new(std::io::ErrorKind::Other, "Too many pixels read!"))
-                    }
-                }
-            } else {
-                chunk_header -= 127;
-                for i in 0..bytes_per_pixel {
-                    color_buffer[i] = file.read_u8()?;
-                }
+        while current_pixel < pixel_count {
+            let chunk_header = file.read_u8()?;
+            // the lower 7 bits are the number of pixels in the packet minus one
+            let chunk_length = (chunk_header & 0x7f) as usize + 1;
 
-                for _ in 0..chunk_header {
-                    for i in 0..bytes_per_pixel {
-                        data[current_byte] = color_buffer[i];
-                        current_byte += 1;
-                    }
-                    current_pixel += 1;
-                    if current_pixel > pixel_count {
-                        return Err(std::io::Error::new(std::io::ErrorKind::Other, "Too many pixels read!"))
-                    }
-                }
+            // check before writing anything, a packet must not run past the end of the image
+            if current_pixel + chunk_length > pixel_count {
+                return Err(TgaError::RleOverflow { pixel_count })
             }
 
-            if current_pixel >= pixel_count {
-                break
+            if chunk_header < 128 {
+                // raw packet, followed by the pixels themselves
+                let start = data.len();
+                data.resize(start + chunk_length * bytes_per_pixel, 0);
+                file.read_exact(&mut data[start..])?;
+            } else {
+                // run-length packet, followed by a single pixel repeated through the packet
+                file.read_exact(&mut color_buffer)?;
+                for _ in 0..chunk_length {
+                    data.extend_from_slice(&color_buffer);
+                }
             }
+            current_pixel += chunk_length;
         }
 
         Ok(data)
     }
 
-    fn unload_rle_data(&self, file: &mut impl Write) -> std::io::Result<()> {
+    fn unload_rle_data(file: &mut impl Write, data: &[u8], bytes_per_pixel: usize) -> TgaResult<()> {
 
         const MAX_CHUNK_LENGTH: usize = 128;
-        let pixel_count = (self.width * self.height) as usize;
+        let pixel_count = data.len() / bytes_per_pixel;
         let mut current_pixel = 0;
 
         while current_pixel < pixel_count {
-            let chunk_start = current_pixel * self.bytes_per_pixel;
-            let mut current_byte = current_pixel * self.bytes_per_pixel;
+            let chunk_start = current_pixel * bytes_per_pixel;
+            let mut current_byte = current_pixel * bytes_per_pixel;
             let mut run_length = 1;
             let mut raw = true;
 
             while current_pixel + run_length < pixel_count && run_length < MAX_CHUNK_LENGTH {
                 let mut success_eq = true;
-                for i in 0..self.bytes_per_pixel {
-                    if success_eq == false { break }
-                    success_eq = self.data[current_byte + i] == self.data[current_byte + i + self.bytes_per_pixel];
+                for i in 0..bytes_per_pixel {
+                    if !success_eq { break }
+                    success_eq = data[current_byte + i] == data[current_byte + i + bytes_per_pixel];
                 }
 
-                current_byte += self.bytes_per_pixel;
+                current_byte += bytes_per_pixel;
                 if run_length == 1 {
                     raw = !success_eq;
                 }
@@ -386,31 +1209,301 @@ impl TgaImage {
             current_pixel += run_length;
 
             file.write_u8((if raw { run_length - 1 } else { run_length + 127 }) as u8)?;
-            file.write(&self.data[chunk_start..(chunk_start + if raw { run_length * self.bytes_per_pixel} else {self.bytes_per_pixel})])?;
+            file.write_all(&data[chunk_start..(chunk_start + if raw { run_length * bytes_per_pixel} else { bytes_per_pixel })])?;
         }
 
         Ok(())
     }
 
+    pub fn bounds(&self) -> Rect {
+        Rect::of_size(self.width, self.height)
+    }
+
+    #[inline]
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
+
+    // Write a pixel, pixels outside of the image are clipped(skipped).
+    // Colors are converted to the format of the image, color images written to a grayscale image are stored as their luma.
+    #[inline]
     pub fn set(&mut self, x: i32, y: i32, color: &TgaColor) {
-        if x < self.width && y < self.height {
-            let location = (x + y * self.width) as usize * self.bytes_per_pixel;
-            for i in 0..self.bytes_per_pixel {
-                self.data[location + i] = color[i];
-            }
+        if self.contains(x, y) {
+            // Safety: (x, y) is inside of the image
+            unsafe { self.set_unchecked(x, y, color) }
         }
     }
 
-    pub fn get(&self, x: i32, y: i32) -> std::io::Result<TgaColor> {
-        if x >= self.width || y >= self.height {
-            Err(std::io::Error::new(std::io::ErrorKind::Other, "Color location is out of bound!"))
+    // Write a pixel, or fail with OutOfBounds.
+    pub fn try_set(&mut self, x: i32, y: i32, color: &TgaColor) -> TgaResult<()> {
+        if !self.contains(x, y) {
+            return Err(TgaError::OutOfBounds { x, y })
+        }
+        // Safety: (x, y) is inside of the image
+        unsafe { self.set_unchecked(x, y, color) }
+        Ok(())
+    }
+
+    /// Write a pixel without bounds checking, for loops which have already clipped their coordinates.
+    ///
+    /// # Safety
+    /// (x, y) must be inside of the image: 0 <= x < width and 0 <= y < height.
+    #[inline]
+    pub unsafe fn set_unchecked(&mut self, x: i32, y: i32, color: &TgaColor) {
+        let (x, y) = (x as usize, y as usize);
+        match &mut self.pixels {
+            | TgaPixels::Grayscale(image) => *image.get_unchecked_mut(x, y) = Luma8([color.luma()]),
+            | TgaPixels::RGB(image)  => *image.get_unchecked_mut(x, y) = Rgb8(color.rgb()),
+            | TgaPixels::RGBA(image) => *image.get_unchecked_mut(x, y) = Rgba8(color.rgba()),
+        }
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> TgaResult<TgaColor> {
+        self.try_get(x, y).ok_or(TgaError::OutOfBounds { x, y })
+    }
+
+    // Read a pixel, None if it's outside of the image.
+    #[inline]
+    pub fn try_get(&self, x: i32, y: i32) -> Option<TgaColor> {
+        if self.contains(x, y) {
+            // Safety: (x, y) is inside of the image
+            Some(unsafe { self.get_unchecked(x, y) })
         } else {
-            let mut color = TgaColor::from_rgba(0, 0, 0, 0);
-            let location = (x + y * self.width) as usize * self.bytes_per_pixel;
-            for i in 0..self.bytes_per_pixel {
-                color[i] = self.data[location + i];
-            }
-            Ok(color)
+            None
+        }
+    }
+
+    /// Read a pixel without bounds checking.
+    ///
+    /// # Safety
+    /// (x, y) must be inside of the image: 0 <= x < width and 0 <= y < height.
+    #[inline]
+    pub unsafe fn get_unchecked(&self, x: i32, y: i32) -> TgaColor {
+        let (x, y) = (x as usize, y as usize);
+        match &self.pixels {
+            | TgaPixels::Grayscale(image) => TgaColor::from_greyscale(image.get_unchecked(x, y).0[0]),
+            | TgaPixels::RGB(image) => {
+                let [r, g, b] = image.get_unchecked(x, y).0;
+                TgaColor::from_rgb(r, g, b)
+            },
+            | TgaPixels::RGBA(image) => {
+                let [r, g, b, a] = image.get_unchecked(x, y).0;
+                TgaColor::from_rgba(r, g, b, a)
+            },
+        }
+    }
+
+    // Fill the part of `rect` inside the image, the color is converted like in `set`.
+    pub fn fill_rect(&mut self, rect: Rect, color: &TgaColor) {
+        match &mut self.pixels {
+            | TgaPixels::Grayscale(image) => image.fill_rect(rect, Luma8([color.luma()])),
+            | TgaPixels::RGB(image)  => image.fill_rect(rect, Rgb8(color.rgb())),
+            | TgaPixels::RGBA(image) => image.fill_rect(rect, Rgba8(color.rgba())),
+        }
+    }
+
+    // Copy the `source_rect` part of `source` with its top-left corner at (x, y), converting the pixels to the format of this image.
+    // The parts falling outside of either image are skipped.
+    pub fn blit(&mut self, x: i32, y: i32, source: &TgaImage, source_rect: Rect) {
+        match &source.pixels {
+            | TgaPixels::Grayscale(source) => self.blit_image(x, y, source, source_rect),
+            | TgaPixels::RGB(source)  => self.blit_image(x, y, source, source_rect),
+            | TgaPixels::RGBA(source) => self.blit_image(x, y, source, source_rect),
+        }
+    }
+
+    // Copy the whole source with its top-left corner at (x, y).
+    pub fn copy_from(&mut self, x: i32, y: i32, source: &TgaImage) {
+        self.blit(x, y, source, Rect::of_size(source.width, source.height))
+    }
+
+    fn blit_image<P: Pixel>(&mut self, x: i32, y: i32, source: &Image<P>, source_rect: Rect) {
+        match &mut self.pixels {
+            | TgaPixels::Grayscale(image) => image.blit(x, y, source, source_rect),
+            | TgaPixels::RGB(image)  => image.blit(x, y, source, source_rect),
+            | TgaPixels::RGBA(image) => image.blit(x, y, source, source_rect),
+        }
+    }
+}
+
+impl From<Image<Luma8>> for TgaImage {
+    fn from(image: Image<Luma8>) -> TgaImage {
+        TgaImage::from_pixels(TgaPixels::Grayscale(image))
+    }
+}
+
+impl From<Image<Rgb8>> for TgaImage {
+    fn from(image: Image<Rgb8>) -> TgaImage {
+        TgaImage::from_pixels(TgaPixels::RGB(image))
+    }
+}
+
+impl From<Image<Rgba8>> for TgaImage {
+    fn from(image: Image<Rgba8>) -> TgaImage {
+        TgaImage::from_pixels(TgaPixels::RGBA(image))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The header of an uncompressed true color image with its origin at the top-left.
+    fn header(width: i16, height: i16, bits_per_pixel: u8) -> TgaHeader {
+        TgaHeader {
+            id_length: 0, colormaptype: 0, datatypecode: 2, colormap_origin: 0, colormap_length: 0, colormap_depth: 0,
+            x_origin: 0, y_origin: 0, width, height, bits_per_pixel, image_descriptor: 0x20,
+        }
+    }
+
+    fn file(header: TgaHeader, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        header.write_header(&mut bytes).unwrap();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn keeps_alpha_without_alpha_bits() {
+        let image = TgaImage::from_bytes(&file(header(1, 1, 32), &[1, 2, 3, 40])).unwrap();
+        assert_eq!(image.format(), TgaFormat::RGBA);
+        assert_eq!(image.get(0, 0).unwrap().rgba(), [3, 2, 1, 40]);
+    }
+
+    #[test]
+    fn ignores_undefined_alpha() {
+        let mut image = TgaImage::new(1, 1, TgaFormat::RGBA);
+        image.set(0, 0, &TgaColor::from_rgba(3, 2, 1, 40));
+        image.metadata.extension = Some(Box::default());
+
+        // written with 8 alpha bits, the byte is alpha whatever the attributes type
+        let mut bytes = image.to_bytes(false, TgaEncoding::TrueColor).unwrap();
+        assert_eq!(TgaImage::from_bytes(&bytes).unwrap().get(0, 0).unwrap().a(), 40);
+
+        // without alpha bits, attributes type 0 says there is no alpha
+        bytes[17] &= 0xf0;
+        assert_eq!(TgaImage::from_bytes(&bytes).unwrap().get(0, 0).unwrap().a(), 255);
+    }
+
+    #[test]
+    fn rejects_dimensions_too_large_for_the_header() {
+        let image = TgaImage::new(32768, 1, TgaFormat::Grayscale);
+        assert!(matches!(image.to_bytes(false, TgaEncoding::TrueColor), Err(TgaError::BadDimensions { width: 32768, height: 1 })));
+        let image = TgaImage::new(1, 32767, TgaFormat::Grayscale);
+        assert!(image.to_bytes(false, TgaEncoding::TrueColor).is_ok());
+    }
+
+    #[test]
+    fn rejects_rle_packets_overrunning_the_image() {
+        let mut rle = header(2, 1, 24);
+        rle.datatypecode = 10;
+        // a run of 3 pixels in an image of 2
+        let result = TgaImage::from_bytes(&file(rle, &[0x82, 1, 2, 3]));
+        assert!(matches!(result, Err(TgaError::RleOverflow { pixel_count: 2 })));
+
+        let mut rle = header(2, 1, 24);
+        rle.datatypecode = 10;
+        // a raw packet of 1 pixel, then a raw packet of 2
+        let result = TgaImage::from_bytes(&file(rle, &[0x00, 1, 2, 3, 0x01, 4, 5, 6, 7, 8, 9]));
+        assert!(matches!(result, Err(TgaError::RleOverflow { pixel_count: 2 })));
+    }
+
+    #[test]
+    fn rejects_bad_dimensions() {
+        for &(width, height) in &[(0, 1), (1, 0), (-1, 1), (1, -2)] {
+            let result = TgaImage::from_bytes(&file(header(width, height, 24), &[0; 3]));
+            assert!(matches!(result, Err(TgaError::BadDimensions { .. })), "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        assert!(matches!(TgaImage::from_bytes(&[0; 10]), Err(TgaError::TruncatedHeader)));
+        assert!(matches!(TgaImage::from_bytes(&file(header(2, 2, 24), &[0; 11])), Err(TgaError::TruncatedData)));
+
+        // a few bytes of run-length data can't describe the whole image, nothing is allocated for it
+        let mut rle = header(i16::MAX, i16::MAX, 32);
+        rle.datatypecode = 10;
+        assert!(matches!(TgaImage::from_bytes(&file(rle, &[0xff, 1, 2, 3, 4])), Err(TgaError::TruncatedData)));
+
+        // a raw packet of 4 pixels followed by only 2
+        let mut rle = header(4, 1, 24);
+        rle.datatypecode = 10;
+        assert!(TgaImage::from_bytes(&file(rle, &[0x03, 1, 2, 3, 4, 5, 6])).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_color_map_index() {
+        let mut mapped = header(2, 1, 8);
+        mapped.colormaptype = 1;
+        mapped.datatypecode = 1;
+        mapped.colormap_length = 2;
+        mapped.colormap_depth = 24;
+        let palette = [0, 0, 255, 0, 255, 0];
+
+        let image = TgaImage::from_bytes(&file(mapped, &[&palette[..], &[1, 0]].concat())).unwrap();
+        assert_eq!(image.get(0, 0).unwrap().rgb(), [0, 255, 0]);
+        assert_eq!(image.get(1, 0).unwrap().rgb(), [255, 0, 0]);
+
+        let mut mapped = header(2, 1, 8);
+        mapped.colormaptype = 1;
+        mapped.datatypecode = 1;
+        mapped.colormap_length = 2;
+        mapped.colormap_depth = 24;
+        let result = TgaImage::from_bytes(&file(mapped, &[&palette[..], &[1, 2]].concat()));
+        assert!(matches!(result, Err(TgaError::InvalidColorMap(_))));
+    }
+
+    #[test]
+    fn round_trips_rle() {
+        let mut image = TgaImage::new(300, 3, TgaFormat::RGB);
+        for x in 0..300 {
+            image.set(x, 1, &TgaColor::from_rgb((x / 7) as u8, 0, x as u8));
+        }
+        let bytes = image.to_bytes(true, TgaEncoding::TrueColor).unwrap();
+        assert_eq!(TgaImage::from_bytes(&bytes).unwrap().raw_data(), image.raw_data());
+    }
+
+    #[test]
+    fn converts_colors_to_gray_with_one_luma() {
+        let colors = [TgaColor::from_rgb(255, 0, 0), TgaColor::from_rgb(0, 255, 0), TgaColor::from_rgb(0, 0, 255), TgaColor::from_rgb(12, 200, 99)];
+        for color in &colors {
+            let mut gray = TgaImage::new(1, 1, TgaFormat::Grayscale);
+            gray.set(0, 0, color);
+            let converted = Image::from_vec(1, 1, vec![Rgb8([color.r(), color.g(), color.b()])]).unwrap().convert::<Luma8>();
+            assert_eq!(gray.raw_data(), &[color.luma()]);
+            assert_eq!(converted[(0, 0)], Luma8([color.luma()]));
+        }
+        assert_eq!(colors[0].luma(), 54);
+    }
+
+    #[test]
+    fn clamps_negative_dimensions() {
+        let image = TgaImage::new(-3, 4, TgaFormat::RGB);
+        assert_eq!((image.width, image.height), (0, 4));
+        assert!(image.raw_data().is_empty());
+        assert_eq!(image.try_get(0, 0), None);
+    }
+
+    #[test]
+    fn makes_postage_stamps() {
+        let mut image = TgaImage::new(300, 150, TgaFormat::RGB);
+        image.set(299, 149, &TgaColor::from_rgb(1, 2, 3));
+
+        let stamp = image.make_postage_stamp(64);
+        assert_eq!((stamp.width, stamp.height), (64, 32));
+        let stamp = image.make_postage_stamp(1000);
+        assert_eq!((stamp.width, stamp.height), (255, 127));
+        let stamp = image.make_postage_stamp(-5);
+        assert_eq!((stamp.width, stamp.height), (1, 1));
+
+        let small = TgaImage::new(3, 2, TgaFormat::Grayscale).make_postage_stamp(64);
+        assert_eq!((small.width, small.height, small.format()), (3, 2, TgaFormat::Grayscale));
+
+        for &(width, height) in &[(0, 0), (0, 10), (10, 0)] {
+            let stamp = TgaImage::new(width, height, TgaFormat::RGBA).make_postage_stamp(64);
+            assert_eq!((stamp.width, stamp.height), (0, 0));
         }
     }
 }
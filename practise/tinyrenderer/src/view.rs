@@ -0,0 +1,241 @@
+//!
+//! Borrowed views of a rectangle of a TgaImage. A view has its own origin at the top-left corner of the rectangle,
+//! and everything done through it is clipped to the rectangle, e.g. to render several viewports into one sheet.
+//!
+
+use crate::image::Rect;
+use crate::tga::{TgaImage, TgaColor, TgaError, TgaResult};
+use crate::rasterization::RenderTarget;
+use crate::color::Color;
+
+
+impl TgaImage {
+
+    // A view of the part of `rect` inside the image.
+    pub fn view(&self, rect: Rect) -> TgaView<'_> {
+        TgaView { rect: clip(rect, self.bounds()), image: self }
+    }
+
+    pub fn view_mut(&mut self, rect: Rect) -> TgaViewMut<'_> {
+        TgaViewMut { rect: clip(rect, self.bounds()), image: self }
+    }
+}
+
+// Clip a rectangle to the bounds, the empty rectangle at the origin of the bounds if they don't overlap.
+fn clip(rect: Rect, bounds: Rect) -> Rect {
+    rect.intersect(&bounds).unwrap_or(Rect::new(bounds.x, bounds.y, 0, 0))
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct TgaView<'a> {
+    image: &'a TgaImage,
+    rect: Rect, // in the coordinates of the image, inside of it
+}
+
+impl<'a> TgaView<'a> {
+
+    pub fn width(&self) -> i32 { self.rect.width }
+    pub fn height(&self) -> i32 { self.rect.height }
+
+    // The viewed rectangle in the coordinates of the image.
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn image(&self) -> &'a TgaImage {
+        self.image
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> TgaResult<TgaColor> {
+        if !Rect::of_size(self.rect.width, self.rect.height).contains(x, y) {
+            return Err(TgaError::OutOfBounds { x, y })
+        }
+        self.image.get(x + self.rect.x, y + self.rect.y)
+    }
+
+    // A view of `rect`, given in the coordinates of this view and clipped to it.
+    pub fn view(&self, rect: Rect) -> TgaView<'a> {
+        TgaView { image: self.image, rect: clip(rect.translated(self.rect.x, self.rect.y), self.rect) }
+    }
+
+    // Copy the viewed pixels to a new image.
+    pub fn to_image(&self) -> TgaImage {
+        self.image.crop(self.rect)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct TgaViewMut<'a> {
+    image: &'a mut TgaImage,
+    rect: Rect,
+}
+
+impl<'a> TgaViewMut<'a> {
+
+    pub fn width(&self) -> i32 { self.rect.width }
+    pub fn height(&self) -> i32 { self.rect.height }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn as_view(&self) -> TgaView<'_> {
+        TgaView { image: self.image, rect: self.rect }
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> TgaResult<TgaColor> {
+        self.as_view().get(x, y)
+    }
+
+    // Pixels outside of the view are skipped.
+    pub fn set(&mut self, x: i32, y: i32, color: &TgaColor) {
+        if Rect::of_size(self.rect.width, self.rect.height).contains(x, y) {
+            self.image.set(x + self.rect.x, y + self.rect.y, color);
+        }
+    }
+
+    // A mutable view of `rect`, given in the coordinates of this view and clipped to it.
+    pub fn view_mut(&mut self, rect: Rect) -> TgaViewMut<'_> {
+        TgaViewMut { rect: clip(rect.translated(self.rect.x, self.rect.y), self.rect), image: self.image }
+    }
+
+    pub fn fill(&mut self, color: &TgaColor) {
+        self.image.fill_rect(self.rect, color);
+    }
+
+    // Fill `rect`, given in the coordinates of this view and clipped to it.
+    pub fn fill_rect(&mut self, rect: Rect, color: &TgaColor) {
+        if let Some(rect) = rect.translated(self.rect.x, self.rect.y).intersect(&self.rect) {
+            self.image.fill_rect(rect, color);
+        }
+    }
+
+    // Copy the `source_rect` part of `source` with its top-left corner at (x, y) of this view, see `TgaImage::blit`.
+    pub fn blit(&mut self, x: i32, y: i32, source: &TgaImage, source_rect: Rect) {
+        // clip the source to the part landing inside the view, the image then clips nothing more
+        let (dx, dy) = (x + self.rect.x - source_rect.x, y + self.rect.y - source_rect.y);
+        let area = source_rect.intersect(&source.bounds()).and_then(|rect| rect.translated(dx, dy).intersect(&self.rect));
+        if let Some(area) = area {
+            self.image.blit(area.x, area.y, source, area.translated(-dx, -dy));
+        }
+    }
+
+    // Copy a whole view with its top-left corner at (x, y) of this view.
+    pub fn copy_from(&mut self, x: i32, y: i32, source: &TgaView) {
+        self.blit(x, y, source.image, source.rect)
+    }
+}
+
+impl RenderTarget<TgaColor> for TgaViewMut<'_> {
+    fn width(&self) -> i32 { self.rect.width }
+    fn height(&self) -> i32 { self.rect.height }
+    fn set(&mut self, x: i32, y: i32, color: &TgaColor) { TgaViewMut::set(self, x, y, color) }
+    // the view is inside of the image, so are its pixels
+    unsafe fn set_unchecked(&mut self, x: i32, y: i32, color: &TgaColor) { self.image.set_unchecked(x + self.rect.x, y + self.rect.y, color) }
+}
+
+impl RenderTarget<Color> for TgaViewMut<'_> {
+    fn width(&self) -> i32 { self.rect.width }
+    fn height(&self) -> i32 { self.rect.height }
+    fn set(&mut self, x: i32, y: i32, color: &Color) { TgaViewMut::set(self, x, y, &color.to_srgb8()) }
+    unsafe fn set_unchecked(&mut self, x: i32, y: i32, color: &Color) { self.image.set_unchecked(x + self.rect.x, y + self.rect.y, &color.to_srgb8()) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tga::TgaFormat;
+
+    fn numbered(width: i32, height: i32) -> TgaImage {
+        let mut image = TgaImage::new(width, height, TgaFormat::Grayscale);
+        for y in 0..height {
+            for x in 0..width {
+                image.set(x, y, &TgaColor::from_greyscale((x + y * width) as u8));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn clips_views_to_the_image() {
+        let mut image = numbered(6, 4);
+        assert_eq!(image.view(Rect::new(-2, 1, 4, 10)).rect(), Rect::new(0, 1, 2, 3));
+        assert_eq!(image.view(Rect::new(4, -3, 10, 5)).rect(), Rect::new(4, 0, 2, 2));
+        assert_eq!(image.view(Rect::new(7, 0, 2, 2)).rect(), Rect::new(0, 0, 0, 0));
+        assert_eq!(image.view_mut(Rect::new(-1, -1, 3, 3)).rect(), Rect::new(0, 0, 2, 2));
+
+        let view = image.view(Rect::new(4, 2, 5, 5));
+        assert_eq!((view.width(), view.height()), (2, 2));
+        assert_eq!(view.get(1, 1).unwrap().luma(), 23);
+        assert!(matches!(view.get(2, 0), Err(TgaError::OutOfBounds { x: 2, y: 0 })));
+        assert!(matches!(view.get(-1, 0), Err(TgaError::OutOfBounds { .. })));
+        assert_eq!(view.to_image().raw_data(), &[16, 17, 22, 23]);
+    }
+
+    #[test]
+    fn nested_views_compose_their_offsets() {
+        let mut image = numbered(6, 4);
+        let outer = image.view(Rect::new(1, 1, 4, 3));
+        let inner = outer.view(Rect::new(1, 1, 10, 10));
+        assert_eq!(inner.rect(), Rect::new(2, 2, 3, 2));
+        assert_eq!(inner.get(0, 0).unwrap().luma(), 14);
+        assert_eq!(outer.view(Rect::new(-5, -5, 6, 6)).rect(), Rect::new(1, 1, 1, 1));
+
+        let mut outer = image.view_mut(Rect::new(1, 1, 4, 3));
+        let mut inner = outer.view_mut(Rect::new(2, 1, 5, 5));
+        assert_eq!(inner.rect(), Rect::new(3, 2, 2, 2));
+        inner.set(1, 1, &TgaColor::from_greyscale(200));
+        inner.fill_rect(Rect::new(-1, -1, 2, 2), &TgaColor::from_greyscale(100));
+        assert_eq!(image.try_get(4, 3).map(|c| c.luma()), Some(200));
+        assert_eq!(image.try_get(3, 2).map(|c| c.luma()), Some(100));
+        assert_eq!(image.try_get(2, 1).map(|c| c.luma()), Some(8));
+    }
+
+    #[test]
+    fn set_ignores_pixels_outside_of_the_view() {
+        let mut image = numbered(6, 4);
+        let original = image.clone();
+        let mut view = image.view_mut(Rect::new(2, 1, 2, 2));
+        for &(x, y) in &[(-1, 0), (0, -1), (2, 0), (0, 2), (3, 3), (i32::MIN, i32::MAX)] {
+            view.set(x, y, &TgaColor::from_greyscale(255));
+        }
+        assert_eq!(image.raw_data(), original.raw_data());
+
+        let mut view = image.view_mut(Rect::new(2, 1, 2, 2));
+        view.fill(&TgaColor::from_greyscale(255));
+        let changed: Vec<(i32, i32)> = (0..4).flat_map(|y| (0..6).map(move |x| (x, y)))
+            .filter(|&(x, y)| image.try_get(x, y) != original.try_get(x, y))
+            .collect();
+        assert_eq!(changed, [(2, 1), (3, 1), (2, 2), (3, 2)]);
+    }
+
+    #[test]
+    fn blit_only_touches_the_view() {
+        let source = numbered(4, 4);
+        let mut image = TgaImage::new(6, 5, TgaFormat::Grayscale);
+        let mut view = image.view_mut(Rect::new(1, 1, 3, 3));
+        view.blit(-2, -1, &source, source.bounds());
+
+        for y in 0..5 {
+            for x in 0..6 {
+                let v = image.try_get(x, y).unwrap().luma();
+                if (1..4).contains(&x) && (1..4).contains(&y) {
+                    // view (i, j) shows source (i + 2, j + 1)
+                    let (i, j) = (x - 1 + 2, y - 1 + 1);
+                    let expected = if i < 4 && j < 4 { (i + j * 4) as u8 } else { 0 };
+                    assert_eq!(v, expected, "({}, {})", x, y);
+                } else {
+                    assert_eq!(v, 0, "({}, {}) is outside of the view", x, y);
+                }
+            }
+        }
+
+        let mut view = image.view_mut(Rect::new(1, 1, 3, 3));
+        view.blit(-10, 0, &source, source.bounds());
+        view.blit(0, 0, &source, Rect::new(-10, -10, 5, 5));
+        assert_eq!(image.try_get(1, 1).map(|c| c.luma()), Some(6));
+    }
+}
@@ -0,0 +1,538 @@
+//!
+//! A small zlib(RFC 1950) / deflate(RFC 1951) implementation.
+//! Compression uses LZ77 with hash chains and the fixed Huffman codes, decompression supports every block type.
+//!
+
+
+#[derive(Debug)]
+pub enum ZlibError {
+    InvalidHeader,
+    InvalidBlock(&'static str),
+    ChecksumMismatch,
+    TruncatedData,
+    OutputLimitExceeded,
+}
+
+impl std::fmt::Display for ZlibError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            | ZlibError::InvalidHeader => write!(f, "invalid zlib header"),
+            | ZlibError::InvalidBlock(reason) => write!(f, "invalid deflate block: {}", reason),
+            | ZlibError::ChecksumMismatch => write!(f, "adler32 checksum mismatch"),
+            | ZlibError::TruncatedData => write!(f, "compressed data is truncated"),
+            | ZlibError::OutputLimitExceeded => write!(f, "decompressed data is larger than expected"),
+        }
+    }
+}
+
+impl std::error::Error for ZlibError {}
+
+pub type ZlibResult<T> = Result<T, ZlibError>;
+
+
+// Base values and extra bits of length codes 257..285 and distance codes 0..29.
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// The order in which code length code lengths are stored in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN_LENGTH: usize = 128;
+
+
+pub fn adler32(data: &[u8]) -> u32 {
+
+    const MOD_ADLER: u32 = 65521;
+    // 5552 is the largest n such that the sums don't overflow before taking the modulo
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+// Wrap the deflate stream of `data` in a zlib container.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+
+    // CMF: deflate with 32K window, FLG: default compression level and the check bits
+    let mut result = vec![0x78, 0x9c];
+    result.extend_from_slice(&deflate(data));
+    result.extend_from_slice(&adler32(data).to_be_bytes());
+    result
+}
+
+pub fn decompress(data: &[u8]) -> ZlibResult<Vec<u8>> {
+    decompress_with_limit(data, usize::MAX)
+}
+
+// Decompress at most `limit` bytes, protecting against data that expands far more than expected.
+pub fn decompress_with_limit(data: &[u8], limit: usize) -> ZlibResult<Vec<u8>> {
+
+    if data.len() < 2 {
+        return Err(ZlibError::TruncatedData)
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 || !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) || flg & 0x20 != 0 {
+        return Err(ZlibError::InvalidHeader)
+    }
+
+    let mut reader = BitReader::new(&data[2..]);
+    let result = inflate(&mut reader, limit)?;
+
+    let checksum = reader.aligned_bytes(4)?;
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&result) {
+        return Err(ZlibError::ChecksumMismatch)
+    }
+    Ok(result)
+}
+
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+
+    fn new() -> BitWriter {
+        BitWriter { bytes: Vec::new(), buffer: 0, bit_count: 0 }
+    }
+
+    // Deflate packs values from the least significant bit.
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    // Huffman codes are packed starting from their most significant bit.
+    fn write_code(&mut self, code: u32, length: u32) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.write_bits(reversed, length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+// The fixed literal/length code of a symbol, return (code, length).
+fn fixed_literal_code(symbol: u16) -> (u32, u32) {
+    let symbol = symbol as u32;
+    match symbol {
+        | 0..=143   => (0x30 + symbol, 8),
+        | 144..=255 => (0x190 + symbol - 144, 9),
+        | 256..=279 => (symbol - 256, 7),
+        | _         => (0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+
+    let length_index = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+    let (code, code_length) = fixed_literal_code(257 + length_index as u16);
+    writer.write_code(code, code_length);
+    writer.write_bits((length - LENGTH_BASE[length_index] as usize) as u32, LENGTH_EXTRA[length_index] as u32);
+
+    let distance_index = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+    writer.write_code(distance_index as u32, 5);
+    writer.write_bits((distance - DISTANCE_BASE[distance_index] as usize) as u32, DISTANCE_EXTRA[distance_index] as u32);
+}
+
+// Compress to a raw deflate stream, falling back to stored blocks for incompressible data.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+
+    let compressed = deflate_fixed(data);
+    if compressed.len() <= data.len() + data.len() / 65535 * 5 + 5 {
+        return compressed
+    }
+
+    let mut result = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 5);
+    let mut chunks = data.chunks(65535).peekable();
+    if chunks.peek().is_none() {
+        result.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let is_final = chunks.peek().is_none();
+        let length = chunk.len() as u16;
+        result.push(if is_final { 1 } else { 0 });
+        result.extend_from_slice(&length.to_le_bytes());
+        result.extend_from_slice(&(!length).to_le_bytes());
+        result.extend_from_slice(chunk);
+    }
+    result
+}
+
+fn deflate_fixed(data: &[u8]) -> Vec<u8> {
+
+    const HASH_BITS: usize = 15;
+    const NIL: usize = usize::MAX;
+    let hash = |i: usize| -> usize {
+        let v = (data[i] as usize) << 16 | (data[i + 1] as usize) << 8 | data[i + 2] as usize;
+        (v.wrapping_mul(2654435761) >> 8) & ((1 << HASH_BITS) - 1)
+    };
+
+    // head[h] is the latest position with hash h, prev[i % WINDOW_SIZE] the previous one
+    let mut head = vec![NIL; 1 << HASH_BITS];
+    let mut prev = vec![NIL; WINDOW_SIZE];
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i % WINDOW_SIZE] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut writer = BitWriter::new();
+    // a single final block with fixed codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+
+        if i + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != NIL && i - candidate < WINDOW_SIZE && chain < MAX_CHAIN_LENGTH {
+                let length = data[candidate..].iter().zip(&data[i..(i + max_length)]).take_while(|(a, b)| a == b).count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = i - candidate;
+                    if length == max_length { break }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                // the slot may already be reused by a newer position
+                if next == NIL || next >= candidate { break }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_distance);
+            for j in i..(i + best_length) {
+                insert(j, &mut head, &mut prev);
+            }
+            i += best_length;
+        } else {
+            let (code, length) = fixed_literal_code(data[i] as u16);
+            writer.write_code(code, length);
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+
+    let (code, length) = fixed_literal_code(256);
+    writer.write_code(code, length);
+    writer.finish()
+}
+
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+
+    fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader { bytes, position: 0, buffer: 0, bit_count: 0 }
+    }
+
+    fn bits(&mut self, count: u32) -> ZlibResult<u32> {
+        while self.bit_count < count {
+            let byte = *self.bytes.get(self.position).ok_or(ZlibError::TruncatedData)?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.buffer & ((1_u64 << count) - 1) as u32;
+        self.buffer = if count == 32 { 0 } else { self.buffer >> count };
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    // Drop the remaining bits of the current byte and take the next `count` bytes.
+    fn aligned_bytes(&mut self, count: usize) -> ZlibResult<&'a [u8]> {
+        // whole bytes still in the buffer were read ahead and must be given back
+        self.position -= (self.bit_count / 8) as usize;
+        self.buffer = 0;
+        self.bit_count = 0;
+
+        let bytes = self.bytes.get(self.position..(self.position + count)).ok_or(ZlibError::TruncatedData)?;
+        self.position += count;
+        Ok(bytes)
+    }
+}
+
+// A canonical Huffman code, decoded one bit at a time from the code length counts(as in zlib's puff).
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+
+    fn new(lengths: &[u8]) -> ZlibResult<Huffman> {
+
+        let mut counts = [0_u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+
+        // reject over-subscribed codes, incomplete ones are only a problem if an unused code is met
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(ZlibError::InvalidBlock("over-subscribed Huffman code"))
+            }
+        }
+
+        let mut offsets = [0_u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> ZlibResult<u16> {
+
+        let (mut code, mut first, mut index) = (0_i32, 0_i32, 0_i32);
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize])
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(ZlibError::InvalidBlock("invalid Huffman code"))
+    }
+}
+
+fn fixed_huffman() -> (Huffman, Huffman) {
+
+    let mut lengths = [0_u8; 288];
+    lengths[0..144].iter_mut().for_each(|length| *length = 8);
+    lengths[144..256].iter_mut().for_each(|length| *length = 9);
+    lengths[256..280].iter_mut().for_each(|length| *length = 7);
+    lengths[280..288].iter_mut().for_each(|length| *length = 8);
+
+    let literal = Huffman::new(&lengths).expect("Fixed literal code is valid");
+    let distance = Huffman::new(&[5; 30]).expect("Fixed distance code is valid");
+    (literal, distance)
+}
+
+fn dynamic_huffman(reader: &mut BitReader) -> ZlibResult<(Huffman, Huffman)> {
+
+    let literal_count  = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(ZlibError::InvalidBlock("too many length or distance symbols"))
+    }
+
+    let mut code_length_lengths = [0_u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_length_lengths)?;
+
+    // literal/length and distance code lengths are run-length encoded as a single sequence
+    let mut lengths = vec![0_u8; literal_count + distance_count];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            | 0..=15 => (symbol as u8, 1),
+            | 16 => {
+                let previous = *lengths.get(i.wrapping_sub(1)).ok_or(ZlibError::InvalidBlock("repeat with no previous length"))?;
+                (previous, 3 + reader.bits(2)? as usize)
+            },
+            | 17 => (0, 3 + reader.bits(3)? as usize),
+            | _  => (0, 11 + reader.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(ZlibError::InvalidBlock("too many code lengths"))
+        }
+        lengths[i..(i + repeat)].iter_mut().for_each(|length| *length = value);
+        i += repeat;
+    }
+
+    if lengths[256] == 0 {
+        return Err(ZlibError::InvalidBlock("missing end of block code"))
+    }
+    let literal = Huffman::new(&lengths[..literal_count])?;
+    let distance = Huffman::new(&lengths[literal_count..])?;
+    Ok((literal, distance))
+}
+
+fn inflate(reader: &mut BitReader, limit: usize) -> ZlibResult<Vec<u8>> {
+
+    let mut output = Vec::new();
+    loop {
+        let is_final = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            | 0 => {
+                let header = reader.aligned_bytes(4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(ZlibError::InvalidBlock("stored block length mismatch"))
+                }
+                if output.len() + length as usize > limit {
+                    return Err(ZlibError::OutputLimitExceeded)
+                }
+                output.extend_from_slice(reader.aligned_bytes(length as usize)?);
+            },
+            | 1 => {
+                let (literal, distance) = fixed_huffman();
+                inflate_block(reader, &mut output, &literal, &distance, limit)?;
+            },
+            | 2 => {
+                let (literal, distance) = dynamic_huffman(reader)?;
+                inflate_block(reader, &mut output, &literal, &distance, limit)?;
+            },
+            | _ => return Err(ZlibError::InvalidBlock("reserved block type")),
+        }
+
+        if is_final {
+            return Ok(output)
+        }
+    }
+}
+
+fn inflate_block(reader: &mut BitReader, output: &mut Vec<u8>, literal: &Huffman, distance: &Huffman, limit: usize) -> ZlibResult<()> {
+
+    loop {
+        let symbol = literal.decode(reader)? as usize;
+        if symbol < 256 {
+            if output.len() >= limit {
+                return Err(ZlibError::OutputLimitExceeded)
+            }
+            output.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(())
+        } else {
+            let length_index = symbol - 257;
+            if length_index >= LENGTH_BASE.len() {
+                return Err(ZlibError::InvalidBlock("invalid length symbol"))
+            }
+            let length = LENGTH_BASE[length_index] as usize + reader.bits(LENGTH_EXTRA[length_index] as u32)? as usize;
+
+            let distance_index = distance.decode(reader)? as usize;
+            if distance_index >= DISTANCE_BASE.len() {
+                return Err(ZlibError::InvalidBlock("invalid distance symbol"))
+            }
+            let distance = DISTANCE_BASE[distance_index] as usize + reader.bits(DISTANCE_EXTRA[distance_index] as u32)? as usize;
+
+            if distance > output.len() {
+                return Err(ZlibError::InvalidBlock("distance is too far back"))
+            }
+            if output.len() + length > limit {
+                return Err(ZlibError::OutputLimitExceeded)
+            }
+            // the source may overlap the bytes being copied, so copy one byte at a time
+            let start = output.len() - distance;
+            for i in 0..length {
+                output.push(output[start + i]);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic bytes which don't compress, so that stored blocks are used.
+    fn noise(length: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        (0..length).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        let repeated: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8 ^ (i / 4096) as u8).collect();
+        for data in &[vec![], b"a".to_vec(), b"abcabcabcabcabcabcabc".to_vec(), vec![0; 1000], repeated, noise(200_000)] {
+            let compressed = compress(data);
+            assert_eq!(&decompress(&compressed).unwrap(), data);
+        }
+        // incompressible data only grows by the block headers
+        let data = noise(200_000);
+        assert!(compress(&data).len() <= data.len() + 4 * 5 + 6);
+    }
+
+    #[test]
+    fn decompresses_dynamic_blocks() {
+        // zlib.compress(text, 9) of Python's zlib, which uses a dynamic Huffman block
+        const COMPRESSED: [u8; 92] = [
+            0x78, 0xda, 0x85, 0xcb, 0xcb, 0x09, 0x80, 0x30, 0x10, 0x05, 0xc0, 0xbb, 0x55, 0xbc, 0x02, 0x44,
+            0xc8, 0x3f, 0x5b, 0x8e, 0xc2, 0x8a, 0x87, 0x60, 0x40, 0x03, 0xb6, 0x6f, 0x01, 0x59, 0x78, 0xe7,
+            0x61, 0x44, 0x70, 0xf4, 0x31, 0x9a, 0xbe, 0xe8, 0x27, 0x0e, 0xd5, 0x07, 0xfd, 0xc6, 0xb8, 0x14,
+            0xdf, 0xde, 0xda, 0x0a, 0x99, 0x7c, 0x5b, 0xa4, 0x92, 0x53, 0x8d, 0x53, 0xc8, 0x29, 0xc6, 0xc9,
+            0xe4, 0x64, 0xe3, 0x24, 0x72, 0x92, 0x71, 0x22, 0x39, 0xd1, 0x38, 0x81, 0x9c, 0x60, 0x1c, 0x4f,
+            0x8e, 0x37, 0x8e, 0x23, 0xc7, 0xcd, 0xe7, 0x07, 0xfc, 0x33, 0x9a, 0x9f,
+        ];
+        let text: String = (91..100).rev().map(|i| format!("{} bottles of beer on the wall, {} bottles of beer.\n", i, i)).collect();
+        assert_eq!(decompress(&COMPRESSED).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn rejects_bad_streams() {
+        let data = b"hello hello hello hello".to_vec();
+        let compressed = compress(&data);
+
+        assert!(matches!(decompress(&[0x78]), Err(ZlibError::TruncatedData)));
+        assert!(matches!(decompress(&[0x78, 0x9d, 0x03, 0x00]), Err(ZlibError::InvalidHeader)));
+        for length in 2..compressed.len() {
+            assert!(decompress(&compressed[..length]).is_err(), "truncated to {} bytes", length);
+        }
+
+        let mut corrupted = compressed.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(decompress(&corrupted), Err(ZlibError::ChecksumMismatch)));
+
+        assert!(matches!(decompress_with_limit(&compressed, data.len() - 1), Err(ZlibError::OutputLimitExceeded)));
+        assert_eq!(decompress_with_limit(&compressed, data.len()).unwrap(), data);
+    }
+}